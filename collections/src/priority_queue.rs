@@ -53,6 +53,25 @@ impl<P: Ord, V> PriorityQueue<P, V> {
         self.data.iter()
     }
 
+    /// Returns `true` if any queued value matches `pred`. This scans the whole queue, O(n).
+    #[inline]
+    pub fn contains<F>(&self, pred: F) -> bool
+    where
+        F: FnMut(&V) -> bool,
+    {
+        self.find(pred).is_some()
+    }
+
+    /// Returns the first queued value, in heap order rather than priority order, that matches
+    /// `pred`. This scans the whole queue, O(n).
+    #[inline]
+    pub fn find<F>(&self, mut pred: F) -> Option<&V>
+    where
+        F: FnMut(&V) -> bool,
+    {
+        self.data.iter().map(|(_, v)| v).find(|v| pred(v))
+    }
+
     #[inline]
     pub fn drain(&mut self) -> Drain<'_, (P, V)> {
         self.data.drain(..)
@@ -247,3 +266,35 @@ fn test_priorityqueue_serde() {
         [(9, 99), (5, 55), (3, 33), (2, 22), (1, 11)]
     );
 }
+
+#[test]
+fn test_priorityqueue_tuple_priority() {
+    use alloc::vec::Vec;
+
+    let mut queue: PriorityQueue<(u8, u32), &str> = PriorityQueue::new();
+    queue.push((1, 2), "b");
+    queue.push((2, 0), "d");
+    queue.push((1, 9), "c");
+    queue.push((0, 99), "a");
+    queue.push((2, 1), "e");
+
+    let mut vals = Vec::new();
+    while let Some((_, v)) = queue.pop() {
+        vals.push(v);
+    }
+    assert_eq!(vals, ["e", "d", "c", "b", "a"]);
+}
+
+#[test]
+fn test_priorityqueue_contains_find() {
+    let mut queue: PriorityQueue<u8, (u64, &str)> = PriorityQueue::new();
+    queue.push(1, (10, "a"));
+    queue.push(3, (20, "b"));
+    queue.push(2, (30, "c"));
+
+    assert!(queue.contains(|(id, _)| *id == 20));
+    assert!(!queue.contains(|(id, _)| *id == 40));
+    assert_eq!(queue.find(|(id, _)| *id == 30), Some(&(30, "c")));
+    assert_eq!(queue.find(|(id, _)| *id == 40), None);
+    assert_eq!(queue.len(), 3);
+}
@@ -0,0 +1,66 @@
+//! Benchmarks for DequeBTreeMap, run with `cargo bench -p dequemap`.
+//!
+//! push_back of an existing key and remove find the key in the deque of indices through its
+//! tracked position, which is O(log n), the other cases are the baseline they are compared
+//! against.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use dequemap::DequeBTreeMap;
+
+const SIZES: [u64; 3] = [1_000, 10_000, 100_000];
+
+fn filled(n: u64) -> DequeBTreeMap<u64, u64> {
+    (0..n).map(|k| (k, k)).collect()
+}
+
+fn bench_btreemap(c: &mut Criterion) {
+    let mut group = c.benchmark_group("DequeBTreeMap");
+    for n in SIZES {
+        group.bench_with_input(BenchmarkId::new("insert_new", n), &n, |b, &n| {
+            b.iter(|| {
+                let mut map = DequeBTreeMap::new();
+                for k in 0..n {
+                    map.insert(k, k);
+                }
+                map
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("push_back_existing", n), &n, |b, &n| {
+            let mut map = filled(n);
+            let mut k = 0;
+            b.iter(|| {
+                //Cycles through the keys, so each one is somewhere in the middle of the deque
+                k = (k + 7919) % n;
+                map.push_back(black_box(k), k)
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("remove", n), &n, |b, &n| {
+            let map = filled(n);
+            b.iter_batched_ref(
+                || map.clone(),
+                |map| map.remove(black_box(&(n / 2))),
+                BatchSize::LargeInput,
+            )
+        });
+
+        group.bench_with_input(BenchmarkId::new("retain_few_removed", n), &n, |b, &n| {
+            let map = filled(n);
+            b.iter_batched_ref(
+                || map.clone(),
+                |map| map.retain(|k, _| k % 100 != 0),
+                BatchSize::LargeInput,
+            )
+        });
+
+        group.bench_with_input(BenchmarkId::new("iter", n), &n, |b, &n| {
+            let map = filled(n);
+            b.iter(|| map.iter().map(|(_, v)| *v).sum::<u64>())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_btreemap);
+criterion_main!(benches);
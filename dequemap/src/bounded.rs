@@ -0,0 +1,224 @@
+use core::borrow::Borrow;
+
+use crate::btreemap::{DequeBTreeMap, Iter};
+
+/// Which entry a `BoundedDequeBTreeMap` gives up when it is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eviction {
+    /// Evicts the entry inserted first, reading does not change the order.
+    Fifo,
+    /// Evicts the entry used least recently, `get` and `get_mut` move an entry to the back.
+    Lru,
+}
+
+/// A `DequeBTreeMap` holding at most `limit` entries, inserting beyond the limit evicts
+/// from the front. With `Eviction::Lru` it works as an LRU cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoundedDequeBTreeMap<K, V> {
+    map: DequeBTreeMap<K, V>,
+    limit: usize,
+    eviction: Eviction,
+}
+
+impl<K, V> BoundedDequeBTreeMap<K, V> {
+    /// Creates an empty map holding at most `limit` entries, a limit of 0 is treated as 1.
+    pub fn new(limit: usize, eviction: Eviction) -> Self {
+        Self {
+            map: DequeBTreeMap::new(),
+            limit: limit.max(1),
+            eviction,
+        }
+    }
+
+    #[inline]
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    #[inline]
+    pub fn eviction(&self) -> Eviction {
+        self.eviction
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    #[inline]
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.map.iter()
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    /// Returns the underlying map, reading through it never changes the order.
+    #[inline]
+    pub fn as_map(&self) -> &DequeBTreeMap<K, V> {
+        &self.map
+    }
+
+    #[inline]
+    pub fn into_map(self) -> DequeBTreeMap<K, V> {
+        self.map
+    }
+}
+
+impl<K, V> BoundedDequeBTreeMap<K, V>
+where
+    K: Clone + Ord,
+{
+    /// Inserts a key-value pair like `DequeBTreeMap::insert`, with `Eviction::Lru` an existing
+    /// key is also moved to the back.
+    ///
+    /// Returns the previous entry of `key` if there was one, otherwise the entry evicted from
+    /// the front to stay within the limit, if any.
+    #[inline]
+    pub fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        match self.eviction {
+            Eviction::Fifo => {
+                if self.map.contains_key(&key) {
+                    let old_val = self.map.insert(key.clone(), value);
+                    old_val.map(|v| (key, v))
+                } else {
+                    self.map.insert(key, value);
+                    self.evict()
+                }
+            }
+            Eviction::Lru => self.push_back(key, value),
+        }
+    }
+
+    /// Inserts a key-value pair at the back like `DequeBTreeMap::push_back`.
+    ///
+    /// Returns the previous entry of `key` if there was one, otherwise the entry evicted from
+    /// the front to stay within the limit, if any.
+    #[inline]
+    pub fn push_back(&mut self, key: K, value: V) -> Option<(K, V)> {
+        if let Some(old_val) = self.map.push_back(key.clone(), value) {
+            Some((key, old_val))
+        } else {
+            self.evict()
+        }
+    }
+
+    /// Returns the value of `k`, with `Eviction::Lru` the entry becomes the most recently used.
+    #[inline]
+    pub fn get<Q>(&mut self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match self.eviction {
+            Eviction::Fifo => self.map.get(k),
+            Eviction::Lru => self.map.touch_get(k),
+        }
+    }
+
+    /// Returns the value of `k`, with `Eviction::Lru` the entry becomes the most recently used.
+    #[inline]
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match self.eviction {
+            Eviction::Fifo => self.map.get_mut(k),
+            Eviction::Lru => self.map.touch_get_mut(k),
+        }
+    }
+
+    /// Returns the value of `k` without changing the order.
+    #[inline]
+    pub fn peek<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.map.get(k)
+    }
+
+    #[inline]
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.map.contains_key(k)
+    }
+
+    #[inline]
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        self.map.remove(k)
+    }
+
+    /// Removes the entry that would be evicted next.
+    #[inline]
+    pub fn pop_front(&mut self) -> Option<(K, V)> {
+        self.map.pop_front()
+    }
+
+    #[inline]
+    fn evict(&mut self) -> Option<(K, V)> {
+        if self.map.len() > self.limit {
+            self.map.pop_front()
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn test_bounded_fifo() {
+    use alloc::vec::Vec;
+    let to_vec = |map: &BoundedDequeBTreeMap<i32, i32>| {
+        map.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>()
+    };
+
+    let mut map = BoundedDequeBTreeMap::new(3, Eviction::Fifo);
+    assert_eq!(map.insert(1, 10), None);
+    assert_eq!(map.insert(2, 20), None);
+    assert_eq!(map.insert(3, 30), None);
+    assert_eq!(map.get(&1), Some(&10));
+    assert_eq!(map.insert(4, 40), Some((1, 10)));
+    assert_eq!(to_vec(&map), [(2, 20), (3, 30), (4, 40)]);
+
+    //Updating a key keeps its position and evicts nothing
+    assert_eq!(map.insert(2, 21), Some((2, 20)));
+    assert_eq!(to_vec(&map), [(2, 21), (3, 30), (4, 40)]);
+    assert_eq!(map.push_back(5, 50), Some((2, 21)));
+    assert_eq!(to_vec(&map), [(3, 30), (4, 40), (5, 50)]);
+    assert_eq!(map.len(), map.limit());
+}
+
+#[test]
+fn test_bounded_lru() {
+    use alloc::vec::Vec;
+    let to_vec = |map: &BoundedDequeBTreeMap<i32, i32>| {
+        map.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>()
+    };
+
+    let mut map = BoundedDequeBTreeMap::new(3, Eviction::Lru);
+    map.insert(1, 10);
+    map.insert(2, 20);
+    map.insert(3, 30);
+    assert_eq!(map.get(&1), Some(&10));
+    assert_eq!(to_vec(&map), [(2, 20), (3, 30), (1, 10)]);
+    assert_eq!(map.insert(4, 40), Some((2, 20)));
+    assert_eq!(to_vec(&map), [(3, 30), (1, 10), (4, 40)]);
+
+    //peek does not count as a use
+    assert_eq!(map.peek(&3), Some(&30));
+    *map.get_mut(&1).unwrap() += 1;
+    assert_eq!(map.insert(5, 50), Some((3, 30)));
+    assert_eq!(to_vec(&map), [(4, 40), (1, 11), (5, 50)]);
+    assert_eq!(map.get(&3), None);
+}
@@ -1,17 +1,20 @@
 use alloc::collections::vec_deque::IntoIter as DequeIntoIter;
-use alloc::collections::vec_deque::Iter as DequeIter;
 
 use alloc::collections::BTreeSet;
 use alloc::collections::VecDeque;
 use alloc::collections::{btree_map, BTreeMap};
+use alloc::string::String;
+use alloc::vec::{self, Vec};
 use core::borrow::Borrow;
+use core::cmp::Ordering;
 use core::fmt;
+use core::hash::{Hash, Hasher};
 use core::iter::DoubleEndedIterator;
 use core::iter::ExactSizeIterator;
 use core::iter::FromIterator;
 use core::iter::FusedIterator;
-use core::mem::replace;
-use core::ops::{Index, IndexMut};
+use core::mem::{replace, size_of, take};
+use core::ops::{Bound, Index, IndexMut, RangeBounds};
 
 ///Double-ended queue with Map feature.
 ///
@@ -44,11 +47,11 @@ use core::ops::{Index, IndexMut};
 ///the insertion order of the entries while also providing efficient access to the values by
 ///their keys.
 ///
-///One potential limitation of DequeBTreeMap is that it is not optimized for processing large
-///batches of data with many duplicates. This is because the insert() method has a
-///worst-case time complexity of O(n), where n is the number of entries in the map. This
-///means that if you try to insert a large number of duplicate keys into the map, the
-///performance may degrade significantly.
+///Removing a key from the middle of the deque leaves a tombstone in its slot instead of
+///shifting the keys behind it, and the slot of every key is tracked in a second BTreeMap. So
+///remove(), and push_back() or push_front() of a key that is already present, take amortized
+///O(log n) time. The tombstones are compacted away once they outnumber the entries, until
+///then looking up an entry by its position in the deque (e.g. map[0]) scans the deque.
 ///
 ///Additionally, DequeBTreeMap uses a BTreeMap internally, which means that the keys must
 ///implement the Ord trait. This means that the keys must have a total order and must be
@@ -58,9 +61,6 @@ use core::ops::{Index, IndexMut};
 ///Overall, while DequeBTreeMap is a useful data structure in many cases, it is important to
 ///consider its performance and limitations when deciding whether to use it in your own code.
 ///
-/// When the element is present, the maximum time complexity is O(n). So it is not suitable for
-/// processing large batches of data with too many duplicates.
-///
 /// Here are some examples of using DequeBTreeMap in Rust code:
 ///
 ///```
@@ -91,21 +91,21 @@ use core::ops::{Index, IndexMut};
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct DequeBTreeMap<K, V> {
     entries: BTreeMap<K, V>,
-    indices: VecDeque<K>,
+    indices: Indices<K>,
 }
 
 impl<K, V> DequeBTreeMap<K, V> {
     pub fn new() -> Self {
         Self {
             entries: BTreeMap::new(),
-            indices: VecDeque::new(),
+            indices: Indices::new(),
         }
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
             entries: BTreeMap::default(),
-            indices: VecDeque::with_capacity(capacity),
+            indices: Indices::with_capacity(capacity),
         }
     }
 }
@@ -114,7 +114,7 @@ impl<K, V> Default for DequeBTreeMap<K, V> {
     fn default() -> Self {
         Self {
             entries: BTreeMap::default(),
-            indices: VecDeque::default(),
+            indices: Indices::new(),
         }
     }
 }
@@ -137,15 +137,30 @@ where
         } else {
             self.entries.insert(key.clone(), value);
             self.indices.push_back(key);
+            self.check_invariants();
             None
         }
     }
 
+    /// Inserts a key-value pair at the back of the deque if the key is not in the map yet, and
+    /// returns a mutable reference to the value.
+    ///
+    /// If the key is already in the map, nothing is changed and the error holds the occupied
+    /// entry along with the value that was not inserted.
+    #[inline]
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<&mut V, OccupiedError<'_, K, V>> {
+        match self.entry(key) {
+            Entry::Occupied(entry) => Err(OccupiedError { entry, value }),
+            Entry::Vacant(entry) => Ok(entry.insert(value)),
+        }
+    }
+
     #[inline]
     pub fn push_back(&mut self, key: K, value: V) -> Option<V> {
         let old_val = self.remove_entry(&key);
         self.entries.insert(key.clone(), value);
         self.indices.push_back(key);
+        self.check_invariants();
         old_val
     }
 
@@ -154,27 +169,260 @@ where
         let old_val = self.remove_entry(&key);
         self.entries.insert(key.clone(), value);
         self.indices.push_front(key);
+        self.check_invariants();
         old_val
     }
 
+    /// Inserts a key-value pair right in front of `pivot` in the deque.
+    ///
+    /// If `key` is already in the map elsewhere, it is moved from its old position and the old
+    /// value is returned, as with `push_back`. If `key` is `pivot` itself, only the value is
+    /// replaced. Returns the pair back as an error if `pivot` is not in the map.
+    #[inline]
+    pub fn insert_before<Q>(&mut self, pivot: &Q, key: K, value: V) -> Result<Option<V>, (K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.insert_beside(pivot, false, key, value)
+    }
+
+    /// Inserts a key-value pair right behind `pivot` in the deque, like `insert_before`
+    /// otherwise.
+    #[inline]
+    pub fn insert_after<Q>(&mut self, pivot: &Q, key: K, value: V) -> Result<Option<V>, (K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.insert_beside(pivot, true, key, value)
+    }
+
+    #[inline]
+    fn insert_beside<Q>(
+        &mut self,
+        pivot: &Q,
+        after: bool,
+        key: K,
+        value: V,
+    ) -> Result<Option<V>, (K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        if !self.entries.contains_key(pivot) {
+            return Err((key, value));
+        }
+        if key.borrow() == pivot {
+            return Ok(self.entries.insert(key, value));
+        }
+        let old_val = self.remove_entry(&key);
+        let slot = self.indices.slot(pivot).expect("the pivot is in the deque") + after as usize;
+        self.entries.insert(key.clone(), value);
+        self.indices.insert_at_slot(slot, key);
+        self.check_invariants();
+        Ok(old_val)
+    }
+
+    /// Reorders the deque into key order, so `iter` yields the entries in the same order as
+    /// `iter_sorted`. The values are not touched.
+    ///
+    /// This is O(n). The keys are unique, so there is no separate unstable variant.
+    #[inline]
+    pub fn sort_keys(&mut self) {
+        self.indices.sort();
+        self.check_invariants();
+    }
+
+    /// Renames the key `old` to `new`, keeping its position in the deque and its value.
+    ///
+    /// Fails without changing the map if `old` is not in the map or `new` already is.
+    /// Renaming a key to itself is a no-op.
+    #[inline]
+    pub fn replace_key(&mut self, old: &K, new: K) -> Result<(), RenameError> {
+        if !self.entries.contains_key(old) {
+            return Err(RenameError::KeyNotFound);
+        }
+        if *old == new {
+            return Ok(());
+        }
+        if self.entries.contains_key(&new) {
+            return Err(RenameError::KeyExists);
+        }
+        if let Some(value) = self.entries.remove(old) {
+            self.entries.insert(new.clone(), value);
+            self.indices.rename(old, new);
+        }
+        self.check_invariants();
+        Ok(())
+    }
+
+    /// Moves all entries of `other` to the front of the deque, keeping their order, so the front
+    /// of `other` becomes the new front. A key present in both maps takes the value from `other`
+    /// and moves to the front segment. `other` is left empty.
+    #[inline]
+    pub fn append_front(&mut self, other: &mut DequeBTreeMap<K, V>) {
+        while let Some((k, v)) = other.pop_back() {
+            self.push_front(k, v);
+        }
+    }
+
+    /// Moves all entries of `other` to the back of the deque, keeping their order. `other` is
+    /// left empty.
+    ///
+    /// A key present in both maps is handled as by `push_back`: the value from `other` replaces
+    /// the old one, which is dropped, and the key moves to the back segment. This differs from
+    /// `BTreeMap::append`, which overwrites the value in place.
+    #[inline]
+    pub fn append(&mut self, other: &mut DequeBTreeMap<K, V>) {
+        while let Some((k, v)) = other.pop_front() {
+            self.push_back(k, v);
+        }
+    }
+
+    /// Merges `other` into the map. For a key present in both maps, `resolve` combines the
+    /// incoming value into the existing one in place and the key keeps its position; the other
+    /// keys are pushed to the back in the order of `other`.
+    ///
+    /// ```
+    /// use dequemap::DequeBTreeMap;
+    ///
+    /// let mut counts: DequeBTreeMap<_, _> = [("a", 1), ("b", 2)].into();
+    /// counts.merge_with([("c", 5), ("a", 3)].into(), |_, count, n| *count += n);
+    /// assert!(counts.iter().eq([(&"a", &4), (&"b", &2), (&"c", &5)]));
+    /// ```
+    pub fn merge_with<F>(&mut self, other: DequeBTreeMap<K, V>, mut resolve: F)
+    where
+        F: FnMut(&K, &mut V, V),
+    {
+        for (k, v) in other {
+            match self.entries.get_mut(&k) {
+                Some(existing) => resolve(&k, existing, v),
+                None => {
+                    self.entries.insert(k.clone(), v);
+                    self.indices.push_back(k);
+                }
+            }
+        }
+        self.check_invariants();
+    }
+
+    /// Splits the map in two at position `at` of the deque: `self` keeps the entries before
+    /// `at`, the entries from `at` on are returned in a new map in the same order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at` is larger than the length of the map.
+    #[inline]
+    pub fn split_off(&mut self, at: usize) -> Self {
+        assert!(
+            at <= self.len(),
+            "DequeBTreeMap: split_off index out of bounds"
+        );
+        let mut other = Self::new();
+        while self.indices.len() > at {
+            if let Some(k) = self.indices.pop_back() {
+                if let Some(v) = self.entries.remove(&k) {
+                    other.entries.insert(k.clone(), v);
+                    other.indices.push_front(k);
+                }
+            }
+        }
+        self.check_invariants();
+        other.check_invariants();
+        other
+    }
+
+    /// Creates a map from pairs whose keys are strictly increasing, so they are unique and the
+    /// deque order is also the key order, e.g. the rows of a sorted database scan.
+    ///
+    /// Both the entries and the deque are built in bulk, skipping the per-key lookups of
+    /// `FromIterator`. Keys out of order are caught by a debug assertion, in release builds they
+    /// leave the map in an unspecified but memory-safe state.
+    pub fn from_sorted_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let items: Vec<(K, V)> = iter.into_iter().collect();
+        debug_assert!(
+            items.windows(2).all(|w| w[0].0 < w[1].0),
+            "DequeBTreeMap::from_sorted_iter: keys are not strictly increasing"
+        );
+        let indices = Indices::from_sorted(items.iter().map(|(k, _)| k));
+        let map = Self {
+            entries: items.into_iter().collect(),
+            indices,
+        };
+        map.check_invariants();
+        map
+    }
+
+    /// Creates a map from an iterator using `push_back`: a repeated key takes the position
+    /// of its last occurrence along with its last value.
+    ///
+    /// `FromIterator` keeps the position of the first occurrence instead.
+    pub fn from_iter_last_position<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut map = Self::new();
+        for (k, v) in iter {
+            map.push_back(k, v);
+        }
+        map
+    }
+
     #[inline]
     pub fn entry(&mut self, key: K) -> Entry<K, V>
     where
         K: Ord,
     {
+        self.entry_at(key, false)
+    }
+
+    /// Like `entry`, but a vacant entry is inserted at the front of the deque instead of the
+    /// back. An occupied entry stays where it is, as with `entry`.
+    #[inline]
+    pub fn entry_front(&mut self, key: K) -> Entry<'_, K, V> {
+        self.entry_at(key, true)
+    }
+
+    #[inline]
+    fn entry_at(&mut self, key: K, front: bool) -> Entry<'_, K, V> {
+        let len = self.entries.len();
         match self.entries.entry(key) {
             btree_map::Entry::Vacant(entry) => Entry::Vacant(VacantEntry {
                 vacant: entry,
                 indices: &mut self.indices,
+                front,
+                len,
+            }),
+            btree_map::Entry::Occupied(entry) => Entry::Occupied(OccupiedEntry {
+                occupied: entry,
+                indices: &mut self.indices,
+                len,
             }),
-            btree_map::Entry::Occupied(entry) => Entry::Occupied(OccupiedEntry { occupied: entry }),
         }
     }
 
+    /// Like `entry`, but an existing key is moved to the back of the deque first, so however
+    /// the entry is resolved the key ends up at the back, as with `push_back`.
+    #[inline]
+    pub fn entry_push_back(&mut self, key: K) -> Entry<'_, K, V>
+    where
+        K: Ord,
+    {
+        let mut entry = self.entry(key);
+        if let Entry::Occupied(occupied) = &mut entry {
+            occupied.move_to_back();
+        }
+        entry
+    }
+
     #[inline]
     fn remove_entry(&mut self, key: &K) -> Option<V> {
         if let Some(old_val) = self.entries.remove(key) {
-            self.remove_from_index(key);
+            self.indices.remove(key);
             Some(old_val)
         } else {
             None
@@ -186,8 +434,27 @@ where
         self.indices.shrink_to_fit();
     }
 
+    /// Shrinks the deque of indices to fit and rebuilds the BTreeMap from the remaining entries,
+    /// which packs them into full nodes, e.g. after most entries of a long-lived map were removed.
+    #[inline]
+    pub fn compact(&mut self) {
+        self.indices.shrink_to_fit();
+        self.entries = take(&mut self.entries).into_iter().collect();
+        self.check_invariants();
+    }
+
+    /// Replaces the entries and their order with those of `new`, keeping the allocation of the
+    /// deque of indices when it is large enough, e.g. to refresh a map in full without growing
+    /// a new deque every time.
+    #[inline]
+    pub fn replace_contents(&mut self, new: DequeBTreeMap<K, V>) {
+        self.indices.replace(new.indices);
+        self.entries = new.entries;
+        self.check_invariants();
+    }
+
     #[inline]
-    pub fn capacity(&mut self) -> usize {
+    pub fn capacity(&self) -> usize {
         self.indices.capacity()
     }
 }
@@ -203,6 +470,12 @@ impl<K, V> DequeBTreeMap<K, V> {
     pub fn clear(&mut self) {
         self.indices.clear();
         self.entries.clear();
+        //Both are empty, so unlike `check_invariants` this needs no `K: Ord`
+        #[cfg(feature = "debug-invariants")]
+        debug_assert!(
+            self.indices.is_empty() && self.indices.slots.is_empty() && self.entries.is_empty(),
+            "DequeBTreeMap: entries and indices out of sync"
+        );
     }
 
     #[inline]
@@ -211,7 +484,8 @@ impl<K, V> DequeBTreeMap<K, V> {
         K: Ord,
     {
         if let Some(old_val) = self.entries.remove(k) {
-            self.remove_from_index(k);
+            self.indices.remove(k);
+            self.check_invariants();
             Some(old_val)
         } else {
             None
@@ -236,817 +510,3532 @@ impl<K, V> DequeBTreeMap<K, V> {
         self.entries.get_key_value(key)
     }
 
+    /// Returns the position of `key` in the deque, e.g. to pair an entry with the element at
+    /// the same position of a parallel `Vec`.
+    ///
+    /// The position of the key is tracked, so this is O(log n) unless keys were removed from
+    /// the middle of the deque since it was last compacted, then the keys in front of it are
+    /// counted, O(n).
     #[inline]
-    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    pub fn get_index_of<Q>(&self, key: &Q) -> Option<usize>
     where
         K: Borrow<Q> + Ord,
         Q: Ord + ?Sized,
     {
-        self.entries.get_mut(k)
+        self.indices.position(key)
     }
 
     #[inline]
-    pub fn iter(&self) -> Iter<'_, K, V> {
-        Iter {
-            inner: self.indices.iter(),
-            entries: &self.entries,
-        }
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.entries.get_mut(k)
     }
 
+    /// Returns mutable references to the values of all `keys` at once, or `None` if any key is
+    /// missing or the keys are not distinct.
+    ///
+    /// This walks the entries between the smallest and the largest of the keys, so it is
+    /// cheapest when the keys are close together.
     #[inline]
-    pub fn len(&self) -> usize {
-        self.indices.len()
+    pub fn get_disjoint_mut<Q, const N: usize>(&mut self, keys: [&Q; N]) -> Option<[&mut V; N]>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        //Indexes into `keys` in key order, so a repeated key ends up next to itself
+        let mut order: [usize; N] = core::array::from_fn(|i| i);
+        order.sort_unstable_by(|&a, &b| keys[a].cmp(keys[b]));
+        if order.windows(2).any(|w| keys[w[0]] == keys[w[1]]) {
+            return None;
+        }
+        let mut values: [Option<&mut V>; N] = core::array::from_fn(|_| None);
+        if let (Some(&first), Some(&last)) = (order.first(), order.last()) {
+            let range = (Bound::Included(keys[first]), Bound::Included(keys[last]));
+            let mut wanted = order.iter().peekable();
+            for (k, v) in self.entries.range_mut::<Q, _>(range) {
+                let Some(&&idx) = wanted.peek() else {
+                    break;
+                };
+                match k.borrow().cmp(keys[idx]) {
+                    Ordering::Less => {}
+                    Ordering::Equal => {
+                        values[idx] = Some(v);
+                        wanted.next();
+                    }
+                    Ordering::Greater => return None,
+                }
+            }
+        }
+        if values.iter().any(Option::is_none) {
+            return None;
+        }
+        Some(values.map(|v| v.expect("checked above")))
     }
 
+    /// Returns the value of `k` and moves the key to the back, making it the most
+    /// recently used one in an LRU cache.
     #[inline]
-    pub fn is_empty(&self) -> bool {
-        self.indices.is_empty()
+    pub fn touch_get<Q>(&mut self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.move_to_back(k);
+        self.entries.get(k)
     }
 
+    /// Like `touch_get`, but returns a mutable reference to the value.
     #[inline]
-    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    pub fn touch_get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
     where
         K: Borrow<Q> + Ord,
         Q: Ord + ?Sized,
     {
-        self.entries.contains_key(k)
+        self.move_to_back(k);
+        self.entries.get_mut(k)
     }
 
+    /// Returns the entry at position `idx` of the deque, or `None` if `idx` is out of bounds.
+    ///
+    /// This is O(1) unless keys were removed from the middle of the deque since it was last
+    /// compacted, then the deque is scanned up to `idx`, O(n).
     #[inline]
-    pub fn front(&self) -> Option<(&K, &V)>
+    pub fn get_index(&self, idx: usize) -> Option<(&K, &V)>
     where
         K: Ord,
     {
-        if self.is_empty() {
-            return None;
-        }
-        if let Some(k) = self.indices.front() {
-            self.entries.get(k).map(|v| (k, v))
-        } else {
-            None
-        }
+        let k = self.indices.get(idx)?;
+        self.entries.get(k).map(|v| (k, v))
     }
 
+    /// Returns the entry at position `idx` of the deque with a mutable reference to the value,
+    /// or `None` if `idx` is out of bounds.
     #[inline]
-    pub fn pop_front(&mut self) -> Option<(K, V)>
+    pub fn get_index_mut(&mut self, idx: usize) -> Option<(&K, &mut V)>
     where
         K: Ord,
     {
-        if let Some(k) = self.indices.pop_front() {
-            self.entries.remove(&k).map(|v| (k, v))
-        } else {
-            None
-        }
+        let k = self.indices.get(idx)?;
+        self.entries.get_mut(k).map(|v| (k, v))
     }
 
+    /// Removes and returns the entry at position `idx` of the deque, the entries behind it move
+    /// up one position. Returns `None` if `idx` is out of bounds.
     #[inline]
-    pub fn back(&self) -> Option<(&K, &V)>
+    pub fn remove_index(&mut self, idx: usize) -> Option<(K, V)>
     where
         K: Ord,
     {
-        if self.is_empty() {
-            return None;
-        }
-        if let Some(k) = self.indices.back() {
-            self.entries.get(k).map(|v| (k, v))
-        } else {
-            None
-        }
+        let k = self.indices.remove_at(idx)?;
+        let item = self.entries.remove(&k).map(|v| (k, v));
+        self.check_invariants();
+        item
     }
 
+    /// Reorders the deque with a comparator over the entries, e.g. to order them by value. The
+    /// sort is stable, entries that compare equal keep their relative order. Lookups by key are
+    /// not affected.
     #[inline]
-    pub fn pop_back(&mut self) -> Option<(K, V)>
+    pub fn sort_by<F>(&mut self, mut cmp: F)
     where
         K: Ord,
+        F: FnMut((&K, &V), (&K, &V)) -> Ordering,
     {
-        if let Some(k) = self.indices.pop_back() {
-            self.entries.remove(&k).map(|v| (k, v))
-        } else {
-            None
-        }
+        let entries = &self.entries;
+        self.indices
+            .sort_by(|a, b| cmp((a, &entries[a]), (b, &entries[b])));
+        self.check_invariants();
     }
 
+    /// Swaps the entries at positions `a` and `b` of the deque, the values stay with their keys
+    /// and the other entries keep their positions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `a` or `b` is out of bounds.
     #[inline]
-    pub fn retain<F>(&mut self, mut f: F)
+    pub fn swap_indices(&mut self, a: usize, b: usize)
     where
-        K: Ord + Clone,
-        F: FnMut(&K, &mut V) -> bool,
+        K: Ord,
     {
-        let mut removeds = BTreeSet::new();
-        self.entries.retain(|k, v| {
-            if f(k, v) {
-                true
-            } else {
-                removeds.insert(k.clone());
-                false
-            }
-        });
-        self.indices.retain(|k| !removeds.contains(k))
+        self.indices.swap(a, b);
+        self.check_invariants();
     }
 
+    /// Rotates the deque `mid` places to the left, so the entry at position `mid` becomes the
+    /// front, like `VecDeque::rotate_left`. The values stay with their keys.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mid` is larger than the length of the map.
     #[inline]
-    fn get_index(&self, k: &K) -> Option<usize>
+    pub fn rotate_left(&mut self, mid: usize)
     where
         K: Ord,
     {
-        self.indices
-            .iter()
-            .enumerate()
-            .find(|(_, x)| *x == k)
-            .map(|(idx, _)| idx)
+        self.indices.rotate_left(mid);
+        self.check_invariants();
     }
 
+    /// Rotates the deque `k` places to the right, so the entry at position `len - k` becomes
+    /// the front, like `VecDeque::rotate_right`. The values stay with their keys.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `k` is larger than the length of the map.
     #[inline]
-    fn remove_from_index(&mut self, k: &K) -> Option<K>
+    pub fn rotate_right(&mut self, k: usize)
     where
         K: Ord,
     {
-        if let Some(idx) = self.get_index(k) {
-            self.indices.remove(idx)
-        } else {
-            None
-        }
-    }
-}
-
-impl<'a, K, Q, V> Index<&'a Q> for DequeBTreeMap<K, V>
-where
-    K: Borrow<Q> + Ord,
-    Q: Ord,
-{
-    type Output = V;
-
-    fn index(&self, key: &'a Q) -> &Self::Output {
-        self.get(key).expect("no entry found for key")
+        assert!(k <= self.len(), "DequeBTreeMap: rotate index out of bounds");
+        self.indices.rotate_left(self.len() - k);
+        self.check_invariants();
     }
-}
-
-impl<K: Ord, V> Index<usize> for DequeBTreeMap<K, V> {
-    type Output = V;
 
-    fn index(&self, index: usize) -> &Self::Output {
-        let key = self
-            .indices
-            .get(index)
-            .expect("DequeBTreeMap: index out of bounds");
-        self.entries
-            .get(key)
-            .expect("DequeBTreeMap: index out of bounds")
+    /// Moves `key` to the back of the deque without touching its value, returns whether the
+    /// key is in the map. Moving the key at the back is a no-op.
+    #[inline]
+    pub fn move_to_back<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        let found = self.indices.move_to_back(key);
+        self.check_invariants();
+        found
     }
-}
 
-impl<K: Ord, V> IndexMut<usize> for DequeBTreeMap<K, V> {
-    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        let key = self
-            .indices
-            .get(index)
-            .expect("DequeBTreeMap: index out of bounds");
-        self.entries
-            .get_mut(key)
-            .expect("DequeBTreeMap: index out of bounds")
+    /// Moves `key` to the front of the deque without touching its value, returns whether the
+    /// key is in the map. Moving the key at the front is a no-op.
+    #[inline]
+    pub fn move_to_front<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        let found = self.indices.move_to_front(key);
+        self.check_invariants();
+        found
     }
-}
 
-impl<K, V> IntoIterator for DequeBTreeMap<K, V>
-where
-    K: Ord,
-{
-    type Item = (K, V);
-    type IntoIter = IntoIter<K, V>;
-
-    fn into_iter(self) -> Self::IntoIter {
-        IntoIter {
-            inner: self.indices.into_iter(),
-            entries: self.entries,
+    #[inline]
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            inner: self.indices.iter(),
+            entries: &self.entries,
         }
     }
-}
 
-impl<'a, K, V> Extend<(&'a K, &'a V)> for DequeBTreeMap<K, V>
-where
-    K: Ord + Copy,
-    V: Copy,
-{
-    fn extend<T>(&mut self, iter: T)
+    /// Iterates over the entries in deque order with mutable references to the values.
+    ///
+    /// Creating the iterator puts every entry in deque order up front, which takes O(n) time
+    /// and allocates a `Vec` of n references; each step is then O(1).
+    #[inline]
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V>
     where
-        T: IntoIterator<Item = (&'a K, &'a V)>,
+        K: Ord,
     {
-        for (k, v) in iter {
-            self.insert(*k, *v);
+        IterMut {
+            inner: self.indices.order_mut(&mut self.entries).into_iter(),
         }
     }
-}
 
-impl<K, V> Extend<(K, V)> for DequeBTreeMap<K, V>
-where
-    K: Ord + Clone,
-{
-    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
-        for (k, v) in iter {
-            self.insert(k, v);
-        }
+    /// Iterates over the keys in deque order.
+    #[inline]
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys { inner: self.iter() }
     }
-}
 
-impl<K, V> FromIterator<(K, V)> for DequeBTreeMap<K, V>
-where
-    K: Ord + Clone,
-{
-    fn from_iter<T>(iter: T) -> Self
+    /// Iterates over the values in deque order.
+    #[inline]
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values { inner: self.iter() }
+    }
+
+    /// Iterates over mutable references to the values in deque order. Like `iter_mut`, creating
+    /// it allocates a `Vec` of n references.
+    #[inline]
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V>
     where
-        T: IntoIterator<Item = (K, V)>,
+        K: Ord,
     {
-        let mut map = DequeBTreeMap::new();
-        map.extend(iter);
-        map
+        ValuesMut {
+            inner: self.iter_mut(),
+        }
     }
-}
 
-impl<K, V, const N: usize> From<[(K, V); N]> for DequeBTreeMap<K, V>
-where
-    K: Ord + Clone,
-{
-    fn from(items: [(K, V); N]) -> Self {
-        let mut map = DequeBTreeMap::new();
-        map.extend(items);
-        map
+    /// Iterates over the entries in key order, unlike `iter` which follows the deque order.
+    #[inline]
+    pub fn iter_sorted(&self) -> impl DoubleEndedIterator<Item = (&K, &V)> + ExactSizeIterator {
+        self.entries.iter()
     }
-}
-
-impl<'a, K: Ord, V> IntoIterator for &'a DequeBTreeMap<K, V> {
-    type Item = (&'a K, &'a V);
-    type IntoIter = Iter<'a, K, V>;
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.iter()
+    /// Iterates over the entries in deque order along with their position in the deque.
+    #[inline]
+    pub fn iter_indexed(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = (usize, &K, &V)> + ExactSizeIterator + '_
+    where
+        K: Ord,
+    {
+        self.iter().enumerate().map(|(idx, (k, v))| (idx, k, v))
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct Iter<'a, K, V> {
-    inner: DequeIter<'a, K>,
-    entries: &'a BTreeMap<K, V>,
-}
+    /// Iterates over the entries whose positions in the deque fall in `range`, in deque order.
+    /// Bounds past the end are clamped to the length of the map instead of panicking, so a
+    /// page beyond the last entry is empty.
+    ///
+    /// This is O(1) to create unless keys were removed from the middle of the deque since it
+    /// was last compacted, then the deque is scanned up to the start of `range`.
+    #[inline]
+    pub fn get_range<R>(&self, range: R) -> Iter<'_, K, V>
+    where
+        R: RangeBounds<usize>,
+    {
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.saturating_add(1),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len(),
+        }
+        .min(self.len());
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        }
+        .min(end);
+        let mut rest = self.indices.iter();
+        rest.split_front(start);
+        Iter {
+            inner: rest.split_front(end - start),
+            entries: &self.entries,
+        }
+    }
 
-impl<'a, K: Ord, V> Iterator for Iter<'a, K, V> {
-    type Item = (&'a K, &'a V);
+    /// Iterates over all windows of `size` consecutive entries in deque order, the windows
+    /// overlap. There are no windows if `size` is larger than the map.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    #[inline]
+    pub fn windows(&self, size: usize) -> impl Iterator<Item = Iter<'_, K, V>> + '_
+    where
+        K: Ord,
+    {
+        assert!(size != 0, "window size must be non-zero");
+        let mut rest = self.indices.iter();
+        (0..(self.len() + 1).saturating_sub(size)).map(move |_| {
+            let window = rest.clone().split_front(size);
+            rest.next();
+            Iter {
+                inner: window,
+                entries: &self.entries,
+            }
+        })
+    }
 
+    /// Iterates over `size` consecutive entries at a time in deque order, the chunks do not
+    /// overlap. The last chunk is shorter if `size` does not divide the length of the map.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
     #[inline]
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(k) = self.inner.next() {
-            self.entries.get(k).map(|v| (k, v))
-        } else {
-            None
-        }
+    pub fn chunks(&self, size: usize) -> impl Iterator<Item = Iter<'_, K, V>> + '_
+    where
+        K: Ord,
+    {
+        assert!(size != 0, "chunk size must be non-zero");
+        let mut rest = self.indices.iter();
+        (0..self.len()).step_by(size).map(move |_| Iter {
+            inner: rest.split_front(size),
+            entries: &self.entries,
+        })
     }
 
     #[inline]
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        self.inner.size_hint()
+    pub fn len(&self) -> usize {
+        self.indices.len()
     }
 
+    /// Returns an approximate number of heap bytes used by the map.
+    ///
+    /// The estimate covers the leaf nodes of the BTreeMaps of entries and of key positions
+    /// (assuming fully packed nodes of 11 entries) and the allocated capacity of the VecDeque of
+    /// indices. Internal BTreeMap nodes and any
+    /// allocations owned by the keys or values themselves (e.g. the buffer of a `String`) are
+    /// not counted, so the real footprint is somewhat larger.
     #[inline]
-    fn count(self) -> usize {
-        self.inner.count()
+    pub fn heap_size(&self) -> usize {
+        const NODE_CAPACITY: usize = 11;
+        let node_size = |item_size: usize| {
+            NODE_CAPACITY * item_size + size_of::<usize>() + 2 * size_of::<u16>()
+        };
+        let nodes = (self.entries.len() + NODE_CAPACITY - 1) / NODE_CAPACITY;
+        nodes * (node_size(size_of::<K>() + size_of::<V>()) + node_size(size_of::<(K, isize)>()))
+            + self.indices.capacity() * size_of::<Option<K>>()
     }
-}
 
-impl<K: Ord, V> DoubleEndedIterator for Iter<'_, K, V> {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        if let Some(k) = self.inner.next_back() {
-            self.entries.get(k).map(|v| (k, v))
-        } else {
-            None
-        }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
     }
-}
 
-impl<K: Ord, V> ExactSizeIterator for Iter<'_, K, V> {
-    fn len(&self) -> usize {
-        self.inner.len()
+    #[inline]
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.entries.contains_key(k)
     }
-}
 
-impl<K: Ord, V> FusedIterator for Iter<'_, K, V> {}
+    /// Returns the deque position, key and value of the first entry, in deque order, whose value
+    /// matches `pred`.
+    ///
+    /// This scans the entries one by one, O(n).
+    #[inline]
+    pub fn find_position<F>(&self, mut pred: F) -> Option<(usize, &K, &V)>
+    where
+        K: Ord,
+        F: FnMut(&V) -> bool,
+    {
+        self.iter()
+            .enumerate()
+            .find(|(_, (_, v))| pred(v))
+            .map(|(idx, (k, v))| (idx, k, v))
+    }
 
-pub struct IntoIter<K, V> {
-    inner: DequeIntoIter<K>,
-    entries: BTreeMap<K, V>,
-}
+    /// Returns the position of the first entry, in deque order, for which `pred` returns true,
+    /// stopping at the first match.
+    #[inline]
+    pub fn position<F>(&self, mut pred: F) -> Option<usize>
+    where
+        K: Ord,
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.iter().position(|(k, v)| pred(k, v))
+    }
 
-impl<K: Ord, V> Iterator for IntoIter<K, V> {
-    type Item = (K, V);
+    /// Like `position`, but scans from the back of the deque and returns the position of the
+    /// last matching entry.
+    #[inline]
+    pub fn rposition<F>(&self, mut pred: F) -> Option<usize>
+    where
+        K: Ord,
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.iter().rposition(|(k, v)| pred(k, v))
+    }
 
     #[inline]
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(k) = self.inner.next() {
-            self.entries.remove(&k).map(|v| (k, v))
+    pub fn front(&self) -> Option<(&K, &V)>
+    where
+        K: Ord,
+    {
+        if self.is_empty() {
+            return None;
+        }
+        if let Some(k) = self.indices.front() {
+            self.entries.get(k).map(|v| (k, v))
         } else {
             None
         }
     }
 
     #[inline]
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        self.inner.size_hint()
+    pub fn pop_front(&mut self) -> Option<(K, V)>
+    where
+        K: Ord,
+    {
+        if let Some(k) = self.indices.pop_front() {
+            let item = self.entries.remove(&k).map(|v| (k, v));
+            self.check_invariants();
+            item
+        } else {
+            None
+        }
     }
 
     #[inline]
-    fn count(self) -> usize {
-        self.inner.count()
+    pub fn back(&self) -> Option<(&K, &V)>
+    where
+        K: Ord,
+    {
+        if self.is_empty() {
+            return None;
+        }
+        if let Some(k) = self.indices.back() {
+            self.entries.get(k).map(|v| (k, v))
+        } else {
+            None
+        }
     }
-}
 
-impl<K: Ord, V> DoubleEndedIterator for IntoIter<K, V> {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        if let Some(k) = self.inner.next_back() {
-            self.entries.remove(&k).map(|v| (k, v))
+    #[inline]
+    pub fn pop_back(&mut self) -> Option<(K, V)>
+    where
+        K: Ord,
+    {
+        if let Some(k) = self.indices.pop_back() {
+            let item = self.entries.remove(&k).map(|v| (k, v));
+            self.check_invariants();
+            item
         } else {
             None
         }
     }
-}
 
-impl<K: Ord, V> ExactSizeIterator for IntoIter<K, V> {
-    fn len(&self) -> usize {
-        self.inner.len()
+    /// Returns the front entry with a mutable reference to its value.
+    #[inline]
+    pub fn front_mut(&mut self) -> Option<(&K, &mut V)>
+    where
+        K: Ord,
+    {
+        let k = self.indices.front()?;
+        self.entries.get_mut(k).map(|v| (k, v))
     }
-}
-
-impl<K: Ord, V> FusedIterator for IntoIter<K, V> {}
 
-/// A view into a single entry in a map, which may either be vacant or occupied.
-///
-/// This `enum` is constructed from the [`entry`] method on [`DequeBTreeMap`].
-///
-/// [`entry`]: DequeBTreeMap::entry
-pub enum Entry<'a, K, V> {
-    /// A vacant entry.
-    Vacant(VacantEntry<'a, K, V>),
-    /// An occupied entry.
-    Occupied(OccupiedEntry<'a, K, V>),
-}
+    /// Returns the back entry with a mutable reference to its value.
+    #[inline]
+    pub fn back_mut(&mut self) -> Option<(&K, &mut V)>
+    where
+        K: Ord,
+    {
+        let k = self.indices.back()?;
+        self.entries.get_mut(k).map(|v| (k, v))
+    }
 
-impl<'a, K: Ord, V> Entry<'a, K, V> {
-    /// Ensures a value is in the entry by inserting the default if empty,
-    /// and returns a mutable reference to the value in the entry.
-    pub fn or_insert(self, default: V) -> &'a mut V
+    /// Pops the front entry only if `pred` returns true for it, e.g. to expire entries from
+    /// the front of a timeout queue while they are due.
+    #[inline]
+    pub fn pop_front_if<F>(&mut self, pred: F) -> Option<(K, V)>
     where
-        K: Clone,
+        K: Ord,
+        F: FnOnce(&K, &V) -> bool,
     {
-        match self {
-            Self::Occupied(entry) => entry.into_mut(),
-            Self::Vacant(entry) => entry.insert(default),
+        let (k, v) = self.front()?;
+        if pred(k, v) {
+            self.pop_front()
+        } else {
+            None
         }
     }
 
-    /// Ensures a value is in the entry by inserting the result
-    /// of the default function if empty,
-    /// and returns a mutable reference to the value in the entry.
-    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V
+    /// Pops the back entry only if `pred` returns true for it.
+    #[inline]
+    pub fn pop_back_if<F>(&mut self, pred: F) -> Option<(K, V)>
     where
-        K: Clone,
+        K: Ord,
+        F: FnOnce(&K, &V) -> bool,
     {
-        match self {
-            Self::Occupied(entry) => entry.into_mut(),
-            Self::Vacant(entry) => entry.insert(default()),
+        let (k, v) = self.back()?;
+        if pred(k, v) {
+            self.pop_back()
+        } else {
+            None
         }
     }
 
-    /// Ensures a value is in the entry by inserting,
-    /// if empty, the result of the default function.
-    ///
-    /// This method allows for generating key-derived values for
-    /// insertion by providing the default function a reference
-    /// to the key that was moved during the `.entry(key)` method call.
-    ///
-    /// The reference to the moved key is provided
-    /// so that cloning or copying the key is
-    /// unnecessary, unlike with `.or_insert_with(|| ... )`.
-    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V
+    /// Returns the value at the front of the deque, like `front` without the key.
+    #[inline]
+    pub fn first(&self) -> Option<&V>
     where
-        K: Clone,
+        K: Ord,
     {
-        match self {
-            Self::Occupied(entry) => entry.into_mut(),
-            Self::Vacant(entry) => {
-                let value = default(entry.key());
-                entry.insert(value)
-            }
-        }
+        let k = self.indices.front()?;
+        self.entries.get(k)
     }
 
-    /// Returns a reference to this entry’s key.
-    pub fn key(&self) -> &K {
-        match *self {
-            Self::Occupied(ref entry) => entry.key(),
-            Self::Vacant(ref entry) => entry.key(),
+    /// Returns a mutable reference to the value at the front of the deque.
+    #[inline]
+    pub fn first_mut(&mut self) -> Option<&mut V>
+    where
+        K: Ord,
+    {
+        let k = self.indices.front()?;
+        self.entries.get_mut(k)
+    }
+
+    /// Returns the value at the back of the deque, like `back` without the key.
+    #[inline]
+    pub fn last(&self) -> Option<&V>
+    where
+        K: Ord,
+    {
+        let k = self.indices.back()?;
+        self.entries.get(k)
+    }
+
+    /// Returns a mutable reference to the value at the back of the deque.
+    #[inline]
+    pub fn last_mut(&mut self) -> Option<&mut V>
+    where
+        K: Ord,
+    {
+        let k = self.indices.back()?;
+        self.entries.get_mut(k)
+    }
+
+    /// Keeps only the `n` most recent entries, dropping from the front of the deque.
+    #[inline]
+    pub fn keep_back(&mut self, n: usize)
+    where
+        K: Ord,
+    {
+        while self.indices.len() > n {
+            if let Some(k) = self.indices.pop_front() {
+                self.entries.remove(&k);
+            }
         }
+        self.check_invariants();
     }
 
-    /// Provides in-place mutable access to an occupied entry
-    /// before any potential inserts into the map.
-    pub fn and_modify<F>(self, f: F) -> Self
+    /// Keeps only the `n` oldest entries, dropping from the back of the deque.
+    #[inline]
+    pub fn keep_front(&mut self, n: usize)
     where
-        F: FnOnce(&mut V),
+        K: Ord,
     {
-        match self {
-            Self::Occupied(mut entry) => {
-                f(entry.get_mut());
-                Self::Occupied(entry)
+        while self.indices.len() > n {
+            if let Some(k) = self.indices.pop_back() {
+                self.entries.remove(&k);
+            }
+        }
+        self.check_invariants();
+    }
+
+    /// Keeps the first `len` entries of the deque and drops the rest, like
+    /// `VecDeque::truncate`. The same as `keep_front`.
+    #[inline]
+    pub fn truncate_back(&mut self, len: usize)
+    where
+        K: Ord,
+    {
+        self.keep_front(len);
+    }
+
+    /// Keeps the last `len` entries of the deque and drops the rest. The same as `keep_back`.
+    #[inline]
+    pub fn truncate_front(&mut self, len: usize)
+    where
+        K: Ord,
+    {
+        self.keep_back(len);
+    }
+
+    /// Compares two maps lexicographically by their `(K, V)` pairs in deque order.
+    ///
+    /// This differs from the derived `Ord`, which compares the entries in key order first and
+    /// only falls back to the insertion order when the entries are equal.
+    #[inline]
+    pub fn cmp_by_sequence(&self, other: &Self) -> Ordering
+    where
+        K: Ord,
+        V: Ord,
+    {
+        self.iter().cmp(other.iter())
+    }
+
+    /// Returns whether both maps hold the same `(K, V)` pairs, whatever their order in the
+    /// deque.
+    ///
+    /// Unlike `==`, which also requires the same deque order.
+    #[inline]
+    pub fn eq_unordered(&self, other: &Self) -> bool
+    where
+        K: PartialEq,
+        V: PartialEq,
+    {
+        self.entries == other.entries
+    }
+
+    #[inline]
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        K: Ord + Clone,
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.entries.retain(|k, v| f(k, v));
+        let entries = &self.entries;
+        self.indices.retain(|k| entries.contains_key(k));
+        self.check_invariants();
+    }
+
+    /// Retains only the entries for which `f` returns true, changes made to the values of the
+    /// kept entries stay. The kept entries keep their order.
+    ///
+    /// The same as `retain`, which already passes the values mutably, named after
+    /// `Vec::retain_mut`.
+    #[inline]
+    pub fn retain_mut<F>(&mut self, f: F)
+    where
+        K: Ord + Clone,
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.retain(f);
+    }
+
+    /// Retains only the entries for which `f` returns true, calling it in deque order, and
+    /// returns the removed entries in their deque order.
+    #[inline]
+    pub fn retain_returning<F>(&mut self, mut f: F) -> Vec<(K, V)>
+    where
+        K: Ord + Clone,
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let mut removeds = Vec::new();
+        let entries = &mut self.entries;
+        self.indices.retain(|k| {
+            let keep = entries.get_mut(k).map(|v| f(k, v)).unwrap_or(false);
+            if !keep {
+                if let Some(v) = entries.remove(k) {
+                    removeds.push((k.clone(), v));
+                }
+            }
+            keep
+        });
+        self.check_invariants();
+        removeds
+    }
+
+    /// Clones the entries into a `VecDeque` of `(K, V)` pairs in deque order.
+    #[inline]
+    pub fn to_vecdeque(&self) -> VecDeque<(K, V)>
+    where
+        K: Ord + Clone,
+        V: Clone,
+    {
+        self.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    /// Converts the map into a `VecDeque` of `(K, V)` pairs in deque order.
+    #[inline]
+    pub fn into_vecdeque(self) -> VecDeque<(K, V)>
+    where
+        K: Ord,
+    {
+        self.into_iter().collect()
+    }
+
+    /// Clones the entries into a `Vec` of `(K, V)` pairs in deque order.
+    #[inline]
+    pub fn to_vec(&self) -> Vec<(K, V)>
+    where
+        K: Ord + Clone,
+        V: Clone,
+    {
+        let mut vec = Vec::with_capacity(self.len());
+        vec.extend(self.iter().map(|(k, v)| (k.clone(), v.clone())));
+        vec
+    }
+
+    /// Converts the map into a `Vec` of `(K, V)` pairs in deque order.
+    #[inline]
+    pub fn into_vec(self) -> Vec<(K, V)>
+    where
+        K: Ord,
+    {
+        let mut vec = Vec::with_capacity(self.len());
+        vec.extend(self);
+        vec
+    }
+
+    /// Borrows the entries as a `BTreeMap`, which iterates in key order; the deque order is not
+    /// visible through it.
+    #[inline]
+    pub fn as_btreemap(&self) -> &BTreeMap<K, V> {
+        &self.entries
+    }
+
+    /// Converts the map into a `BTreeMap` without moving or cloning any entry. The deque order
+    /// is lost.
+    #[inline]
+    pub fn into_btreemap(self) -> BTreeMap<K, V> {
+        self.entries
+    }
+
+    /// Checks that the ordered entries and the deque describe the same set of keys: equal
+    /// lengths, no duplicate key in the deque, every deque key present in the entries and every
+    /// key position up to date. Returns a description of the first broken invariant.
+    ///
+    /// It is O(n log n) and meant for tests and fuzzers, the map's own methods keep these
+    /// invariants.
+    pub fn validate(&self) -> Result<(), String>
+    where
+        K: Ord,
+    {
+        if self.entries.len() != self.indices.len() {
+            return Err(alloc::format!(
+                "entries has {} keys but indices has {}",
+                self.entries.len(),
+                self.indices.len()
+            ));
+        }
+        let mut seen = BTreeSet::new();
+        for (i, k) in self.indices.iter().enumerate() {
+            if !seen.insert(k) {
+                return Err(alloc::format!("duplicate key in indices at {}", i));
+            }
+            if !self.entries.contains_key(k) {
+                return Err(alloc::format!(
+                    "key in indices at {} is missing from entries",
+                    i
+                ));
             }
-            Self::Vacant(entry) => Self::Vacant(entry),
         }
+        if !self.indices.is_consistent() {
+            return Err(String::from("key positions out of sync"));
+        }
+        Ok(())
+    }
+
+    /// Asserts `validate`.
+    ///
+    /// Only active with the `debug-invariants` feature in debug builds, otherwise this
+    /// compiles to nothing.
+    #[inline]
+    fn check_invariants(&self)
+    where
+        K: Ord,
+    {
+        #[cfg(feature = "debug-invariants")]
+        debug_assert_eq!(self.validate(), Ok(()), "DequeBTreeMap: invariants broken");
     }
 }
 
-impl<'a, K, V> Entry<'a, K, V>
+impl<'a, K, Q, V> Index<&'a Q> for DequeBTreeMap<K, V>
 where
-    K: Ord + Clone,
-    V: Default,
+    K: Borrow<Q> + Ord,
+    Q: Ord,
 {
-    /// Ensures a value is in the entry by inserting the default value if empty,
-    /// and returns a mutable reference to the value in the entry.
-    pub fn or_default(self) -> &'a mut V {
-        match self {
-            Self::Occupied(entry) => entry.into_mut(),
-            Self::Vacant(entry) => entry.insert(Default::default()),
+    type Output = V;
+
+    fn index(&self, key: &'a Q) -> &Self::Output {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+impl<K: Ord, V> Index<usize> for DequeBTreeMap<K, V> {
+    type Output = V;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.get_index(index)
+            .map(|(_, v)| v)
+            .expect("DequeBTreeMap: index out of bounds")
+    }
+}
+
+impl<K: Ord, V> IndexMut<usize> for DequeBTreeMap<K, V> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        self.get_index_mut(index)
+            .map(|(_, v)| v)
+            .expect("DequeBTreeMap: index out of bounds")
+    }
+}
+
+//Hashes the entries in deque order, as maps with the same entries in another order are not equal
+impl<K: Hash + Ord, V: Hash> Hash for DequeBTreeMap<K, V> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        for (k, v) in self.iter() {
+            k.hash(state);
+            v.hash(state);
         }
     }
 }
 
-impl<K, V> fmt::Debug for Entry<'_, K, V>
+/// Requires crate feature `"rayon"`
+#[cfg(feature = "rayon")]
+impl<K, V> DequeBTreeMap<K, V>
 where
-    K: fmt::Debug + Ord,
-    V: fmt::Debug,
+    K: Ord + Sync,
+    V: Sync,
 {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Entry::Vacant(entry) => entry.fmt(f),
-            Entry::Occupied(entry) => entry.fmt(f),
-        }
+    /// Iterates over the entries in parallel. The deque order is kept by order-preserving
+    /// adaptors such as `collect` into a `Vec`.
+    #[inline]
+    pub fn par_iter(&self) -> impl rayon::iter::ParallelIterator<Item = (&K, &V)> + '_ {
+        use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+        let entries = &self.entries;
+        self.indices
+            .slots
+            .par_iter()
+            .filter_map(move |k| k.as_ref().map(|k| (k, &entries[k])))
     }
-}
 
-/// A view into a vacant entry in an [`DequeBTreeMap`]. It is part of the [`Entry`] `enum`.
-pub struct VacantEntry<'a, K, V> {
-    /// The underlying vacant entry.
-    vacant: btree_map::VacantEntry<'a, K, V>,
-    /// The vector that stores all slots.
-    indices: &'a mut VecDeque<K>,
+    /// Iterates over the values in parallel, like `par_iter` without the keys.
+    #[inline]
+    pub fn par_values(&self) -> impl rayon::iter::ParallelIterator<Item = &V> + '_ {
+        use rayon::iter::ParallelIterator;
+
+        self.par_iter().map(|(_, v)| v)
+    }
 }
 
-impl<'a, K, V> VacantEntry<'a, K, V>
+impl<K, V> IntoIterator for DequeBTreeMap<K, V>
 where
     K: Ord,
 {
-    /// Gets a reference to the key that would be used when inserting a value through the VacantEntry.
-    pub fn key(&self) -> &K {
-        self.vacant.key()
-    }
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
 
-    /// Take ownership of the key.
-    pub fn into_key(self) -> K {
-        self.vacant.into_key()
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            inner: self.indices.into_iter(),
+            entries: self.entries,
+        }
     }
+}
 
-    /// Sets the value of the entry with the `VacantEntry`’s key,
-    /// and returns a mutable reference to it.
-    pub fn insert(self, value: V) -> &'a mut V
+impl<'a, K, V> Extend<(&'a K, &'a V)> for DequeBTreeMap<K, V>
+where
+    K: Ord + Copy,
+    V: Copy,
+{
+    fn extend<T>(&mut self, iter: T)
     where
-        K: Clone,
+        T: IntoIterator<Item = (&'a K, &'a V)>,
     {
-        self.indices.push_back(self.vacant.key().clone());
-        self.vacant.insert(value)
+        for (k, v) in iter {
+            self.insert(*k, *v);
+        }
     }
 }
 
-impl<K, V> fmt::Debug for VacantEntry<'_, K, V>
+impl<K, V> Extend<(K, V)> for DequeBTreeMap<K, V>
 where
-    K: fmt::Debug + Ord,
+    K: Ord + Clone,
 {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("VacantEntry")
-            .field("key", self.key())
-            .finish()
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
     }
 }
 
-/// A view into an occupied entry in a [`DequeBTreeMap`]. It is part of the [`Entry`] `enum`.
-pub struct OccupiedEntry<'a, K, V> {
-    /// The underlying occupied entry.
-    occupied: btree_map::OccupiedEntry<'a, K, V>,
+/// Builds the map with `insert`: a repeated key keeps the position of its first occurrence
+/// but takes the value of its last one. See `from_iter_last_position` for the alternative.
+impl<K, V> FromIterator<(K, V)> for DequeBTreeMap<K, V>
+where
+    K: Ord + Clone,
+{
+    fn from_iter<T>(iter: T) -> Self
+    where
+        T: IntoIterator<Item = (K, V)>,
+    {
+        let mut map = DequeBTreeMap::new();
+        map.extend(iter);
+        map
+    }
 }
 
-impl<'a, K, V> OccupiedEntry<'a, K, V>
+impl<K, V, const N: usize> From<[(K, V); N]> for DequeBTreeMap<K, V>
 where
-    K: Ord,
+    K: Ord + Clone,
 {
-    /// Gets a reference to the key in the entry.
-    pub fn key(&self) -> &K {
-        self.occupied.key()
+    fn from(items: [(K, V); N]) -> Self {
+        let mut map = DequeBTreeMap::new();
+        map.extend(items);
+        map
     }
+}
 
-    /// Gets a reference to the value in the entry.
-    pub fn get(&self) -> &V {
-        self.occupied.get()
+impl<'a, K: Ord, V> IntoIterator for &'a DequeBTreeMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
     }
+}
 
-    /// Gets a mutable reference to the value in the entry.
-    ///
-    /// If you need a reference to the `OccupiedEntry` that may outlive the
-    /// destruction of the `Entry` value, see [`into_mut`].
-    ///
-    /// [`into_mut`]: OccupiedEntry::into_mut
-    pub fn get_mut(&mut self) -> &mut V {
-        self.occupied.get_mut()
+/// Iterates like `iter_mut`, allocating a `Vec` of n references.
+impl<'a, K: Ord, V> IntoIterator for &'a mut DequeBTreeMap<K, V> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
     }
+}
 
-    /// Converts the entry into a mutable reference to its value.
-    ///
-    /// If you need multiple references to the `OccupiedEntry`, see [`get_mut`].
-    ///
-    /// [`get_mut`]: OccupiedEntry::get_mut
-    pub fn into_mut(self) -> &'a mut V {
-        self.occupied.into_mut()
+#[derive(Debug, Clone)]
+pub struct Iter<'a, K, V> {
+    inner: IndicesIter<'a, K>,
+    entries: &'a BTreeMap<K, V>,
+}
+
+impl<'a, K: Ord, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(k) = self.inner.next() {
+            self.entries.get(k).map(|v| (k, v))
+        } else {
+            None
+        }
     }
 
-    /// Sets the value of the entry with the `OccupiedEntry`’s key,
-    /// and returns the entry’s old value.
-    pub fn insert(&mut self, value: V) -> V
-    where
-        K: Clone,
-    {
-        replace(self.occupied.get_mut(), value)
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.inner.count()
+    }
+}
+
+impl<K: Ord, V> DoubleEndedIterator for Iter<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if let Some(k) = self.inner.next_back() {
+            self.entries.get(k).map(|v| (k, v))
+        } else {
+            None
+        }
+    }
+}
+
+impl<K: Ord, V> ExactSizeIterator for Iter<'_, K, V> {
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+impl<K: Ord, V> FusedIterator for Iter<'_, K, V> {}
+
+#[derive(Debug)]
+pub struct IterMut<'a, K, V> {
+    //The entries in deque order
+    inner: vec::IntoIter<(&'a K, &'a mut V)>,
+}
+
+impl<'a, K: Ord, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.inner.count()
+    }
+}
+
+impl<K: Ord, V> DoubleEndedIterator for IterMut<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+impl<K: Ord, V> ExactSizeIterator for IterMut<'_, K, V> {
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+impl<K: Ord, V> FusedIterator for IterMut<'_, K, V> {}
+
+#[derive(Debug, Clone)]
+pub struct Keys<'a, K, V> {
+    inner: Iter<'a, K, V>,
+}
+
+impl<'a, K: Ord, V> Iterator for Keys<'a, K, V> {
+    type Item = &'a K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, _)| k)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
     }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.inner.count()
+    }
+}
+
+impl<K: Ord, V> DoubleEndedIterator for Keys<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(k, _)| k)
+    }
+}
+
+impl<K: Ord, V> ExactSizeIterator for Keys<'_, K, V> {
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+impl<K: Ord, V> FusedIterator for Keys<'_, K, V> {}
+
+#[derive(Debug, Clone)]
+pub struct Values<'a, K, V> {
+    inner: Iter<'a, K, V>,
+}
+
+impl<'a, K: Ord, V> Iterator for Values<'a, K, V> {
+    type Item = &'a V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.inner.count()
+    }
+}
+
+impl<K: Ord, V> DoubleEndedIterator for Values<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(_, v)| v)
+    }
+}
+
+impl<K: Ord, V> ExactSizeIterator for Values<'_, K, V> {
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+impl<K: Ord, V> FusedIterator for Values<'_, K, V> {}
+
+#[derive(Debug)]
+pub struct ValuesMut<'a, K, V> {
+    inner: IterMut<'a, K, V>,
+}
+
+impl<'a, K: Ord, V> Iterator for ValuesMut<'a, K, V> {
+    type Item = &'a mut V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.inner.count()
+    }
+}
+
+impl<K: Ord, V> DoubleEndedIterator for ValuesMut<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(_, v)| v)
+    }
+}
+
+impl<K: Ord, V> ExactSizeIterator for ValuesMut<'_, K, V> {
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+impl<K: Ord, V> FusedIterator for ValuesMut<'_, K, V> {}
+
+pub struct IntoIter<K, V> {
+    inner: IndicesIntoIter<K>,
+    entries: BTreeMap<K, V>,
+}
+
+impl<K: Ord, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(k) = self.inner.next() {
+            self.entries.remove(&k).map(|v| (k, v))
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.inner.count()
+    }
+}
+
+impl<K: Ord, V> DoubleEndedIterator for IntoIter<K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if let Some(k) = self.inner.next_back() {
+            self.entries.remove(&k).map(|v| (k, v))
+        } else {
+            None
+        }
+    }
+}
+
+impl<K: Ord, V> ExactSizeIterator for IntoIter<K, V> {
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+impl<K: Ord, V> FusedIterator for IntoIter<K, V> {}
+
+/// The keys of a [`DequeBTreeMap`] in deque order.
+///
+/// Removing a key from the middle leaves a tombstone in its slot instead of shifting the keys
+/// behind it, and `positions` holds the slot of every key, so a key is found and removed in
+/// O(log n). Tombstones at either end are dropped right away, the others are compacted away
+/// once they outnumber the keys.
+struct Indices<K> {
+    slots: VecDeque<Option<K>>,
+    //The slot of each key offset by `head`, so pushing to the front does not shift them
+    positions: BTreeMap<K, isize>,
+    //The offset of the first slot
+    head: isize,
+    len: usize,
+}
+
+impl<K> Indices<K> {
+    //Pairs the entries with mutable values in deque order. `positions` is in key order like the
+    //entries, so each entry is placed at its slot without a lookup.
+    #[inline]
+    fn order_mut<'a, V>(&self, entries: &'a mut BTreeMap<K, V>) -> Vec<(&'a K, &'a mut V)> {
+        let mut slots: Vec<Option<(&'a K, &'a mut V)>> = Vec::new();
+        slots.resize_with(self.slots.len(), || None);
+        for ((k, v), pos) in entries.iter_mut().zip(self.positions.values()) {
+            slots[(pos - self.head) as usize] = Some((k, v));
+        }
+        slots.into_iter().flatten().collect()
+    }
+
+    #[inline]
+    fn new() -> Self {
+        Self {
+            slots: VecDeque::new(),
+            positions: BTreeMap::new(),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    #[inline]
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: VecDeque::with_capacity(capacity),
+            ..Self::new()
+        }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    fn tombstones(&self) -> usize {
+        self.slots.len() - self.len
+    }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    #[inline]
+    fn reserve(&mut self, additional: usize) {
+        self.slots.reserve(additional);
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.slots.clear();
+        self.positions.clear();
+        self.head = 0;
+        self.len = 0;
+    }
+
+    //Takes the positions of `new` as they are, the slots only move into the current deque when
+    //it can hold them, so its allocation is kept. The positions stay valid with `new`'s head as
+    //the slots keep their order.
+    #[inline]
+    fn replace(&mut self, new: Self) {
+        if self.slots.capacity() >= new.slots.len() {
+            self.slots.clear();
+            self.slots.extend(new.slots);
+            self.positions = new.positions;
+            self.head = new.head;
+            self.len = new.len;
+        } else {
+            *self = new;
+        }
+    }
+
+    #[inline]
+    fn iter(&self) -> IndicesIter<'_, K> {
+        IndicesIter {
+            slots: &self.slots,
+            front: 0,
+            back: self.slots.len(),
+            len: self.len,
+        }
+    }
+
+    //The slots at either end are never tombstones
+    #[inline]
+    fn front(&self) -> Option<&K> {
+        self.slots.front().and_then(Option::as_ref)
+    }
+
+    #[inline]
+    fn back(&self) -> Option<&K> {
+        self.slots.back().and_then(Option::as_ref)
+    }
+
+    /// Returns the slot of the key at `index` in deque order, scanning the deque if there are
+    /// tombstones.
+    #[inline]
+    fn slot_at(&self, index: usize) -> Option<usize> {
+        if index >= self.len {
+            None
+        } else if self.tombstones() == 0 {
+            Some(index)
+        } else {
+            self.slots
+                .iter()
+                .enumerate()
+                .filter(|(_, k)| k.is_some())
+                .nth(index)
+                .map(|(slot, _)| slot)
+        }
+    }
+
+    #[inline]
+    fn get(&self, index: usize) -> Option<&K> {
+        self.slots[self.slot_at(index)?].as_ref()
+    }
+}
+
+impl<K: Ord> Indices<K> {
+    #[inline]
+    fn slot<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.positions.get(k).map(|pos| (pos - self.head) as usize)
+    }
+
+    /// Returns the position of `k` in deque order, scanning the deque if there are tombstones.
+    #[inline]
+    fn position<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let slot = self.slot(k)?;
+        if self.tombstones() == 0 {
+            Some(slot)
+        } else {
+            Some(self.slots.range(..slot).filter(|s| s.is_some()).count())
+        }
+    }
+
+    #[inline]
+    fn pop_front(&mut self) -> Option<K> {
+        let key = self.slots.pop_front()??;
+        self.head += 1;
+        self.positions.remove(&key);
+        self.len -= 1;
+        self.trim();
+        Some(key)
+    }
+
+    #[inline]
+    fn pop_back(&mut self) -> Option<K> {
+        let key = self.slots.pop_back()??;
+        self.positions.remove(&key);
+        self.len -= 1;
+        self.trim();
+        Some(key)
+    }
+
+    #[inline]
+    fn remove<Q>(&mut self, k: &Q) -> Option<K>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let pos = self.positions.remove(k)?;
+        let key = self.slots[(pos - self.head) as usize].take();
+        self.len -= 1;
+        self.trim();
+        key
+    }
+
+    #[inline]
+    fn remove_at(&mut self, index: usize) -> Option<K> {
+        let slot = self.slot_at(index)?;
+        let key = self.slots[slot].take()?;
+        self.positions.remove(&key);
+        self.len -= 1;
+        self.trim();
+        Some(key)
+    }
+
+    #[inline]
+    fn move_to_back<Q>(&mut self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let Some(pos) = self.positions.get_mut(k) else {
+            return false;
+        };
+        let slot = (*pos - self.head) as usize;
+        if slot + 1 == self.slots.len() {
+            return true;
+        }
+        *pos = self.head + self.slots.len() as isize;
+        let key = self.slots[slot].take();
+        self.slots.push_back(key);
+        self.trim();
+        true
+    }
+
+    #[inline]
+    fn move_to_front<Q>(&mut self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let Some(pos) = self.positions.get_mut(k) else {
+            return false;
+        };
+        let slot = (*pos - self.head) as usize;
+        if slot == 0 {
+            return true;
+        }
+        self.head -= 1;
+        *pos = self.head;
+        let key = self.slots[slot].take();
+        self.slots.push_front(key);
+        self.trim();
+        true
+    }
+
+    #[inline]
+    fn swap(&mut self, a: usize, b: usize) {
+        let a = self.slot_at(a).expect("DequeBTreeMap: index out of bounds");
+        let b = self.slot_at(b).expect("DequeBTreeMap: index out of bounds");
+        self.slots.swap(a, b);
+        for slot in [a, b] {
+            if let Some(pos) = self.slots[slot]
+                .as_ref()
+                .and_then(|k| self.positions.get_mut(k))
+            {
+                *pos = self.head + slot as isize;
+            }
+        }
+    }
+
+    //Moves the keys across the ends of the deque, in whichever direction is shorter
+    #[inline]
+    fn rotate_left(&mut self, mid: usize) {
+        assert!(mid <= self.len, "DequeBTreeMap: rotate index out of bounds");
+        if mid <= self.len - mid {
+            for _ in 0..mid {
+                let key = self.slots.pop_front().flatten();
+                self.head += 1;
+                if let Some(pos) = key.as_ref().and_then(|k| self.positions.get_mut(k)) {
+                    *pos = self.head + self.slots.len() as isize;
+                }
+                self.slots.push_back(key);
+                self.trim();
+            }
+        } else {
+            for _ in 0..self.len - mid {
+                let key = self.slots.pop_back().flatten();
+                self.head -= 1;
+                if let Some(pos) = key.as_ref().and_then(|k| self.positions.get_mut(k)) {
+                    *pos = self.head;
+                }
+                self.slots.push_front(key);
+                self.trim();
+            }
+        }
+    }
+
+    #[inline]
+    fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K) -> bool,
+    {
+        let positions = &mut self.positions;
+        self.slots.retain(|slot| match slot {
+            Some(k) if f(k) => true,
+            Some(k) => {
+                positions.remove(k);
+                false
+            }
+            None => false,
+        });
+        self.len = self.slots.len();
+        self.renumber();
+    }
+
+    #[inline]
+    fn sort_by<F>(&mut self, cmp: F)
+    where
+        F: FnMut(&K, &K) -> Ordering,
+    {
+        let mut keys: Vec<K> = self.slots.drain(..).flatten().collect();
+        keys.sort_by(cmp);
+        self.slots.extend(keys.into_iter().map(Some));
+        self.renumber();
+    }
+
+    #[inline]
+    fn shrink_to_fit(&mut self) {
+        if self.tombstones() > 0 {
+            self.compact();
+        }
+        self.slots.shrink_to_fit();
+    }
+
+    //Drops the tombstones at either end, and all of them once they outnumber the keys
+    #[inline]
+    fn trim(&mut self) {
+        while let Some(None) = self.slots.front() {
+            self.slots.pop_front();
+            self.head += 1;
+        }
+        while let Some(None) = self.slots.back() {
+            self.slots.pop_back();
+        }
+        if self.tombstones() > self.len {
+            self.compact();
+        }
+    }
+
+    #[inline]
+    fn compact(&mut self) {
+        self.slots.retain(Option::is_some);
+        self.renumber();
+    }
+
+    //Resets the positions to the slots, which must hold no tombstones
+    #[inline]
+    fn renumber(&mut self) {
+        self.head = 0;
+        for (slot, k) in self.slots.iter().flatten().enumerate() {
+            if let Some(pos) = self.positions.get_mut(k) {
+                *pos = slot as isize;
+            }
+        }
+    }
+
+    //Asserts the invariants that hold without looking at the entries, for the entry types which
+    //borrow them. Like `DequeBTreeMap::check_invariants` it compiles to nothing without the
+    //`debug-invariants` feature.
+    #[inline]
+    fn check_invariants(&self, _entries_len: usize) {
+        #[cfg(feature = "debug-invariants")]
+        {
+            debug_assert_eq!(
+                self.len, _entries_len,
+                "DequeBTreeMap: entries and indices out of sync"
+            );
+            debug_assert!(
+                self.is_consistent(),
+                "DequeBTreeMap: key positions out of sync"
+            );
+        }
+    }
+
+    fn is_consistent(&self) -> bool {
+        self.positions.len() == self.len
+            && self.slots.iter().flatten().count() == self.len
+            && self.slots.front().map_or(true, Option::is_some)
+            && self.slots.back().map_or(true, Option::is_some)
+            && self.slots.iter().enumerate().all(|(slot, k)| {
+                k.as_ref().map_or(true, |k| {
+                    self.positions.get(k) == Some(&(self.head + slot as isize))
+                })
+            })
+    }
+}
+
+impl<K: Clone + Ord> Indices<K> {
+    //Both the deque and the positions are built in bulk from keys in increasing order
+    #[inline]
+    fn from_sorted<'a, I>(keys: I) -> Self
+    where
+        K: 'a,
+        I: Iterator<Item = &'a K>,
+    {
+        let slots: VecDeque<Option<K>> = keys.map(|k| Some(k.clone())).collect();
+        let positions = slots
+            .iter()
+            .flatten()
+            .enumerate()
+            .map(|(slot, k)| (k.clone(), slot as isize))
+            .collect();
+        Self {
+            len: slots.len(),
+            slots,
+            positions,
+            head: 0,
+        }
+    }
+
+    #[inline]
+    fn push_back(&mut self, key: K) {
+        let pos = self.head + self.slots.len() as isize;
+        self.positions.insert(key.clone(), pos);
+        self.slots.push_back(Some(key));
+        self.len += 1;
+    }
+
+    #[inline]
+    fn push_front(&mut self, key: K) {
+        self.head -= 1;
+        self.positions.insert(key.clone(), self.head);
+        self.slots.push_front(Some(key));
+        self.len += 1;
+    }
+
+    //The positions are kept in key order, so the deque is refilled from them
+    #[inline]
+    fn sort(&mut self) {
+        self.slots.clear();
+        self.slots.extend(self.positions.keys().cloned().map(Some));
+        self.head = 0;
+        for (slot, pos) in self.positions.values_mut().enumerate() {
+            *pos = slot as isize;
+        }
+    }
+
+    //Puts `new` in the slot of `old`, which must be in the deque while `new` is not
+    #[inline]
+    fn rename(&mut self, old: &K, new: K) {
+        if let Some(pos) = self.positions.remove(old) {
+            self.slots[(pos - self.head) as usize] = Some(new.clone());
+            self.positions.insert(new, pos);
+        }
+    }
+
+    //Inserts `key` in front of the one in `slot`, renumbering the shorter side of the deque
+    #[inline]
+    fn insert_at_slot(&mut self, slot: usize, key: K) {
+        self.positions.insert(key.clone(), 0);
+        self.slots.insert(slot, Some(key));
+        self.len += 1;
+        let (start, end) = if slot < self.slots.len() - slot {
+            self.head -= 1;
+            (0, slot + 1)
+        } else {
+            (slot, self.slots.len())
+        };
+        for (slot, k) in self.slots.range(start..end).enumerate() {
+            if let Some(pos) = k.as_ref().and_then(|k| self.positions.get_mut(k)) {
+                *pos = self.head + (start + slot) as isize;
+            }
+        }
+    }
+}
+
+impl<K: Clone> Clone for Indices<K> {
+    fn clone(&self) -> Self {
+        Self {
+            slots: self.slots.clone(),
+            positions: self.positions.clone(),
+            head: self.head,
+            len: self.len,
+        }
+    }
+}
+
+//Indices compare and print as the keys in deque order, regardless of tombstones
+impl<K: PartialEq> PartialEq for Indices<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Eq> Eq for Indices<K> {}
+
+impl<K: PartialOrd> PartialOrd for Indices<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<K: Ord> Ord for Indices<K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<K: fmt::Debug> fmt::Debug for Indices<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Clone + Ord> Extend<K> for Indices<K> {
+    fn extend<I: IntoIterator<Item = K>>(&mut self, iter: I) {
+        for key in iter {
+            self.push_back(key);
+        }
+    }
+}
+
+impl<K: Clone + Ord> FromIterator<K> for Indices<K> {
+    fn from_iter<I: IntoIterator<Item = K>>(iter: I) -> Self {
+        let mut indices = Self::new();
+        indices.extend(iter);
+        indices
+    }
+}
+
+impl<K> IntoIterator for Indices<K> {
+    type Item = K;
+    type IntoIter = IndicesIntoIter<K>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IndicesIntoIter {
+            inner: self.slots.into_iter(),
+            len: self.len,
+        }
+    }
+}
+
+struct IndicesIter<'a, K> {
+    slots: &'a VecDeque<Option<K>>,
+    front: usize,
+    back: usize,
+    len: usize,
+}
+
+impl<'a, K> IndicesIter<'a, K> {
+    /// Splits off an iterator over the next `n` keys, or fewer if there are not that many left,
+    /// and advances past them.
+    #[inline]
+    fn split_front(&mut self, n: usize) -> Self {
+        let n = n.min(self.len);
+        let start = self.front;
+        if self.back - self.front == self.len {
+            //No tombstones left
+            self.front += n;
+        } else {
+            let mut taken = 0;
+            while taken < n {
+                if self.slots[self.front].is_some() {
+                    taken += 1;
+                }
+                self.front += 1;
+            }
+        }
+        self.len -= n;
+        Self {
+            slots: self.slots,
+            front: start,
+            back: self.front,
+            len: n,
+        }
+    }
+}
+
+impl<K> Clone for IndicesIter<'_, K> {
+    fn clone(&self) -> Self {
+        Self {
+            slots: self.slots,
+            front: self.front,
+            back: self.back,
+            len: self.len,
+        }
+    }
+}
+
+impl<K: fmt::Debug> fmt::Debug for IndicesIter<'_, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+impl<'a, K> Iterator for IndicesIter<'a, K> {
+    type Item = &'a K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let slots = self.slots;
+        while self.front < self.back {
+            self.front += 1;
+            if let Some(k) = &slots[self.front - 1] {
+                self.len -= 1;
+                return Some(k);
+            }
+        }
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K> DoubleEndedIterator for IndicesIter<'_, K> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let slots = self.slots;
+        while self.front < self.back {
+            self.back -= 1;
+            if let Some(k) = &slots[self.back] {
+                self.len -= 1;
+                return Some(k);
+            }
+        }
+        None
+    }
+}
+
+impl<K> ExactSizeIterator for IndicesIter<'_, K> {}
+
+impl<K> FusedIterator for IndicesIter<'_, K> {}
+
+struct IndicesIntoIter<K> {
+    inner: DequeIntoIter<Option<K>>,
+    len: usize,
+}
+
+impl<K> Iterator for IndicesIntoIter<K> {
+    type Item = K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.inner.by_ref().flatten().next()?;
+        self.len -= 1;
+        Some(key)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K> DoubleEndedIterator for IndicesIntoIter<K> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let key = self.inner.by_ref().rev().flatten().next()?;
+        self.len -= 1;
+        Some(key)
+    }
+}
+
+impl<K> ExactSizeIterator for IndicesIntoIter<K> {}
+
+impl<K> FusedIterator for IndicesIntoIter<K> {}
+
+/// A view into a single entry in a map, which may either be vacant or occupied.
+///
+/// This `enum` is constructed from the [`entry`] method on [`DequeBTreeMap`].
+///
+/// [`entry`]: DequeBTreeMap::entry
+pub enum Entry<'a, K, V> {
+    /// A vacant entry.
+    Vacant(VacantEntry<'a, K, V>),
+    /// An occupied entry.
+    Occupied(OccupiedEntry<'a, K, V>),
+}
+
+impl<'a, K: Ord, V> Entry<'a, K, V> {
+    /// Ensures a value is in the entry by inserting the default if empty,
+    /// and returns a mutable reference to the value in the entry.
+    pub fn or_insert(self, default: V) -> &'a mut V
+    where
+        K: Clone,
+    {
+        match self {
+            Self::Occupied(entry) => entry.into_mut(),
+            Self::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the result
+    /// of the default function if empty,
+    /// and returns a mutable reference to the value in the entry.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V
+    where
+        K: Clone,
+    {
+        match self {
+            Self::Occupied(entry) => entry.into_mut(),
+            Self::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting,
+    /// if empty, the result of the default function.
+    ///
+    /// This method allows for generating key-derived values for
+    /// insertion by providing the default function a reference
+    /// to the key that was moved during the `.entry(key)` method call.
+    ///
+    /// The reference to the moved key is provided
+    /// so that cloning or copying the key is
+    /// unnecessary, unlike with `.or_insert_with(|| ... )`.
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V
+    where
+        K: Clone,
+    {
+        match self {
+            Self::Occupied(entry) => entry.into_mut(),
+            Self::Vacant(entry) => {
+                let value = default(entry.key());
+                entry.insert(value)
+            }
+        }
+    }
+
+    /// Returns a reference to this entry’s key.
+    pub fn key(&self) -> &K {
+        match *self {
+            Self::Occupied(ref entry) => entry.key(),
+            Self::Vacant(ref entry) => entry.key(),
+        }
+    }
+
+    /// Provides in-place mutable access to an occupied entry
+    /// before any potential inserts into the map.
+    pub fn and_modify<F>(self, f: F) -> Self
+    where
+        F: FnOnce(&mut V),
+    {
+        match self {
+            Self::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Self::Occupied(entry)
+            }
+            Self::Vacant(entry) => Self::Vacant(entry),
+        }
+    }
+}
+
+impl<'a, K, V> Entry<'a, K, V>
+where
+    K: Ord + Clone,
+    V: Default,
+{
+    /// Ensures a value is in the entry by inserting the default value if empty,
+    /// and returns a mutable reference to the value in the entry.
+    pub fn or_default(self) -> &'a mut V {
+        match self {
+            Self::Occupied(entry) => entry.into_mut(),
+            Self::Vacant(entry) => entry.insert(Default::default()),
+        }
+    }
+}
+
+impl<K, V> fmt::Debug for Entry<'_, K, V>
+where
+    K: fmt::Debug + Ord,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Vacant(entry) => entry.fmt(f),
+            Entry::Occupied(entry) => entry.fmt(f),
+        }
+    }
+}
+
+/// A view into a vacant entry in an [`DequeBTreeMap`]. It is part of the [`Entry`] `enum`.
+pub struct VacantEntry<'a, K, V> {
+    /// The underlying vacant entry.
+    vacant: btree_map::VacantEntry<'a, K, V>,
+    /// The vector that stores all slots.
+    indices: &'a mut Indices<K>,
+    /// Whether the key is inserted at the front of the deque rather than the back.
+    front: bool,
+    /// The number of entries in the map, the entries are borrowed by `vacant`.
+    len: usize,
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V>
+where
+    K: Ord,
+{
+    /// Gets a reference to the key that would be used when inserting a value through the VacantEntry.
+    pub fn key(&self) -> &K {
+        self.vacant.key()
+    }
+
+    /// Take ownership of the key.
+    pub fn into_key(self) -> K {
+        self.vacant.into_key()
+    }
+
+    /// Sets the value of the entry with the `VacantEntry`’s key,
+    /// and returns a mutable reference to it.
+    pub fn insert(self, value: V) -> &'a mut V
+    where
+        K: Clone,
+    {
+        let key = self.vacant.key().clone();
+        if self.front {
+            self.indices.push_front(key);
+        } else {
+            self.indices.push_back(key);
+        }
+        self.indices.check_invariants(self.len + 1);
+        self.vacant.insert(value)
+    }
+}
+
+impl<K, V> fmt::Debug for VacantEntry<'_, K, V>
+where
+    K: fmt::Debug + Ord,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VacantEntry")
+            .field("key", self.key())
+            .finish()
+    }
+}
+
+/// A view into an occupied entry in a [`DequeBTreeMap`]. It is part of the [`Entry`] `enum`.
+pub struct OccupiedEntry<'a, K, V> {
+    /// The underlying occupied entry.
+    occupied: btree_map::OccupiedEntry<'a, K, V>,
+    /// The deque order of the map.
+    indices: &'a mut Indices<K>,
+    /// The number of entries in the map, the entries are borrowed by `occupied`.
+    len: usize,
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V>
+where
+    K: Ord,
+{
+    /// Gets a reference to the key in the entry.
+    pub fn key(&self) -> &K {
+        self.occupied.key()
+    }
+
+    /// Gets a reference to the value in the entry.
+    pub fn get(&self) -> &V {
+        self.occupied.get()
+    }
+
+    /// Gets a mutable reference to the value in the entry.
+    ///
+    /// If you need a reference to the `OccupiedEntry` that may outlive the
+    /// destruction of the `Entry` value, see [`into_mut`].
+    ///
+    /// [`into_mut`]: OccupiedEntry::into_mut
+    pub fn get_mut(&mut self) -> &mut V {
+        self.occupied.get_mut()
+    }
+
+    /// Converts the entry into a mutable reference to its value.
+    ///
+    /// If you need multiple references to the `OccupiedEntry`, see [`get_mut`].
+    ///
+    /// [`get_mut`]: OccupiedEntry::get_mut
+    pub fn into_mut(self) -> &'a mut V {
+        self.occupied.into_mut()
+    }
+
+    /// Sets the value of the entry with the `OccupiedEntry`’s key,
+    /// and returns the entry’s old value.
+    pub fn insert(&mut self, value: V) -> V
+    where
+        K: Clone,
+    {
+        replace(self.occupied.get_mut(), value)
+    }
+
+    /// Returns the position of the entry's key in the deque.
+    pub fn position(&self) -> usize {
+        self.indices
+            .position(self.occupied.key())
+            .expect("an occupied key is in the deque")
+    }
+
+    /// Moves the entry's key to the back of the deque.
+    pub fn move_to_back(&mut self) {
+        self.indices.move_to_back(self.occupied.key());
+        self.indices.check_invariants(self.len);
+    }
+
+    /// Moves the entry's key to the front of the deque.
+    pub fn move_to_front(&mut self) {
+        self.indices.move_to_front(self.occupied.key());
+        self.indices.check_invariants(self.len);
+    }
+}
+
+impl<K, V> fmt::Debug for OccupiedEntry<'_, K, V>
+where
+    K: fmt::Debug + Ord,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OccupiedEntry")
+            .field("key", self.key())
+            .field("value", self.get())
+            .finish()
+    }
+}
+
+/// The error returned by [`DequeBTreeMap::replace_key`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenameError {
+    /// The key to rename is not in the map.
+    KeyNotFound,
+    /// The new key is already in the map.
+    KeyExists,
+}
+
+impl fmt::Display for RenameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenameError::KeyNotFound => write!(f, "the key to rename is not in the map"),
+            RenameError::KeyExists => write!(f, "the new key is already in the map"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RenameError {}
+
+/// The error returned by [`DequeBTreeMap::try_insert`] when the key is already in the map.
+///
+/// Holds the occupied entry, which is left unchanged, and the value that was not inserted.
+pub struct OccupiedError<'a, K, V> {
+    entry: OccupiedEntry<'a, K, V>,
+    value: V,
+}
+
+impl<'a, K, V> OccupiedError<'a, K, V>
+where
+    K: Ord,
+{
+    /// Gets a reference to the value already in the map.
+    pub fn existing(&self) -> &V {
+        self.entry.get()
+    }
+
+    /// Gets a reference to the value that was not inserted.
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    /// Takes the occupied entry, e.g. to update the existing value after all.
+    pub fn into_entry(self) -> OccupiedEntry<'a, K, V> {
+        self.entry
+    }
+
+    /// Takes back the value that was not inserted.
+    pub fn into_value(self) -> V {
+        self.value
+    }
+}
+
+impl<K, V> fmt::Debug for OccupiedError<'_, K, V>
+where
+    K: fmt::Debug + Ord,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OccupiedError")
+            .field("key", self.entry.key())
+            .field("old_value", self.existing())
+            .field("new_value", self.value())
+            .finish()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K, V> serde::ser::Serialize for DequeBTreeMap<K, V>
+where
+    K: serde::ser::Serialize + Ord,
+    V: serde::ser::Serialize,
+{
+    fn serialize<T>(&self, serializer: T) -> Result<T::Ok, T::Error>
+    where
+        T: serde::ser::Serializer,
+    {
+        serializer.collect_map(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+struct DequeBTreeMapVisitor<K, V>(core::marker::PhantomData<(K, V)>);
+
+#[cfg(feature = "serde")]
+impl<'de, K, V> serde::de::Visitor<'de> for DequeBTreeMapVisitor<K, V>
+where
+    K: serde::de::Deserialize<'de> + Ord + Clone,
+    V: serde::de::Deserialize<'de>,
+{
+    type Value = DequeBTreeMap<K, V>;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(formatter, "a map")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        let mut values = DequeBTreeMap::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some((key, value)) = map.next_entry()? {
+            values.insert(key, value);
+        }
+        Ok(values)
+    }
+}
+
+/// Requires crate feature `"serde"`
+#[cfg(feature = "serde")]
+impl<'de, K, V> serde::de::Deserialize<'de> for DequeBTreeMap<K, V>
+where
+    K: serde::de::Deserialize<'de> + Ord + Clone,
+    V: serde::de::Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(DequeBTreeMapVisitor(core::marker::PhantomData))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, V, E> serde::de::IntoDeserializer<'de, E> for DequeBTreeMap<K, V>
+where
+    K: serde::de::IntoDeserializer<'de, E> + Ord,
+    V: serde::de::IntoDeserializer<'de, E>,
+    E: serde::de::Error,
+{
+    type Deserializer = serde::de::value::MapDeserializer<'de, <Self as IntoIterator>::IntoIter, E>;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        serde::de::value::MapDeserializer::new(self.into_iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_dequebtreemap_serde() {
+    use alloc::vec::Vec;
+    let to_vec = |map: &DequeBTreeMap<i32, i32>| {
+        map.iter()
+            .map(|t| (*t.0, *t.1))
+            .collect::<Vec<(i32, i32)>>()
+    };
+
+    let mut map = DequeBTreeMap::new();
+    map.push_back(2, 20);
+    map.push_back(1, 10);
+    map.push_back(9, 90);
+    map.push_back(3, 30);
+    map.push_back(5, 50);
+
+    assert_eq!(to_vec(&map), [(2, 20), (1, 10), (9, 90), (3, 30), (5, 50)]);
+
+    let data = bincode::serialize(&map).unwrap();
+    let map: DequeBTreeMap<i32, i32> = bincode::deserialize(&data).unwrap();
+    assert_eq!(to_vec(&map), [(2, 20), (1, 10), (9, 90), (3, 30), (5, 50)]);
+}
+
+#[test]
+fn test_insert() {
+    use alloc::vec::Vec;
+    let to_vec = |map: &DequeBTreeMap<i32, i32>| {
+        map.iter()
+            .map(|t| (*t.0, *t.1))
+            .collect::<Vec<(i32, i32)>>()
+    };
+
+    let mut map = DequeBTreeMap::new();
+    map.insert(2, 20);
+    map.insert(1, 10);
+    map.insert(9, 90);
+    assert_eq!(to_vec(&map), [(2, 20), (1, 10), (9, 90)]);
+
+    map.insert(7, 70);
+    map.insert(1, 100);
+    assert_eq!(to_vec(&map), [(2, 20), (1, 100), (9, 90), (7, 70)]);
+
+    assert_eq!(map.validate(), Ok(()));
+
+    assert_eq!(map.pop_front(), Some((2, 20)));
+    assert_eq!(map.pop_back(), Some((7, 70)));
+    assert_eq!(to_vec(&map), [(1, 100), (9, 90)]);
+
+    map.insert(3, 30);
+    map.insert(7, 70);
+    map.insert(9, 900);
+    map.push_back(1, 10);
+    assert_eq!(to_vec(&map), [(9, 900), (3, 30), (7, 70), (1, 10)]);
+    assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+fn test_entry() {
+    use alloc::vec::Vec;
+    let to_vec = |map: &DequeBTreeMap<i32, i32>| {
+        map.iter()
+            .map(|t| (*t.0, *t.1))
+            .collect::<Vec<(i32, i32)>>()
+    };
+
+    let mut map = DequeBTreeMap::new();
+    map.entry(2).or_insert(20);
+    map.entry(1).or_insert(10);
+    map.entry(9).or_insert(90);
+    map.entry(3).or_insert(30);
+    map.entry(5).or_insert(50);
+    assert_eq!(map.get(&1), Some(&10));
+    assert_eq!(map.get(&2), Some(&20));
+    assert_eq!(map.get(&3), Some(&30));
+    assert_eq!(map.get(&5), Some(&50));
+    assert_eq!(map.get(&9), Some(&90));
+
+    assert_eq!(to_vec(&map), [(2, 20), (1, 10), (9, 90), (3, 30), (5, 50)]);
+    assert_eq!(map.validate(), Ok(()));
+
+    map.entry(3).and_modify(|v| *v = 300);
+
+    assert_eq!(to_vec(&map), [(2, 20), (1, 10), (9, 90), (3, 300), (5, 50)]);
+    assert_eq!(map.validate(), Ok(()));
+
+    map.entry(7).or_insert_with(|| 70);
+    assert_eq!(
+        to_vec(&map),
+        [(2, 20), (1, 10), (9, 90), (3, 300), (5, 50), (7, 70)]
+    );
+    assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+fn test_dequemap() {
+    use alloc::vec::Vec;
+    let to_vec = |map: &DequeBTreeMap<i32, i32>| {
+        map.iter()
+            .map(|t| (*t.0, *t.1))
+            .collect::<Vec<(i32, i32)>>()
+    };
+
+    let mut map = DequeBTreeMap::new();
+    map.push_back(2, 20);
+    map.push_back(1, 10);
+    map.push_back(9, 90);
+    map.push_back(3, 30);
+    map.push_back(5, 50);
+    assert_eq!(map.get(&1), Some(&10));
+    assert_eq!(map.get(&2), Some(&20));
+    assert_eq!(map.get(&3), Some(&30));
+    assert_eq!(map.get(&5), Some(&50));
+    assert_eq!(map.get(&9), Some(&90));
+    assert_eq!(map.len(), 5);
+    assert_eq!(map.pop_front(), Some((2, 20)));
+    assert_eq!(map.len(), 4);
+    assert_eq!(map.pop_back(), Some((5, 50)));
+    assert_eq!(map.len(), 3);
+    assert_eq!(to_vec(&map), [(1, 10), (9, 90), (3, 30)]);
+    assert_eq!(map.validate(), Ok(()));
+
+    let mut map1: DequeBTreeMap<i32, i32> = DequeBTreeMap::new();
+    map1.push_back(7, 70);
+    map1.push_back(9, 900);
+    map.extend(map1);
+    assert_eq!(to_vec(&map), [(1, 10), (9, 900), (3, 30), (7, 70)]);
+    assert_eq!(map.validate(), Ok(()));
+
+    assert_eq!(map.front(), Some((&1, &10)));
+    assert_eq!(map.back(), Some((&7, &70)));
+
+    assert_eq!(to_vec(&map), [(1, 10), (9, 900), (3, 30), (7, 70)]);
+    assert_eq!(map.validate(), Ok(()));
+
+    map.remove(&3);
+    assert_eq!(to_vec(&map), [(1, 10), (9, 900), (7, 70)]);
+    assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+fn test_dequemap_index() {
+    let mut map = DequeBTreeMap::new();
+    map.push_back(2, 20);
+    map.push_back(1, 10);
+    map.push_back(9, 90);
+    assert_eq!(map.index_mut(1), &mut 10);
+    assert_eq!(map.index(2), &90);
+}
+
+#[test]
+fn test_dequemap_extend() {
+    use alloc::vec::Vec;
+    let to_vec = |map: &DequeBTreeMap<i32, i32>| {
+        map.iter()
+            .map(|t| (*t.0, *t.1))
+            .collect::<Vec<(i32, i32)>>()
+    };
+    let mut map = DequeBTreeMap::new();
+    map.push_back(2, 20);
+    map.push_back(1, 10);
+    map.push_back(9, 90);
+    map.extend([(10, 100), (5, 50)]);
+    assert_eq!(
+        to_vec(&map),
+        [(2, 20), (1, 10), (9, 90), (10, 100), (5, 50)]
+    );
+    assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+fn test_dequemap_retain() {
+    let mut map = DequeBTreeMap::new();
+    map.push_back(2, 20);
+    map.push_back(1, 10);
+    map.push_back(9, 90);
+    map.extend([(10, 100), (5, 50)]);
+
+    assert_eq!(map.validate(), Ok(()));
+    assert_eq!(map.entries.len(), 5);
+
+    map.retain(|k, _| *k != 10 && *k != 2);
+
+    assert_eq!(map.validate(), Ok(()));
+    assert_eq!(map.entries.len(), 3);
+}
+
+#[cfg(feature = "debug-invariants")]
+#[test]
+fn test_dequemap_invariants() {
+    //xorshift, keeps the sequence reproducible without pulling in a rand dependency
+    let mut seed: u32 = 0x9E37_79B9;
+    let mut next = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+
+    let mut map = DequeBTreeMap::new();
+    for _ in 0..10_000 {
+        let key = next() % 64;
+        match next() % 9 {
+            0 => {
+                map.insert(key, key);
+            }
+            6 => {
+                map.entry_front(key).or_insert(key);
+            }
+            7 => {
+                if let Entry::Occupied(mut entry) = map.entry(key) {
+                    entry.move_to_back();
+                }
+            }
+            8 => {
+                if let Entry::Occupied(mut entry) = map.entry(key) {
+                    entry.move_to_front();
+                }
+            }
+            1 => {
+                map.push_back(key, key);
+            }
+            2 => {
+                map.push_front(key, key);
+            }
+            3 => {
+                map.remove(&key);
+            }
+            4 => {
+                map.pop_front();
+            }
+            _ => {
+                map.pop_back();
+            }
+        }
+        map.check_invariants();
+    }
+    map.retain(|k, _| k % 2 == 0);
+    map.check_invariants();
+    assert_eq!(map.validate(), Ok(()));
+    map.clear();
+    assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+fn test_dequemap_heap_size() {
+    let mut map: DequeBTreeMap<u64, u64> = DequeBTreeMap::new();
+    assert_eq!(map.heap_size(), 0);
+    let mut last = 0;
+    for i in 0..1000 {
+        map.push_back(i, i);
+        let size = map.heap_size();
+        assert!(size >= last);
+        last = size;
+    }
+    assert!(last >= 1000 * 2 * size_of::<u64>());
+    map.clear();
+    map.shrink_to_fit();
+    assert_eq!(map.heap_size(), 0);
+}
+
+#[test]
+fn test_dequemap_cmp_by_sequence() {
+    let mut a = DequeBTreeMap::new();
+    a.push_back(2, 0);
+    a.push_back(1, 0);
+    let mut b = DequeBTreeMap::new();
+    b.push_back(1, 0);
+    b.push_back(3, 0);
+
+    assert_eq!(a.cmp(&b), Ordering::Less);
+    assert_eq!(a.cmp_by_sequence(&b), Ordering::Greater);
+    assert_eq!(a.cmp_by_sequence(&a.clone()), Ordering::Equal);
+}
+
+#[test]
+fn test_dequemap_from_iter_duplicates() {
+    use alloc::vec::Vec;
+    let items = [(1, 10), (2, 20), (1, 11), (3, 30)];
+    let to_vec =
+        |map: &DequeBTreeMap<u8, i32>| map.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+
+    let map: DequeBTreeMap<u8, i32> = items.iter().copied().collect();
+    assert_eq!(to_vec(&map), [(1, 11), (2, 20), (3, 30)]);
+
+    let map = DequeBTreeMap::from_iter_last_position(items.iter().copied());
+    assert_eq!(to_vec(&map), [(2, 20), (1, 11), (3, 30)]);
+    assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+fn test_dequemap_touch_get() {
+    use alloc::vec::Vec;
+    let keys = |map: &DequeBTreeMap<i32, i32>| map.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+
+    let mut map: DequeBTreeMap<i32, i32> = [(1, 10), (2, 20), (3, 30)].into();
+    assert_eq!(map.touch_get(&2), Some(&20));
+    assert_eq!(keys(&map), [1, 3, 2]);
+    *map.touch_get_mut(&1).unwrap() += 1;
+    assert_eq!(keys(&map), [3, 2, 1]);
+    assert_eq!(map.get(&1), Some(&11));
+
+    assert_eq!(map.touch_get(&4), None);
+    assert_eq!(keys(&map), [3, 2, 1]);
+    assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+fn test_dequemap_iter_indexed() {
+    use alloc::vec::Vec;
+    let mut map = DequeBTreeMap::new();
+    map.push_back(3, 30);
+    map.push_back(1, 10);
+    map.push_front(2, 20);
+
+    let items = map.iter_indexed().collect::<Vec<_>>();
+    assert_eq!(items, [(0, &2, &20), (1, &3, &30), (2, &1, &10)]);
+    let items = map.iter_indexed().rev().collect::<Vec<_>>();
+    assert_eq!(items, [(2, &1, &10), (1, &3, &30), (0, &2, &20)]);
+    assert_eq!(map.iter_indexed().len(), map.len());
+}
+
+#[test]
+fn test_dequemap_occupied_entry_position() {
+    use alloc::vec::Vec;
+    let keys = |map: &DequeBTreeMap<i32, i32>| map.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+
+    let mut map: DequeBTreeMap<i32, i32> = [(1, 10), (2, 20), (3, 30)].into();
+    if let Entry::Occupied(mut entry) = map.entry(2) {
+        assert_eq!(entry.position(), 1);
+        entry.move_to_back();
+        assert_eq!(entry.position(), 2);
+        *entry.get_mut() += 1;
+    } else {
+        unreachable!()
+    }
+    assert_eq!(keys(&map), [1, 3, 2]);
+    assert_eq!(map.get(&2), Some(&21));
+
+    if let Entry::Occupied(mut entry) = map.entry(3) {
+        entry.move_to_front();
+        assert_eq!(entry.position(), 0);
+    } else {
+        unreachable!()
+    }
+    assert_eq!(keys(&map), [3, 1, 2]);
+    assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+fn test_dequemap_windows_chunks() {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    let collect = |it: Iter<'_, i32, char>| it.map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+
+    let mut map = DequeBTreeMap::new();
+    for (k, v) in [(5, 'a'), (3, 'b'), (4, 'c'), (1, 'd'), (2, 'e')] {
+        map.push_back(k, v);
+    }
+
+    let windows = map.windows(2).map(collect).collect::<Vec<_>>();
+    assert_eq!(
+        windows,
+        vec![
+            vec![(5, 'a'), (3, 'b')],
+            vec![(3, 'b'), (4, 'c')],
+            vec![(4, 'c'), (1, 'd')],
+            vec![(1, 'd'), (2, 'e')],
+        ]
+    );
+    let chunks = map.chunks(2).map(collect).collect::<Vec<_>>();
+    assert_eq!(
+        chunks,
+        vec![
+            vec![(5, 'a'), (3, 'b')],
+            vec![(4, 'c'), (1, 'd')],
+            vec![(2, 'e')],
+        ]
+    );
+
+    assert_eq!(map.windows(6).count(), 0);
+    assert_eq!(map.windows(5).count(), 1);
+    assert_eq!(
+        map.chunks(6).map(collect).collect::<Vec<_>>(),
+        vec![collect(map.iter())]
+    );
+    assert_eq!(DequeBTreeMap::<i32, char>::new().chunks(2).count(), 0);
+}
+
+#[test]
+#[should_panic(expected = "window size must be non-zero")]
+fn test_dequemap_windows_zero() {
+    let map: DequeBTreeMap<i32, i32> = DequeBTreeMap::new();
+    let _ = map.windows(0);
+}
+
+#[test]
+fn test_dequemap_retain_returning() {
+    let mut map = DequeBTreeMap::new();
+    for k in [5, 2, 8, 1, 4, 7] {
+        map.push_back(k, k * 10);
+    }
+    let removeds = map.retain_returning(|k, v| {
+        *v += 1;
+        k % 2 == 0
+    });
+    assert_eq!(removeds, [(5, 51), (1, 11), (7, 71)]);
+    assert_eq!(
+        map.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+        [(2, 21), (8, 81), (4, 41)]
+    );
+    assert!(map.retain_returning(|_, _| true).is_empty());
+    assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+fn test_dequemap_first_last() {
+    let mut map = DequeBTreeMap::new();
+    assert_eq!(map.first(), None);
+    assert_eq!(map.last_mut(), None);
+    map.push_back(3, 30);
+    map.push_back(1, 10);
+    map.push_front(2, 20);
+
+    assert_eq!(map.first(), map.front().map(|(_, v)| v));
+    assert_eq!(map.last(), map.back().map(|(_, v)| v));
+    assert_eq!(map.first(), Some(&20));
+    assert_eq!(map.last(), Some(&10));
+    *map.first_mut().unwrap() += 1;
+    *map.last_mut().unwrap() += 1;
+    assert_eq!(
+        map.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+        [(2, 21), (3, 30), (1, 11)]
+    );
+}
+
+#[test]
+fn test_dequemap_to_vecdeque() {
+    let mut map = DequeBTreeMap::new();
+    map.push_back(3, "c");
+    map.push_back(1, "a");
+    map.push_front(2, "b");
+
+    let deque = map.to_vecdeque();
+    assert!(deque.iter().map(|(k, v)| (k, v)).eq(map.iter()));
+    assert_eq!(map.into_vecdeque(), deque);
+    assert_eq!(deque, [(2, "b"), (3, "c"), (1, "a")]);
+}
+
+#[test]
+fn test_dequemap_to_vec() {
+    let mut map = DequeBTreeMap::new();
+    map.push_back(3, "c");
+    map.push_back(1, "a");
+    map.push_front(2, "b");
+    map.remove(&3);
+
+    let vec = map.to_vec();
+    assert_eq!(vec, [(2, "b"), (1, "a")]);
+    assert_eq!(map.into_vec(), vec);
+}
+
+#[test]
+fn test_dequemap_keep_back_front() {
+    let map: DequeBTreeMap<_, _> = (1..=5).map(|i| (i, i * 10)).collect();
+
+    let mut back = map.clone();
+    back.keep_back(2);
+    assert_eq!(back.iter().collect::<Vec<_>>(), [(&4, &40), (&5, &50)]);
+    assert_eq!(back.validate(), Ok(()));
+
+    let mut front = map.clone();
+    front.keep_front(2);
+    assert_eq!(front.iter().collect::<Vec<_>>(), [(&1, &10), (&2, &20)]);
+    assert_eq!(front.validate(), Ok(()));
+
+    let mut all = map;
+    all.keep_back(9);
+    assert_eq!(all.len(), 5);
+    all.keep_front(0);
+    assert!(all.is_empty());
+}
+
+#[test]
+fn test_dequemap_truncate() {
+    let mut map: DequeBTreeMap<_, _> = (1..=5).map(|i| (i, i * 10)).collect();
+
+    map.truncate_back(4);
+    assert_eq!(map.back(), Some((&4, &40)));
+    map.truncate_front(2);
+    assert_eq!(map.iter().collect::<Vec<_>>(), [(&3, &30), (&4, &40)]);
+    map.truncate_back(2);
+    map.truncate_front(5);
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+fn test_dequemap_entry_push_back() {
+    let mut map: DequeBTreeMap<_, _> = [(1, 10), (2, 20), (3, 30)].into();
+
+    *map.entry_push_back(1).and_modify(|v| *v += 1).or_insert(0) *= 2;
+    assert_eq!(map.back(), Some((&1, &22)));
+    assert_eq!(map.iter().map(|(k, _)| *k).collect::<Vec<_>>(), [2, 3, 1]);
+
+    map.entry_push_back(4).and_modify(|v| *v += 1).or_insert(40);
+    assert_eq!(map.back(), Some((&4, &40)));
+    assert_eq!(
+        map.iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+        [2, 3, 1, 4]
+    );
+    assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+fn test_dequemap_iter_mut() {
+    let mut map = DequeBTreeMap::new();
+    map.push_back(3, 30);
+    map.push_back(1, 10);
+    map.push_front(2, 20);
+
+    for (k, v) in &mut map {
+        *v += *k;
+    }
+    assert_eq!(
+        map.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+        [(2, 22), (3, 33), (1, 11)]
+    );
+
+    let mut iter = map.iter_mut();
+    assert_eq!(iter.len(), 3);
+    *iter.next_back().unwrap().1 = 0;
+    assert_eq!(iter.next(), Some((&2, &mut 22)));
+    assert_eq!(iter.len(), 1);
+    assert_eq!(map.back(), Some((&1, &0)));
+
+    //Removed entries leave tombstones in the deque
+    map.push_front(9, 90);
+    map.remove(&3);
+    assert_eq!(
+        map.iter_mut().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+        [(9, 90), (2, 22), (1, 0)]
+    );
+}
+
+#[test]
+fn test_dequemap_compact() {
+    //Built directly, inserting one by one is slow with debug-invariants
+    let mut map = DequeBTreeMap {
+        entries: (0..10_000).map(|k| (k, k)).collect(),
+        indices: (0..10_000).collect(),
+    };
+    map.retain(|k, _| k % 100 == 0);
+    assert_eq!(map.len(), 100);
+    assert!(map.capacity() >= 10_000);
+
+    map.compact();
+    assert!(map.capacity() < 1_000);
+    assert_eq!(
+        map.iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+        (0..10_000).step_by(100).collect::<Vec<_>>()
+    );
+    assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+fn test_dequemap_iter_sorted() {
+    let mut map = DequeBTreeMap::new();
+    map.push_back(3, "c");
+    map.push_back(1, "a");
+    map.push_front(2, "b");
+
+    assert_eq!(map.iter().map(|(k, _)| *k).collect::<Vec<_>>(), [2, 3, 1]);
+    assert_eq!(
+        map.iter_sorted().collect::<Vec<_>>(),
+        [(&1, &"a"), (&2, &"b"), (&3, &"c")]
+    );
+    assert_eq!(map.iter_sorted().next_back(), Some((&3, &"c")));
+}
+
+#[test]
+fn test_dequemap_append_front() {
+    let mut map: DequeBTreeMap<_, _> = [(1, 10), (2, 20), (3, 30)].into();
+    let mut other: DequeBTreeMap<_, _> = [(9, 90), (2, 200)].into();
+
+    map.append_front(&mut other);
+    assert!(other.is_empty());
+    assert_eq!(
+        map.iter().collect::<Vec<_>>(),
+        [(&9, &90), (&2, &200), (&1, &10), (&3, &30)]
+    );
+    assert_eq!(map.validate(), Ok(()));
+
+    map.append_front(&mut DequeBTreeMap::new());
+    assert_eq!(map.len(), 4);
+}
+
+#[test]
+fn test_dequemap_find_position() {
+    let map: DequeBTreeMap<_, _> = [(5, "a"), (1, "b"), (3, "c"), (2, "c")].into();
+
+    assert_eq!(map.find_position(|v| *v == "b"), Some((1, &1, &"b")));
+    assert_eq!(map.find_position(|v| *v == "c"), Some((2, &3, &"c")));
+    assert_eq!(map.find_position(|v| *v == "d"), None);
+}
+
+#[test]
+fn test_dequemap_replace_contents() {
+    let mut map: DequeBTreeMap<_, _> = (0..100).map(|i| (i, i)).collect();
+    let capacity = map.capacity();
+
+    for round in 0..10 {
+        let new: DequeBTreeMap<_, _> = (0..50).rev().map(|i| (i, i + round)).collect();
+        map.replace_contents(new);
+        assert_eq!(map.capacity(), capacity);
+        assert_eq!(map.len(), 50);
+        assert_eq!(map.front(), Some((&49, &(49 + round))));
+        assert_eq!(map.back(), Some((&0, &round)));
+    }
+
+    //Positions offset by pushes to the front and tombstones move over as they are
+    let mut new = DequeBTreeMap::new();
+    for i in 0..20 {
+        new.push_front(i, i);
+        new.push_back(100 + i, i);
+    }
+    new.remove(&5);
+    new.remove(&105);
+    map.replace_contents(new);
+    assert_eq!(map.capacity(), capacity);
+    assert_eq!(map.len(), 38);
+    assert_eq!(map.get_index(0), Some((&19, &19)));
+    assert_eq!(map.get_index(37), Some((&119, &19)));
+    assert_eq!(map.get(&4), Some(&4));
+    assert_eq!(map.get(&104), Some(&4));
+    map.push_front(-1, -1);
+    assert_eq!(map.front(), Some((&-1, &-1)));
+
+    //A larger map brings its own deque
+    let new: DequeBTreeMap<_, _> = (0..capacity * 2).map(|i| (i as i32, i as i32)).collect();
+    let new_capacity = new.capacity();
+    map.replace_contents(new);
+    assert_eq!(map.capacity(), new_capacity);
+    assert_eq!(map.len(), capacity * 2);
+    assert_eq!(
+        map.back(),
+        Some((&(capacity as i32 * 2 - 1), &(capacity as i32 * 2 - 1)))
+    );
+}
+
+#[test]
+fn test_dequemap_remove_mixed() {
+    let mut map = DequeBTreeMap::new();
+    let mut expected = VecDeque::new();
+    for i in 0..200u32 {
+        let k = i * 7 % 50;
+        match i % 6 {
+            0 | 1 => {
+                map.push_back(k, i);
+                expected.retain(|x| *x != k);
+                expected.push_back(k);
+            }
+            2 => {
+                map.push_front(k, i);
+                expected.retain(|x| *x != k);
+                expected.push_front(k);
+            }
+            3 => {
+                assert_eq!(map.remove(&k).is_some(), expected.contains(&k));
+                expected.retain(|x| *x != k);
+            }
+            4 => {
+                assert_eq!(map.pop_front().map(|(k, _)| k), expected.pop_front());
+                map.touch_get(&k);
+                if let Some(idx) = expected.iter().position(|x| *x == k) {
+                    expected.remove(idx);
+                    expected.push_back(k);
+                }
+            }
+            _ => {
+                assert_eq!(map.pop_back().map(|(k, _)| k), expected.pop_back());
+            }
+        }
+        assert_eq!(map.validate(), Ok(()));
+        assert_eq!(
+            map.iter().map(|(k, _)| *k).collect::<VecDeque<_>>(),
+            expected
+        );
+        assert_eq!(map.iter().rev().count(), expected.len());
+        if let Some(mid) = expected.get(expected.len() / 2) {
+            assert_eq!(map.indices.get(expected.len() / 2), Some(mid));
+            assert_eq!(map.indices.position(mid), Some(expected.len() / 2));
+        }
+        let chunks: Vec<Vec<u32>> = map
+            .chunks(3)
+            .map(|c| c.map(|(k, _)| *k).collect())
+            .collect();
+        assert_eq!(chunks.concat(), Vec::from(expected.clone()));
+        assert!(map.windows(4).all(|w| w.len() == 4));
+    }
+
+    let mut map: DequeBTreeMap<_, _> = (0..100).map(|i| (i, i)).collect();
+    for i in (1..99).step_by(2) {
+        map.remove(&i);
+    }
+    assert_eq!(map.len(), 51);
+    assert_eq!(map.validate(), Ok(()));
+    assert!(map.indices.tombstones() <= map.len());
+    assert_eq!(map[1], 2);
+    assert_eq!(map.into_iter().next_back(), Some((99, 99)));
+}
+
+#[test]
+fn test_dequemap_get_index_of() {
+    use alloc::string::{String, ToString};
+
+    let mut map: DequeBTreeMap<String, u32> = DequeBTreeMap::new();
+    map.push_back("b".into(), 1);
+    map.push_back("c".into(), 2);
+    map.push_front("a".into(), 0);
+    map.push_back("d".into(), 3);
+
+    assert_eq!(map.get_index_of("a"), Some(0));
+    assert_eq!(map.get_index_of("d"), Some(3));
+    assert_eq!(map.get_index_of("e"), None);
+
+    map.remove(&"b".to_string());
+    assert_eq!(map.get_index_of("c"), Some(1));
+    assert_eq!(map.get_index_of("d"), Some(2));
+    map.push_back("a".into(), 0);
+    assert_eq!(map.get_index_of("a"), Some(2));
+    assert_eq!(map.get_index_of("c"), Some(0));
+}
+
+#[test]
+fn test_dequemap_keys_values() {
+    let mut map = DequeBTreeMap::new();
+    map.push_back(3, 30);
+    map.push_back(1, 10);
+    map.push_front(2, 20);
+
+    assert_eq!(map.keys().collect::<Vec<_>>(), [&2, &3, &1]);
+    assert_eq!(map.values().rev().collect::<Vec<_>>(), [&10, &30, &20]);
+    assert_eq!(map.keys().len(), 3);
+
+    for (i, v) in map.values_mut().enumerate() {
+        *v += i;
+    }
+    assert_eq!(map.values().copied().collect::<Vec<_>>(), [20, 31, 12]);
+    let mut values = map.values_mut();
+    *values.next_back().unwrap() = 0;
+    assert_eq!(values.len(), 2);
+    assert_eq!(map.get(&1), Some(&0));
+}
+
+#[test]
+fn test_dequemap_move_to_front_back() {
+    let mut map: DequeBTreeMap<_, _> = [(1, "a"), (2, "b"), (3, "c"), (4, "d")].into();
+
+    assert!(map.move_to_front(&3));
+    assert!(map.move_to_back(&1));
+    assert_eq!(map.keys().copied().collect::<Vec<_>>(), [3, 2, 4, 1]);
+    assert_eq!(map.get(&3), Some(&"c"));
+
+    assert!(map.move_to_front(&3));
+    assert!(map.move_to_back(&1));
+    assert_eq!(map.keys().copied().collect::<Vec<_>>(), [3, 2, 4, 1]);
+
+    assert!(!map.move_to_front(&5));
+    assert!(!map.move_to_back(&5));
+    assert_eq!(map.len(), 4);
+    assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+fn test_dequemap_swap_indices() {
+    let mut map: DequeBTreeMap<_, _> = [(1, "a"), (2, "b"), (3, "c"), (4, "d")].into();
+
+    map.swap_indices(0, 2);
+    assert_eq!(
+        map.iter().collect::<Vec<_>>(),
+        [(&3, &"c"), (&2, &"b"), (&1, &"a"), (&4, &"d")]
+    );
+    map.swap_indices(1, 1);
+    map.remove(&2);
+    map.swap_indices(1, 2);
+    assert_eq!(map.keys().copied().collect::<Vec<_>>(), [3, 4, 1]);
+    assert_eq!(map.get_index_of(&1), Some(2));
+    assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+#[should_panic(expected = "index out of bounds")]
+fn test_dequemap_swap_indices_out_of_bounds() {
+    let mut map: DequeBTreeMap<_, _> = [(1, "a"), (2, "b")].into();
+    map.swap_indices(0, 2);
+}
+
+#[test]
+fn test_dequemap_get_index() {
+    let mut map: DequeBTreeMap<_, _> = [(5, "a"), (1, "b"), (3, "c")].into();
+
+    assert_eq!(map.get_index(0), Some((&5, &"a")));
+    assert_eq!(map.get_index(2), Some((&3, &"c")));
+    assert_eq!(map.get_index(3), None);
+
+    if let Some((k, v)) = map.get_index_mut(1) {
+        assert_eq!(*k, 1);
+        *v = "B";
+    }
+    assert_eq!(map.get(&1), Some(&"B"));
+    assert_eq!(map.get_index_mut(3), None);
+
+    map.remove(&1);
+    assert_eq!(map.get_index(1), Some((&3, &"c")));
+    assert_eq!(map.get_index(2), None);
+}
+
+#[test]
+fn test_dequemap_remove_index() {
+    let mut map: DequeBTreeMap<_, _> = (0..6).map(|i| (i * 10, i)).collect();
+
+    assert_eq!(map.remove_index(2), Some((20, 2)));
+    assert_eq!(map.validate(), Ok(()));
+    assert_eq!(map.get_index(2), Some((&30, &3)));
+    assert_eq!(map.get_index_of(&50), Some(4));
+
+    assert_eq!(map.remove_index(0), Some((0, 0)));
+    assert_eq!(map.remove_index(3), Some((50, 5)));
+    assert_eq!(map.remove_index(3), None);
+    assert_eq!(map.keys().copied().collect::<Vec<_>>(), [10, 30, 40]);
+    assert_eq!(map.validate(), Ok(()));
+}
+
+#[test]
+fn test_dequemap_split_off() {
+    let mut map: DequeBTreeMap<_, _> = [(5, "a"), (1, "b"), (3, "c"), (2, "d")].into();
+
+    let back = map.split_off(1);
+    assert_eq!(map.iter().collect::<Vec<_>>(), [(&5, &"a")]);
+    assert_eq!(
+        back.iter().collect::<Vec<_>>(),
+        [(&1, &"b"), (&3, &"c"), (&2, &"d")]
+    );
+    assert_eq!(map.validate(), Ok(()));
+    assert_eq!(back.validate(), Ok(()));
+
+    assert!(map.split_off(1).is_empty());
+    let all = map.split_off(0);
+    assert!(map.is_empty());
+    assert_eq!(all.len(), 1);
+}
+
+#[test]
+#[should_panic(expected = "split_off index out of bounds")]
+fn test_dequemap_split_off_out_of_bounds() {
+    let mut map: DequeBTreeMap<_, _> = [(1, "a")].into();
+    map.split_off(2);
+}
+
+#[test]
+fn test_dequemap_append() {
+    let mut map: DequeBTreeMap<_, _> = [(1, 10), (2, 20), (3, 30)].into();
+    let mut other: DequeBTreeMap<_, _> = [(9, 90), (2, 200)].into();
+
+    map.append(&mut other);
+    assert!(other.is_empty());
+    assert_eq!(
+        map.iter().collect::<Vec<_>>(),
+        [(&1, &10), (&3, &30), (&9, &90), (&2, &200)]
+    );
+    assert_eq!(map.validate(), Ok(()));
+
+    map.append(&mut DequeBTreeMap::new());
+    assert_eq!(map.len(), 4);
+}
+
+#[test]
+fn test_dequemap_rotate() {
+    let mut map: DequeBTreeMap<_, _> = (0..5).map(|i| (i, i * 10)).collect();
+
+    map.rotate_left(1);
+    assert_eq!(map.front(), Some((&1, &10)));
+    assert_eq!(map.back(), Some((&0, &0)));
+    map.rotate_left(4);
+    assert_eq!(map.keys().copied().collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+
+    map.rotate_right(2);
+    assert_eq!(map.keys().copied().collect::<Vec<_>>(), [3, 4, 0, 1, 2]);
+    map.remove(&0);
+    map.rotate_right(1);
+    assert_eq!(map.keys().copied().collect::<Vec<_>>(), [2, 3, 4, 1]);
+    map.rotate_left(0);
+    map.rotate_right(4);
+    assert_eq!(map.keys().copied().collect::<Vec<_>>(), [2, 3, 4, 1]);
+    assert_eq!(map.get_index(2), Some((&4, &40)));
+    assert_eq!(map.validate(), Ok(()));
 }
 
-impl<K, V> fmt::Debug for OccupiedEntry<'_, K, V>
-where
-    K: fmt::Debug + Ord,
-    V: fmt::Debug,
-{
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("OccupiedEntry")
-            .field("key", self.key())
-            .field("value", self.get())
-            .finish()
-    }
+#[test]
+#[should_panic(expected = "rotate index out of bounds")]
+fn test_dequemap_rotate_out_of_bounds() {
+    let mut map: DequeBTreeMap<_, _> = [(1, "a")].into();
+    map.rotate_right(2);
 }
 
-#[cfg(feature = "serde")]
-impl<K, V> serde::ser::Serialize for DequeBTreeMap<K, V>
-where
-    K: serde::ser::Serialize + Ord,
-    V: serde::ser::Serialize,
-{
-    fn serialize<T>(&self, serializer: T) -> Result<T::Ok, T::Error>
-    where
-        T: serde::ser::Serializer,
-    {
-        serializer.collect_map(self)
-    }
+#[cfg(feature = "std")]
+#[test]
+fn test_dequemap_hash() {
+    use std::collections::hash_map::DefaultHasher;
+
+    fn hash_of(map: &DequeBTreeMap<i32, &str>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        map.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    let a: DequeBTreeMap<_, _> = [(1, "a"), (2, "b"), (3, "c")].into();
+    let mut b = DequeBTreeMap::new();
+    b.push_back(2, "x");
+    b.push_front(1, "a");
+    b.push_back(9, "z");
+    b.push_back(3, "c");
+    b.remove(&9);
+    b.insert(2, "b");
+    assert_eq!(a, b);
+    assert_eq!(hash_of(&a), hash_of(&b));
+
+    b.move_to_back(&1);
+    assert_ne!(a, b);
+    assert_ne!(hash_of(&a), hash_of(&b));
 }
 
-#[cfg(feature = "serde")]
-struct DequeBTreeMapVisitor<K, V>(core::marker::PhantomData<(K, V)>);
+#[test]
+fn test_dequemap_insert_before_after() {
+    let mut map: DequeBTreeMap<_, _> = (0..6).map(|i| (i, i * 10)).collect();
 
-#[cfg(feature = "serde")]
-impl<'de, K, V> serde::de::Visitor<'de> for DequeBTreeMapVisitor<K, V>
-where
-    K: serde::de::Deserialize<'de> + Ord + Clone,
-    V: serde::de::Deserialize<'de>,
-{
-    type Value = DequeBTreeMap<K, V>;
+    assert_eq!(map.insert_before(&0, 10, 100), Ok(None));
+    assert_eq!(map.insert_after(&5, 11, 110), Ok(None));
+    assert_eq!(map.insert_after(&2, 12, 120), Ok(None));
+    assert_eq!(map.insert_before(&4, 13, 130), Ok(None));
+    assert_eq!(
+        map.keys().copied().collect::<Vec<_>>(),
+        [10, 0, 1, 2, 12, 3, 13, 4, 5, 11]
+    );
 
-    fn expecting(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(formatter, "a map")
-    }
+    //An existing key moves next to the pivot
+    assert_eq!(map.insert_after(&10, 5, 55), Ok(Some(50)));
+    assert_eq!(map.insert_before(&11, 1, 15), Ok(Some(10)));
+    assert_eq!(
+        map.keys().copied().collect::<Vec<_>>(),
+        [10, 5, 0, 2, 12, 3, 13, 4, 1, 11]
+    );
+    assert_eq!(map.insert_before(&3, 3, 33), Ok(Some(30)));
+    assert_eq!(map.get_index_of(&3), Some(5));
 
-    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
-    where
-        A: serde::de::MapAccess<'de>,
-    {
-        let mut values = DequeBTreeMap::with_capacity(map.size_hint().unwrap_or(0));
-        while let Some((key, value)) = map.next_entry()? {
-            values.insert(key, value);
-        }
-        Ok(values)
-    }
+    assert_eq!(map.insert_before(&99, 7, 70), Err((7, 70)));
+    assert_eq!(map.get(&5), Some(&55));
+    assert_eq!(map.get_index(8), Some((&1, &15)));
+    assert_eq!(map.validate(), Ok(()));
 }
 
-/// Requires crate feature `"serde"`
-#[cfg(feature = "serde")]
-impl<'de, K, V> serde::de::Deserialize<'de> for DequeBTreeMap<K, V>
-where
-    K: serde::de::Deserialize<'de> + Ord + Clone,
-    V: serde::de::Deserialize<'de>,
-{
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::de::Deserializer<'de>,
-    {
-        deserializer.deserialize_map(DequeBTreeMapVisitor(core::marker::PhantomData))
+#[test]
+fn test_dequemap_sort_keys() {
+    let mut map = DequeBTreeMap::new();
+    for k in [5, 1, 9, 3, 7, 2] {
+        map.push_front(k, k * 10);
     }
+    map.remove(&9);
+
+    map.sort_keys();
+    assert!(map.iter().eq(map.iter_sorted()));
+    assert_eq!(map.keys().copied().collect::<Vec<_>>(), [1, 2, 3, 5, 7]);
+    assert_eq!(map.get_index_of(&5), Some(3));
+    assert_eq!(map.validate(), Ok(()));
 }
 
-#[cfg(feature = "serde")]
-impl<'de, K, V, E> serde::de::IntoDeserializer<'de, E> for DequeBTreeMap<K, V>
-where
-    K: serde::de::IntoDeserializer<'de, E> + Ord,
-    V: serde::de::IntoDeserializer<'de, E>,
-    E: serde::de::Error,
-{
-    type Deserializer = serde::de::value::MapDeserializer<'de, <Self as IntoIterator>::IntoIter, E>;
+#[test]
+fn test_dequemap_sort_by() {
+    let mut map: DequeBTreeMap<_, _> = [(1, 20), (2, 30), (3, 10), (4, 30), (5, 20)].into();
+    map.remove(&3);
 
-    fn into_deserializer(self) -> Self::Deserializer {
-        serde::de::value::MapDeserializer::new(self.into_iter())
-    }
+    map.sort_by(|(_, a), (_, b)| b.cmp(a));
+    assert_eq!(
+        map.iter().collect::<Vec<_>>(),
+        [(&2, &30), (&4, &30), (&1, &20), (&5, &20)]
+    );
+    assert_eq!(map.get(&5), Some(&20));
+    assert_eq!(map.get_index_of(&1), Some(2));
+    assert_eq!(map.validate(), Ok(()));
 }
 
-#[cfg(feature = "serde")]
 #[test]
-fn test_dequebtreemap_serde() {
-    use alloc::vec::Vec;
-    let to_vec = |map: &DequeBTreeMap<i32, i32>| {
-        map.iter()
-            .map(|t| (*t.0, *t.1))
-            .collect::<Vec<(i32, i32)>>()
+fn test_dequemap_retain_large() {
+    //Built directly, inserting one by one is slow with debug-invariants
+    let mut map = DequeBTreeMap {
+        entries: (0..10_000).map(|k| (k, k)).collect(),
+        indices: (0..10_000).rev().collect(),
     };
+    map.remove(&5_000);
+    map.retain(|k, _| k % 3 != 0);
 
-    let mut map = DequeBTreeMap::new();
-    map.push_back(2, 20);
-    map.push_back(1, 10);
-    map.push_back(9, 90);
-    map.push_back(3, 30);
-    map.push_back(5, 50);
+    assert_eq!(map.validate(), Ok(()));
+    assert_eq!(
+        map.keys().copied().collect::<Vec<_>>(),
+        (0..10_000)
+            .rev()
+            .filter(|k| k % 3 != 0 && *k != 5_000)
+            .collect::<Vec<_>>()
+    );
+}
 
-    assert_eq!(to_vec(&map), [(2, 20), (1, 10), (9, 90), (3, 30), (5, 50)]);
+#[test]
+fn test_dequemap_pop_if() {
+    let mut map: DequeBTreeMap<_, _> = [("a", 1), ("b", 2), ("c", 5), ("d", 9)].into();
 
-    let data = bincode::serialize(&map).unwrap();
-    let map: DequeBTreeMap<i32, i32> = bincode::deserialize(&data).unwrap();
-    assert_eq!(to_vec(&map), [(2, 20), (1, 10), (9, 90), (3, 30), (5, 50)]);
+    let mut expired = Vec::new();
+    while let Some(entry) = map.pop_front_if(|_, deadline| *deadline <= 3) {
+        expired.push(entry);
+    }
+    assert_eq!(expired, [("a", 1), ("b", 2)]);
+    assert_eq!(map.pop_back_if(|k, _| *k == "c"), None);
+    assert_eq!(map.pop_back_if(|k, _| *k == "d"), Some(("d", 9)));
+    assert_eq!(map.len(), 1);
+
+    map.clear();
+    assert_eq!(map.pop_front_if(|_, _| true), None);
+    assert_eq!(map.pop_back_if(|_, _| true), None);
 }
 
 #[test]
-fn test_insert() {
-    use alloc::vec::Vec;
-    let to_vec = |map: &DequeBTreeMap<i32, i32>| {
-        map.iter()
-            .map(|t| (*t.0, *t.1))
-            .collect::<Vec<(i32, i32)>>()
-    };
-
-    let mut map = DequeBTreeMap::new();
-    map.insert(2, 20);
-    map.insert(1, 10);
-    map.insert(9, 90);
-    assert_eq!(to_vec(&map), [(2, 20), (1, 10), (9, 90)]);
+fn test_dequemap_entry_front() {
+    let mut map: DequeBTreeMap<_, _> = [(1, 10), (2, 20)].into();
 
-    map.insert(7, 70);
-    map.insert(1, 100);
-    assert_eq!(to_vec(&map), [(2, 20), (1, 100), (9, 90), (7, 70)]);
+    *map.entry_front(3).or_insert(30) += 1;
+    *map.entry_front(2).or_insert_with(|| 0) += 1;
+    map.entry(4).or_insert(40);
+    map.entry_front(5).or_default();
+    assert_eq!(
+        map.iter().collect::<Vec<_>>(),
+        [(&5, &0), (&3, &31), (&1, &10), (&2, &21), (&4, &40)]
+    );
+    assert_eq!(map.validate(), Ok(()));
+}
 
-    assert_eq!(map.entries.len(), map.indices.len());
+#[test]
+fn test_dequemap_front_back_mut() {
+    let mut map = DequeBTreeMap::new();
+    assert_eq!(map.front_mut(), None);
+    assert_eq!(map.back_mut(), None);
 
-    assert_eq!(map.pop_front(), Some((2, 20)));
-    assert_eq!(map.pop_back(), Some((7, 70)));
-    assert_eq!(to_vec(&map), [(1, 100), (9, 90)]);
+    map.push_back("old", 1);
+    map.push_back("new", 2);
+    if let Some((k, v)) = map.back_mut() {
+        assert_eq!(*k, "new");
+        *v += 10;
+    }
+    *map.front_mut().unwrap().1 = 0;
+    assert_eq!(
+        map.iter().collect::<Vec<_>>(),
+        [(&"old", &0), (&"new", &12)]
+    );
+}
 
-    map.insert(3, 30);
-    map.insert(7, 70);
-    map.insert(9, 900);
-    map.push_back(1, 10);
-    assert_eq!(to_vec(&map), [(9, 900), (3, 30), (7, 70), (1, 10)]);
-    assert_eq!(map.entries.len(), map.indices.len());
+#[test]
+fn test_dequemap_try_insert() {
+    let mut map = DequeBTreeMap::new();
+    map.push_back(1, "a");
+
+    *map.try_insert(2, "b").unwrap() = "B";
+    let err = map.try_insert(1, "x").unwrap_err();
+    assert_eq!(err.existing(), &"a");
+    assert_eq!(err.value(), &"x");
+    assert_eq!(err.into_value(), "x");
+
+    let err = map.try_insert(2, "y").unwrap_err();
+    assert_eq!(err.into_entry().insert("z"), "B");
+    assert_eq!(map.iter().collect::<Vec<_>>(), [(&1, &"a"), (&2, &"z")]);
+    assert_eq!(map.validate(), Ok(()));
 }
 
 #[test]
-fn test_entry() {
-    use alloc::vec::Vec;
-    let to_vec = |map: &DequeBTreeMap<i32, i32>| {
-        map.iter()
-            .map(|t| (*t.0, *t.1))
-            .collect::<Vec<(i32, i32)>>()
-    };
+fn test_dequemap_get_disjoint_mut() {
+    let mut map: DequeBTreeMap<_, _> = (0..10).map(|i| (i, i * 10)).collect();
+
+    if let Some([a, b, c]) = map.get_disjoint_mut([&7, &2, &5]) {
+        *a += 1;
+        *b += 2;
+        *c += 3;
+    } else {
+        panic!("all keys are present and distinct");
+    }
+    assert_eq!(map.get(&7), Some(&71));
+    assert_eq!(map.get(&2), Some(&22));
+    assert_eq!(map.get(&5), Some(&53));
+
+    assert!(map.get_disjoint_mut([&1, &3, &1]).is_none());
+    assert!(map.get_disjoint_mut([&1, &30]).is_none());
+    assert!(map.get_disjoint_mut([&-1, &3]).is_none());
+    assert_eq!(map.get_disjoint_mut::<i32, 1>([&9]), Some([&mut 90]));
+    assert_eq!(map.get_disjoint_mut::<i32, 0>([]), Some([]));
+}
 
-    let mut map = DequeBTreeMap::new();
-    map.entry(2).or_insert(20);
-    map.entry(1).or_insert(10);
-    map.entry(9).or_insert(90);
-    map.entry(3).or_insert(30);
-    map.entry(5).or_insert(50);
-    assert_eq!(map.get(&1), Some(&10));
-    assert_eq!(map.get(&2), Some(&20));
-    assert_eq!(map.get(&3), Some(&30));
-    assert_eq!(map.get(&5), Some(&50));
-    assert_eq!(map.get(&9), Some(&90));
+#[test]
+fn test_dequemap_retain_mut() {
+    let mut map: DequeBTreeMap<_, _> = [(4, 1), (2, 2), (3, 3), (1, 4), (5, 5)].into();
 
-    assert_eq!(to_vec(&map), [(2, 20), (1, 10), (9, 90), (3, 30), (5, 50)]);
-    assert_eq!(map.entries.len(), map.indices.len());
+    map.retain_mut(|_, v| {
+        *v *= 10;
+        *v != 30
+    });
+    assert_eq!(
+        map.iter().collect::<Vec<_>>(),
+        [(&4, &10), (&2, &20), (&1, &40), (&5, &50)]
+    );
+    assert_eq!(map.validate(), Ok(()));
+}
 
-    map.entry(3).and_modify(|v| *v = 300);
+#[test]
+fn test_dequemap_eq_unordered() {
+    let a: DequeBTreeMap<_, _> = [(1, "a"), (2, "b"), (3, "c")].into();
+    let b: DequeBTreeMap<_, _> = [(3, "c"), (1, "a"), (2, "b")].into();
+    let c: DequeBTreeMap<_, _> = [(3, "c"), (1, "a"), (2, "x")].into();
+
+    assert_ne!(a, b);
+    assert!(a.eq_unordered(&b));
+    assert!(!a.eq_unordered(&c));
+    assert!(!a.eq_unordered(&DequeBTreeMap::new()));
+}
 
-    assert_eq!(to_vec(&map), [(2, 20), (1, 10), (9, 90), (3, 300), (5, 50)]);
-    assert_eq!(map.entries.len(), map.indices.len());
+#[test]
+fn test_dequemap_replace_key() {
+    let mut map: DequeBTreeMap<_, _> = [(5, "a"), (1, "b"), (3, "c")].into();
 
-    map.entry(7).or_insert_with(|| 70);
+    assert_eq!(map.replace_key(&1, 9), Ok(()));
     assert_eq!(
-        to_vec(&map),
-        [(2, 20), (1, 10), (9, 90), (3, 300), (5, 50), (7, 70)]
+        map.iter().collect::<Vec<_>>(),
+        [(&5, &"a"), (&9, &"b"), (&3, &"c")]
     );
-    assert_eq!(map.entries.len(), map.indices.len());
+    assert_eq!(map.get_index_of(&9), Some(1));
+
+    assert_eq!(map.replace_key(&1, 7), Err(RenameError::KeyNotFound));
+    assert_eq!(map.replace_key(&5, 3), Err(RenameError::KeyExists));
+    assert_eq!(map.replace_key(&5, 5), Ok(()));
+    assert_eq!(map.keys().copied().collect::<Vec<_>>(), [5, 9, 3]);
+    assert_eq!(map.validate(), Ok(()));
 }
 
 #[test]
-fn test_dequemap() {
-    use alloc::vec::Vec;
-    let to_vec = |map: &DequeBTreeMap<i32, i32>| {
-        map.iter()
-            .map(|t| (*t.0, *t.1))
-            .collect::<Vec<(i32, i32)>>()
-    };
+fn test_dequemap_position() {
+    let map: DequeBTreeMap<_, _> = [(5, "a"), (1, "b"), (3, "a"), (2, "c")].into();
+
+    assert_eq!(map.position(|_, v| *v == "a"), Some(0));
+    assert_eq!(map.rposition(|_, v| *v == "a"), Some(2));
+    assert_eq!(map.position(|k, _| *k < 3), Some(1));
+    assert_eq!(map.rposition(|k, _| *k < 3), Some(3));
+    assert_eq!(map.position(|_, v| *v == "d"), None);
+    assert_eq!(map.rposition(|_, v| *v == "d"), None);
+
+    let mut calls = 0;
+    map.position(|_, _| {
+        calls += 1;
+        true
+    });
+    assert_eq!(calls, 1);
+}
 
-    let mut map = DequeBTreeMap::new();
-    map.push_back(2, 20);
-    map.push_back(1, 10);
-    map.push_back(9, 90);
-    map.push_back(3, 30);
-    map.push_back(5, 50);
-    assert_eq!(map.get(&1), Some(&10));
-    assert_eq!(map.get(&2), Some(&20));
-    assert_eq!(map.get(&3), Some(&30));
-    assert_eq!(map.get(&5), Some(&50));
-    assert_eq!(map.get(&9), Some(&90));
-    assert_eq!(map.len(), 5);
-    assert_eq!(map.pop_front(), Some((2, 20)));
-    assert_eq!(map.len(), 4);
-    assert_eq!(map.pop_back(), Some((5, 50)));
-    assert_eq!(map.len(), 3);
-    assert_eq!(to_vec(&map), [(1, 10), (9, 90), (3, 30)]);
-    assert_eq!(map.entries.len(), map.indices.len());
+#[test]
+fn test_dequemap_get_range() {
+    let mut map: DequeBTreeMap<_, _> = (0..10).map(|i| (i, i * 10)).collect();
 
-    let mut map1: DequeBTreeMap<i32, i32> = DequeBTreeMap::new();
-    map1.push_back(7, 70);
-    map1.push_back(9, 900);
-    map.extend(map1);
-    assert_eq!(to_vec(&map), [(1, 10), (9, 900), (3, 30), (7, 70)]);
-    assert_eq!(map.entries.len(), map.indices.len());
+    let keys = |iter: Iter<'_, i32, i32>| iter.map(|(k, _)| *k).collect::<Vec<_>>();
+    assert_eq!(keys(map.get_range(2..5)), [2, 3, 4]);
+    assert_eq!(keys(map.get_range(..=1)), [0, 1]);
+    assert_eq!(keys(map.get_range(8..)), [8, 9]);
+    assert_eq!(keys(map.get_range(8..20)), [8, 9]);
+    assert!(keys(map.get_range(12..20)).is_empty());
+    assert_eq!(map.get_range(..).len(), 10);
 
-    assert_eq!(map.front(), Some((&1, &10)));
-    assert_eq!(map.back(), Some((&7, &70)));
+    map.remove(&3);
+    map.remove(&5);
+    assert_eq!(keys(map.get_range(2..5)), [2, 4, 6]);
+    assert_eq!(map.get_range(6..).next_back(), Some((&9, &90)));
+    assert_eq!(map.get_range(1..4).len(), 3);
+}
 
-    assert_eq!(to_vec(&map), [(1, 10), (9, 900), (3, 30), (7, 70)]);
-    assert_eq!(map.entries.len(), map.indices.len());
+#[cfg(feature = "rayon")]
+#[test]
+fn test_dequemap_par_iter() {
+    use rayon::iter::ParallelIterator;
 
-    map.remove(&3);
-    assert_eq!(to_vec(&map), [(1, 10), (9, 900), (7, 70)]);
-    assert_eq!(map.entries.len(), map.indices.len());
+    let mut map: DequeBTreeMap<_, _> = (0..1000u64).rev().map(|i| (i, i * 2)).collect();
+    map.remove(&500);
+
+    let entries: Vec<_> = map.par_iter().collect();
+    assert_eq!(entries, map.iter().collect::<Vec<_>>());
+    assert_eq!(map.par_values().sum::<u64>(), map.values().sum::<u64>());
 }
 
 #[test]
-fn test_dequemap_index() {
-    let mut map = DequeBTreeMap::new();
-    map.push_back(2, 20);
-    map.push_back(1, 10);
-    map.push_back(9, 90);
-    assert_eq!(map.index_mut(1), &mut 10);
-    assert_eq!(map.index(2), &90);
+fn test_dequemap_from_sorted_iter() {
+    let map = DequeBTreeMap::from_sorted_iter((0..1000).map(|i| (i * 2, i)));
+
+    assert_eq!(map.len(), 1000);
+    assert_eq!(map.validate(), Ok(()));
+    assert!(map.iter().eq(map.iter_sorted()));
+    assert_eq!(map.get_index_of(&20), Some(10));
+    assert_eq!(map, (0..1000).map(|i| (i * 2, i)).collect());
+    assert!(DequeBTreeMap::<u8, u8>::from_sorted_iter(None).is_empty());
 }
 
+#[cfg(debug_assertions)]
 #[test]
-fn test_dequemap_extend() {
-    use alloc::vec::Vec;
-    let to_vec = |map: &DequeBTreeMap<i32, i32>| {
-        map.iter()
-            .map(|t| (*t.0, *t.1))
-            .collect::<Vec<(i32, i32)>>()
-    };
+#[should_panic(expected = "keys are not strictly increasing")]
+fn test_dequemap_from_sorted_iter_unsorted() {
+    DequeBTreeMap::from_sorted_iter([(1, "a"), (3, "b"), (3, "c")]);
+}
+
+#[test]
+fn test_dequemap_into_btreemap() {
     let mut map = DequeBTreeMap::new();
-    map.push_back(2, 20);
-    map.push_back(1, 10);
-    map.push_back(9, 90);
-    map.extend([(10, 100), (5, 50)]);
+    map.push_back(3, "c");
+    map.push_front(1, "a");
+    map.push_back(2, "b");
+
+    assert_eq!(map.as_btreemap().len(), 3);
+    assert!(map.as_btreemap().iter().eq(map.iter_sorted()));
+
+    let sorted = map.into_btreemap();
     assert_eq!(
-        to_vec(&map),
-        [(2, 20), (1, 10), (9, 90), (10, 100), (5, 50)]
+        sorted.into_iter().collect::<Vec<_>>(),
+        [(1, "a"), (2, "b"), (3, "c")]
     );
-    assert_eq!(map.entries.len(), map.indices.len());
 }
 
 #[test]
-fn test_dequemap_retain() {
-    let mut map = DequeBTreeMap::new();
-    map.push_back(2, 20);
-    map.push_back(1, 10);
-    map.push_back(9, 90);
-    map.extend([(10, 100), (5, 50)]);
-
-    assert_eq!(map.entries.len(), map.indices.len());
-    assert_eq!(map.entries.len(), 5);
+fn test_dequemap_validate() {
+    let mut map: DequeBTreeMap<i32, i32> = (0..10).map(|i| (i, i)).collect();
+    map.remove(&4);
+    map.move_to_front(&7);
+    assert_eq!(map.validate(), Ok(()));
+
+    map.entries.insert(20, 20);
+    assert!(map.validate().is_err());
+    map.entries.remove(&20);
+
+    map.entries.remove(&7);
+    map.entries.insert(30, 30);
+    assert_eq!(
+        map.validate(),
+        Err("key in indices at 0 is missing from entries".into())
+    );
+}
 
-    map.retain(|k, _| *k != 10 && *k != 2);
+#[test]
+fn test_dequemap_merge_with() {
+    let mut map: DequeBTreeMap<_, _> = [(1, 10), (2, 20), (3, 30)].into();
+    let other: DequeBTreeMap<_, _> = [(9, 90), (2, 200), (8, 80)].into();
+
+    let mut collisions = Vec::new();
+    map.merge_with(other, |k, v, incoming| {
+        collisions.push(*k);
+        *v += incoming;
+    });
+    assert_eq!(collisions, [2]);
+    assert_eq!(
+        map.iter().collect::<Vec<_>>(),
+        [(&1, &10), (&2, &220), (&3, &30), (&9, &90), (&8, &80)]
+    );
+    assert_eq!(map.validate(), Ok(()));
 
-    assert_eq!(map.entries.len(), map.indices.len());
-    assert_eq!(map.entries.len(), 3);
+    map.merge_with(DequeBTreeMap::new(), |_, _, _| unreachable!());
+    assert_eq!(map.len(), 5);
 }
@@ -39,6 +39,16 @@ pub mod btreemap;
 #[cfg(feature = "btreemap")]
 pub use btreemap::DequeBTreeMap;
 
+#[cfg(all(feature = "btreemap", feature = "serde"))]
+pub mod sequence;
+#[cfg(all(feature = "btreemap", feature = "serde"))]
+pub mod string_keys;
+
+#[cfg(feature = "btreemap")]
+pub mod bounded;
+#[cfg(feature = "btreemap")]
+pub use bounded::{BoundedDequeBTreeMap, Eviction};
+
 #[cfg(feature = "hashmap")]
 pub mod hashmap;
 #[cfg(feature = "hashmap")]
@@ -0,0 +1,85 @@
+//! Serializes a `DequeBTreeMap` as a sequence of `(key, value)` pairs, so the deque order
+//! survives formats that do not keep the order of map entries, e.g. when JSON objects are
+//! read into a hash map on the way. The default `Serialize` impl writes a map instead.
+//!
+//! Use it with `#[serde(with = "dequemap::sequence")]` on a `DequeBTreeMap` field.
+//!
+//! Requires crate features `"btreemap"` and `"serde"`.
+
+use core::marker::PhantomData;
+
+use serde::de::{Deserializer, SeqAccess, Visitor};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+use crate::btreemap::DequeBTreeMap;
+
+/// Serializes `map` as a sequence of `(key, value)` pairs in deque order.
+pub fn serialize<K, V, S>(map: &DequeBTreeMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Serialize + Ord,
+    V: Serialize,
+    S: Serializer,
+{
+    serializer.collect_seq(map.iter())
+}
+
+/// Deserializes a sequence of `(key, value)` pairs, pushing each to the back of the deque. A
+/// repeated key takes the position of its last occurrence along with its last value.
+pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<DequeBTreeMap<K, V>, D::Error>
+where
+    K: Deserialize<'de> + Ord + Clone,
+    V: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_seq(SequenceVisitor(PhantomData))
+}
+
+struct SequenceVisitor<K, V>(PhantomData<(K, V)>);
+
+impl<'de, K, V> Visitor<'de> for SequenceVisitor<K, V>
+where
+    K: Deserialize<'de> + Ord + Clone,
+    V: Deserialize<'de>,
+{
+    type Value = DequeBTreeMap<K, V>;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(formatter, "a sequence of key-value pairs")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut values = DequeBTreeMap::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some((key, value)) = seq.next_element()? {
+            values.push_back(key, value);
+        }
+        Ok(values)
+    }
+}
+
+#[test]
+fn test_sequence() {
+    use alloc::vec::Vec;
+
+    let mut map = DequeBTreeMap::new();
+    map.push_back(20, "b");
+    map.push_back(3, "a");
+    map.push_back(100, "c");
+
+    let mut json = Vec::new();
+    serialize(&map, &mut serde_json::Serializer::new(&mut json)).unwrap();
+    assert_eq!(json, br#"[[20,"b"],[3,"a"],[100,"c"]]"#);
+
+    let decoded: DequeBTreeMap<u32, &str> =
+        deserialize(&mut serde_json::Deserializer::from_slice(&json)).unwrap();
+    assert_eq!(decoded, map);
+
+    let decoded: DequeBTreeMap<u32, &str> = deserialize(&mut serde_json::Deserializer::from_str(
+        r#"[[1,"a"],[2,"b"],[1,"c"]]"#,
+    ))
+    .unwrap();
+    assert_eq!(decoded.iter().collect::<Vec<_>>(), [(&2, &"b"), (&1, &"c")]);
+}
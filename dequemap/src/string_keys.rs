@@ -0,0 +1,140 @@
+//! Serializes a `DequeBTreeMap` as a map with string keys, for formats such as JSON whose
+//! object keys must be strings. Keys are written with `Display` and read back with `FromStr`,
+//! the deque order is kept.
+//!
+//! Use it with `#[serde(with = "dequemap::string_keys")]` on a `DequeBTreeMap` field.
+//!
+//! Requires crate features `"btreemap"` and `"serde"`.
+
+use core::fmt::Display;
+use core::marker::PhantomData;
+use core::str::FromStr;
+
+use serde::de::{DeserializeSeed, Deserializer, MapAccess, Visitor};
+use serde::ser::{SerializeMap, Serializer};
+use serde::{Deserialize, Serialize};
+
+use crate::btreemap::DequeBTreeMap;
+
+/// Serializes `map` with every key written as a string.
+pub fn serialize<K, V, S>(map: &DequeBTreeMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Display + Ord,
+    V: Serialize,
+    S: Serializer,
+{
+    let mut ser_map = serializer.serialize_map(Some(map.len()))?;
+    for (k, v) in map.iter() {
+        ser_map.serialize_entry(&DisplayKey(k), v)?;
+    }
+    ser_map.end()
+}
+
+/// Deserializes a map whose keys are strings parsed into `K`.
+pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<DequeBTreeMap<K, V>, D::Error>
+where
+    K: FromStr + Ord + Clone,
+    K::Err: Display,
+    V: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_map(StringKeysVisitor(PhantomData))
+}
+
+struct DisplayKey<'a, K>(&'a K);
+
+impl<K: Display> Serialize for DisplayKey<'_, K> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self.0)
+    }
+}
+
+struct StringKeysVisitor<K, V>(PhantomData<(K, V)>);
+
+impl<'de, K, V> Visitor<'de> for StringKeysVisitor<K, V>
+where
+    K: FromStr + Ord + Clone,
+    K::Err: Display,
+    V: Deserialize<'de>,
+{
+    type Value = DequeBTreeMap<K, V>;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(formatter, "a map with string keys")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut values = DequeBTreeMap::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(key) = map.next_key_seed(FromStrKey(PhantomData))? {
+            values.insert(key, map.next_value()?);
+        }
+        Ok(values)
+    }
+}
+
+struct FromStrKey<K>(PhantomData<K>);
+
+impl<'de, K> DeserializeSeed<'de> for FromStrKey<K>
+where
+    K: FromStr,
+    K::Err: Display,
+{
+    type Value = K;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<K, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<K> Visitor<'_> for FromStrKey<K>
+where
+    K: FromStr,
+    K::Err: Display,
+{
+    type Value = K;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(formatter, "a string key")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<K, E>
+    where
+        E: serde::de::Error,
+    {
+        K::from_str(v).map_err(E::custom)
+    }
+}
+
+#[test]
+fn test_string_keys() {
+    use alloc::vec::Vec;
+
+    let mut map = DequeBTreeMap::new();
+    map.push_back(20, "b");
+    map.push_back(3, "a");
+    map.push_back(100, "c");
+
+    let mut json = Vec::new();
+    serialize(&map, &mut serde_json::Serializer::new(&mut json)).unwrap();
+    assert_eq!(json, br#"{"20":"b","3":"a","100":"c"}"#);
+
+    let decoded: DequeBTreeMap<u32, &str> =
+        deserialize(&mut serde_json::Deserializer::from_slice(&json)).unwrap();
+    assert_eq!(decoded, map);
+    assert_eq!(
+        decoded.iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+        [20, 3, 100]
+    );
+
+    let err = deserialize::<u32, &str, _>(&mut serde_json::Deserializer::from_str(r#"{"x":"a"}"#));
+    assert!(err.is_err());
+}
@@ -0,0 +1,76 @@
+//! Compares splitting a large payload into chunk messages with the previous implementation,
+//! run with `cargo bench -p handy-grpc --features test-util`. The allocations of one split are
+//! printed before the timings.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use handy_grpc::test_util::split_into_chunks;
+use handy_grpc::transferpb::Message;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const PAYLOAD_SIZE: usize = 64 * 1024 * 1024;
+const CHUNK_SIZE: usize = 64 * 1024;
+
+//The implementation before the chunk count was computed up front
+fn split_via_slices(data: &[u8], chunk_size: usize) -> Vec<Message> {
+    let chunks: Vec<_> = data.chunks(chunk_size).collect();
+    let total_chunks = chunks.len() as u32;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, chunk)| Message {
+            id: 0,
+            priority: 0,
+            total_chunks,
+            chunk_index: i as u32,
+            data: Some(chunk.into()),
+            tag: None,
+        })
+        .collect()
+}
+
+fn allocations<F: Fn() -> Vec<Message>>(f: F) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    drop(f());
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn bench_split(c: &mut Criterion) {
+    let data = vec![7u8; PAYLOAD_SIZE];
+    println!(
+        "allocations per split: before {}, after {}",
+        allocations(|| split_via_slices(&data, CHUNK_SIZE)),
+        allocations(|| split_into_chunks(&data, CHUNK_SIZE))
+    );
+
+    let mut group = c.benchmark_group("split_into_chunks");
+    group.bench_function("before", |b| {
+        b.iter(|| split_via_slices(black_box(&data), CHUNK_SIZE))
+    });
+    group.bench_function("after", |b| {
+        b.iter(|| split_into_chunks(black_box(&data), CHUNK_SIZE))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_split);
+criterion_main!(benches);
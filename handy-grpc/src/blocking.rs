@@ -0,0 +1,100 @@
+//!A blocking facade over `Client` for callers that do not run inside a tokio runtime, e.g.
+//!scripts and synchronous services.
+
+use std::sync::Arc;
+
+use tokio::runtime::{Handle, Runtime};
+
+use super::client::{self, ClientBuilder};
+use super::{Error, Priority, Result};
+
+///Runs a `client::Client` on a tokio runtime and blocks the calling thread on every send.
+///
+///It must not be used from within an async context: its methods return an error there instead
+///of panicking on a nested runtime. A client owning its runtime must not be dropped there either,
+///as dropping a runtime inside another one panics.
+#[derive(Clone)]
+pub struct Client {
+    inner: client::Client,
+    rt: BlockingRuntime,
+}
+
+#[derive(Clone)]
+enum BlockingRuntime {
+    Owned(Arc<Runtime>),
+    Handle(Handle),
+}
+
+impl BlockingRuntime {
+    #[inline]
+    fn block_on<F: std::future::Future>(&self, fut: F) -> Result<F::Output> {
+        if Handle::try_current().is_ok() {
+            return Err(Error::msg(
+                "the blocking client can not be used within an async runtime",
+            ));
+        }
+        Ok(match self {
+            BlockingRuntime::Owned(rt) => rt.block_on(fut),
+            BlockingRuntime::Handle(handle) => handle.block_on(fut),
+        })
+    }
+}
+
+impl Client {
+    ///Connects with a runtime of its own running a single worker thread.
+    pub fn connect(builder: ClientBuilder) -> Result<Self> {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("handy-grpc-blocking")
+            .enable_all()
+            .build()?;
+        Self::connect_with(builder, BlockingRuntime::Owned(Arc::new(rt)))
+    }
+
+    ///Connects on the runtime of `handle`, which has to be a multi-thread runtime so the
+    ///connection is served while no call is blocking.
+    pub fn connect_with_handle(builder: ClientBuilder, handle: Handle) -> Result<Self> {
+        Self::connect_with(builder, BlockingRuntime::Handle(handle))
+    }
+
+    #[inline]
+    fn connect_with(builder: ClientBuilder, rt: BlockingRuntime) -> Result<Self> {
+        let inner = rt.block_on(builder.connect())??;
+        Ok(Self { inner, rt })
+    }
+
+    #[inline]
+    pub fn send(&mut self, data: Vec<u8>) -> Result<Vec<u8>> {
+        let inner = &mut self.inner;
+        self.rt.block_on(inner.send(data))?
+    }
+
+    #[inline]
+    pub fn send_priority(&mut self, data: Vec<u8>, p: Priority) -> Result<Vec<u8>> {
+        let inner = &mut self.inner;
+        self.rt.block_on(inner.send_priority(data, p))?
+    }
+}
+
+#[test]
+fn test_blocking_send() {
+    use super::test_util::spawn_echo_server;
+
+    let server_rt = Runtime::new().unwrap();
+    let addr = server_rt.block_on(spawn_echo_server()).unwrap().to_string();
+
+    let mut c = Client::connect(client::Client::new(addr.clone())).unwrap();
+    assert_eq!(c.send(vec![1, 2, 3]).unwrap(), vec![1, 2, 3]);
+    assert_eq!(c.send_priority(vec![4; 10], 5).unwrap(), vec![4; 10]);
+
+    let mut c = Client::connect_with_handle(
+        client::Client::new(addr).chunk_size(4),
+        server_rt.handle().clone(),
+    )
+    .unwrap();
+    assert_eq!(c.send(vec![5; 10]).unwrap(), vec![5; 10]);
+
+    //Refused inside a runtime rather than panicking
+    let err = server_rt.block_on(async move { c.send(vec![6]) });
+    assert!(err.is_err());
+}
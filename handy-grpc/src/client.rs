@@ -1,23 +1,44 @@
 use anyhow::anyhow;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
 use std::ops::DerefMut;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::Duration;
 
 use collections::PriorityQueue;
-use futures::{SinkExt, Stream};
+use futures::channel::oneshot;
+use futures::{SinkExt, Stream, StreamExt};
+use hyper_util::rt::TokioIo;
 use mpsc::with_priority_channel;
 use parking_lot::RwLock;
+#[cfg(not(feature = "tls-native"))]
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+#[cfg(not(feature = "tls-native"))]
+use rustls::client::WebPkiServerVerifier;
+#[cfg(not(feature = "tls-native"))]
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+#[cfg(not(feature = "tls-native"))]
+use rustls::{DigitallySignedStruct, RootCertStore, SignatureScheme, SupportedProtocolVersion};
+use tokio::net::TcpStream;
+use tokio::sync::{Notify, Semaphore, SemaphorePermit};
+use tokio_util::sync::CancellationToken;
+use tonic::codegen::http::Uri;
 use tonic::codegen::InterceptedService;
-use tonic::metadata::Ascii;
+use tonic::metadata::{Ascii, MetadataKey, MetadataMap};
 use tonic::service::Interceptor;
-use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
-use tonic::{metadata::MetadataValue, Request, Status};
+#[cfg(not(feature = "tls-native"))]
+use tonic::transport::Certificate;
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+use tonic::{metadata::MetadataValue, Code, Request, Status};
 
+#[cfg(test)]
+use super::test_util::{run_server, serve, unused_addr, TestService};
 use super::transferpb::data_transfer_client::DataTransferClient;
 pub use super::transferpb::{self, Message};
-use super::{Error, Id, Priority, Result};
+use super::{Error, Id, Priority, Result, EXCHANGE_ERROR_TAG, TRANSFER_ABORT, TRANSFER_SESSION};
 
 type SendError<T> = mpsc::SendError<T>;
 type Sender<T> = mpsc::Sender<T, SendError<T>>;
@@ -26,6 +47,13 @@ type PriorityQueueType = Arc<parking_lot::RwLock<PriorityQueue<Priority, Message
 
 type DataTransferClientType = DataTransferClient<InterceptedService<Channel, AuthInterceptor>>;
 
+type EventSink = Arc<dyn Fn(&TransferEvent) + Send + Sync>;
+type RequestInterceptor = Arc<dyn Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync>;
+//None once the response stream ended, later sends fail instead of waiting for a reply
+type PendingReplies =
+    Arc<parking_lot::Mutex<Option<HashMap<Id, oneshot::Sender<Result<Vec<u8>>>>>>>;
+
+#[derive(Clone)]
 pub struct ClientBuilder {
     addr: String,
     concurrency_limit: usize,
@@ -34,8 +62,25 @@ pub struct ClientBuilder {
     tls: bool,
     tls_ca: Option<String>,
     tls_domain: Option<String>,
+    tls_sni: Option<String>,
+    tls_alpn: Vec<Vec<u8>>,
+    min_tls_version: Option<TlsVersion>,
     auth_token: Option<String>,
+    auth_header: String,
+    auth_scheme: Option<String>,
     chunk_size: usize,
+    chunking: bool,
+    max_decoding_message_size: Option<usize>,
+    max_encoding_message_size: Option<usize>,
+    max_inflight_chunks: usize,
+    max_inflight_messages: Option<usize>,
+    default_priority: Priority,
+    retry_policy: Option<RetryPolicy>,
+    max_queued_bytes: Option<usize>,
+    transfer_backpressure: bool,
+    event_level: log::LevelFilter,
+    event_sink: Option<EventSink>,
+    interceptor: Option<RequestInterceptor>,
 }
 
 impl Default for ClientBuilder {
@@ -48,14 +93,96 @@ impl Default for ClientBuilder {
             tls: false,
             tls_ca: None,
             tls_domain: None,
+            tls_sni: None,
+            tls_alpn: Vec::new(),
+            min_tls_version: None,
             auth_token: None,
+            auth_header: "authorization".into(),
+            auth_scheme: Some("Bearer".into()),
             chunk_size: CHUNK_SIZE_LIMIT,
+            chunking: true,
+            max_decoding_message_size: None,
+            max_encoding_message_size: None,
+            max_inflight_chunks: 1,
+            max_inflight_messages: None,
+            default_priority: Priority::MIN,
+            retry_policy: None,
+            max_queued_bytes: None,
+            transfer_backpressure: false,
+            event_level: log::LevelFilter::Trace,
+            event_sink: None,
+            interceptor: None,
         }
     }
 }
 
 impl ClientBuilder {
+    ///Checks the settings that would otherwise only fail when connecting: an empty auth token or
+    ///invalid auth header,
+    ///an unparseable address, a zero chunk size, chunk or message concurrency or send attempts and
+    ///unreadable or invalid TLS files.
+    pub fn validate(&self) -> Result<()> {
+        if matches!(&self.auth_token, Some(token) if token.is_empty()) {
+            return Err(Error::msg("auth token is empty"));
+        }
+        if MetadataKey::<Ascii>::from_bytes(self.auth_header.as_bytes()).is_err() {
+            return Err(anyhow!("invalid auth header {:?}", self.auth_header));
+        }
+        let uri = format!("http://{}", self.addr)
+            .parse::<tonic::codegen::http::Uri>()
+            .map_err(|e| anyhow!("invalid address {:?}, {}", self.addr, e))?;
+        if uri.host().map(|host| host.is_empty()).unwrap_or(true) {
+            return Err(anyhow!("invalid address {:?}, missing host", self.addr));
+        }
+        if self.chunk_size == 0 {
+            return Err(Error::msg("chunk size is zero"));
+        }
+        if self.max_inflight_chunks == 0 {
+            return Err(Error::msg("max in-flight chunks is zero"));
+        }
+        if self.max_inflight_messages == Some(0) {
+            return Err(Error::msg("max in-flight messages is zero"));
+        }
+        if matches!(&self.retry_policy, Some(policy) if policy.max_attempts == 0) {
+            return Err(Error::msg("max send attempts is zero"));
+        }
+        if let (true, Some(tls_ca)) = (self.tls, &self.tls_ca) {
+            let pem = std::fs::read_to_string(tls_ca)
+                .map_err(|e| anyhow!("failed to read TLS CA file {:?}, {}", tls_ca, e))?;
+            if !pem.contains("-----BEGIN CERTIFICATE-----") {
+                return Err(anyhow!(
+                    "TLS CA file {:?} contains no PEM certificate",
+                    tls_ca
+                ));
+            }
+        }
+        if !self.tls_alpn.is_empty() || self.min_tls_version.is_some() {
+            if !self.tls {
+                return Err(Error::msg("ALPN and minimum TLS version require TLS"));
+            }
+            if self.tls_ca.is_none() {
+                return Err(Error::msg(
+                    "ALPN and minimum TLS version require a TLS CA file",
+                ));
+            }
+        }
+        if let Some(tls_sni) = &self.tls_sni {
+            if !self.tls {
+                return Err(Error::msg("TLS SNI requires TLS"));
+            }
+            if self.tls_ca.is_none()
+                && matches!(&self.tls_domain, Some(domain) if domain != tls_sni)
+            {
+                return Err(Error::msg(
+                    "TLS SNI differing from the TLS domain requires a TLS CA file",
+                ));
+            }
+        }
+        Ok(())
+    }
+
     pub async fn connect(self) -> Result<Client> {
+        self.validate()?;
         let inner = connect(
             self.addr.as_str(),
             self.concurrency_limit,
@@ -64,16 +191,25 @@ impl ClientBuilder {
             self.tls,
             self.tls_ca.as_ref(),
             self.tls_domain.as_ref(),
-            self.auth_token.clone(),
+            self.tls_sni.as_ref(),
+            &self.tls_alpn,
+            self.min_tls_version,
+            self.auth(),
+            self.interceptor.clone(),
         )
         .await?;
+        let inner = self.message_size_limits(inner);
         Ok(Client {
             inner,
+            in_flight: Arc::new(InFlightMessages::new(self.max_inflight_messages)),
+            #[cfg(feature = "metrics")]
+            latency: Arc::new(LatencyHistogram::new()),
             builder: Arc::new(self),
         })
     }
 
     pub fn connect_lazy(self) -> Result<Client> {
+        self.validate()?;
         let inner = connect_lazy(
             self.addr.as_str(),
             self.concurrency_limit,
@@ -82,14 +218,38 @@ impl ClientBuilder {
             self.tls,
             self.tls_ca.as_ref(),
             self.tls_domain.as_ref(),
-            self.auth_token.clone(),
+            self.tls_sni.as_ref(),
+            &self.tls_alpn,
+            self.min_tls_version,
+            self.auth(),
+            self.interceptor.clone(),
         )?;
+        let inner = self.message_size_limits(inner);
         Ok(Client {
             inner,
+            in_flight: Arc::new(InFlightMessages::new(self.max_inflight_messages)),
+            #[cfg(feature = "metrics")]
+            latency: Arc::new(LatencyHistogram::new()),
             builder: Arc::new(self),
         })
     }
 
+    ///Connects `size` clients, each with its own connection, behind a single `ClientPool`.
+    pub async fn connect_pool(self, size: usize) -> Result<ClientPool> {
+        let mut clients = Vec::with_capacity(size);
+        for _ in 0..size.max(1) {
+            clients.push(self.clone().connect().await?);
+        }
+        Ok(ClientPool::new(clients))
+    }
+
+    pub fn connect_pool_lazy(self, size: usize) -> Result<ClientPool> {
+        let clients = (0..size.max(1))
+            .map(|_| self.clone().connect_lazy())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ClientPool::new(clients))
+    }
+
     pub fn concurrency_limit(mut self, concurrency_limit: usize) -> Self {
         self.concurrency_limit = concurrency_limit;
         self
@@ -112,20 +272,179 @@ impl ClientBuilder {
         self
     }
 
+    ///Sets the server name sent in the TLS handshake (SNI), independently of the address connected
+    ///to, which stays the authority of the requests. The SNI is `tls_sni`, else the `tls` domain,
+    ///else the address host; the server certificate is checked against the `tls` domain, else
+    ///`tls_sni`, else the address host. With the `tls-native` feature both names must be the same.
+    pub fn tls_sni(mut self, tls_sni: String) -> Self {
+        self.tls_sni = Some(tls_sni);
+        self
+    }
+
+    ///Sets the ALPN protocols offered in the TLS handshake, only `h2` is offered by default.
+    pub fn tls_alpn(mut self, protocols: Vec<Vec<u8>>) -> Self {
+        self.tls_alpn = protocols;
+        self
+    }
+
+    ///Sets the lowest TLS version accepted from the server, TLS 1.2 by default. The `tls-native`
+    ///feature does not support `TlsVersion::Tls13`.
+    pub fn min_tls_version(mut self, version: TlsVersion) -> Self {
+        self.min_tls_version = Some(version);
+        self
+    }
+
     pub fn auth_token(mut self, token: Option<String>) -> Self {
         self.auth_token = token;
         self
     }
 
+    ///Sends the auth token under the metadata key `header` instead of `authorization`.
+    pub fn auth_header(mut self, header: String) -> Self {
+        self.auth_header = header;
+        self
+    }
+
+    ///Prefixes the auth token with `scheme` instead of `Bearer`, `None` sends the token verbatim,
+    ///e.g. for gateways reading an `x-api-key` header.
+    pub fn auth_scheme(mut self, scheme: Option<String>) -> Self {
+        self.auth_scheme = scheme;
+        self
+    }
+
+    #[inline]
+    fn auth(&self) -> Option<AuthToken> {
+        self.auth_token.as_ref().map(|token| AuthToken {
+            header: self.auth_header.clone(),
+            scheme: self.auth_scheme.clone(),
+            token: token.clone(),
+        })
+    }
+
     pub fn chunk_size(mut self, chunk_size: usize) -> Self {
         self.chunk_size = chunk_size;
         self
     }
+
+    ///Sends every payload as a single message whatever its size instead of splitting it into
+    ///`chunk_size` chunks. Payloads over 4MB then need a raised `max_encoding_message_size` here
+    ///and `max_decoding_message_size` on the server.
+    pub fn no_chunking(mut self) -> Self {
+        self.chunking = false;
+        self
+    }
+
+    #[inline]
+    fn chunk_limit(&self) -> usize {
+        if self.chunking {
+            self.chunk_size
+        } else {
+            usize::MAX
+        }
+    }
+
+    pub fn max_decoding_message_size(mut self, max_decoding_message_size: usize) -> Self {
+        self.max_decoding_message_size = Some(max_decoding_message_size);
+        self
+    }
+
+    pub fn max_encoding_message_size(mut self, max_encoding_message_size: usize) -> Self {
+        self.max_encoding_message_size = Some(max_encoding_message_size);
+        self
+    }
+
+    #[inline]
+    fn message_size_limits(&self, mut inner: DataTransferClientType) -> DataTransferClientType {
+        if let Some(limit) = self.max_decoding_message_size {
+            inner = inner.max_decoding_message_size(limit);
+        }
+        if let Some(limit) = self.max_encoding_message_size {
+            inner = inner.max_encoding_message_size(limit);
+        }
+        inner
+    }
+
+    ///Lets `send` have up to `max_inflight_chunks` chunks of a message in flight at once instead
+    ///of sending them one by one, the server merges them by chunk index whatever order they
+    ///arrive in.
+    pub fn max_inflight_chunks(mut self, max_inflight_chunks: usize) -> Self {
+        self.max_inflight_chunks = max_inflight_chunks;
+        self
+    }
+
+    ///Lets at most `max_inflight_messages` chunked sends of the client and its clones be unfinished
+    ///at once, further ones wait for a slot, so a producer that starts sends faster than they
+    ///complete cannot grow the server's reassembly buffers without bound. Unlimited by default.
+    pub fn max_inflight_messages(mut self, max_inflight_messages: usize) -> Self {
+        self.max_inflight_messages = Some(max_inflight_messages);
+        self
+    }
+
+    ///Sets the priority `send`, `try_send` and their pool, mailbox and blocking counterparts use,
+    ///`Priority::MIN` by default. `send_priority` still sets it per message.
+    pub fn default_priority(mut self, default_priority: Priority) -> Self {
+        self.default_priority = default_priority;
+        self
+    }
+
+    ///Retries the sends of `send` and its variants that fail with a status `policy` deems
+    ///retryable. The attempts of a send share its message ID and a session, so the server drops
+    ///the chunks it already received instead of handling the message twice, and answers a retry
+    ///whose message it already handled with the reply it gave the first attempt.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    ///Limits the payload bytes waiting in the queue of a `Mailbox`, `try_send` rejects a message
+    ///that does not fit with a full error and `try_reserve_bytes` checks beforehand whether it
+    ///would. `send` only waits for queue slots. Unlimited by default.
+    pub fn max_queued_bytes(mut self, max_queued_bytes: usize) -> Self {
+        self.max_queued_bytes = Some(max_queued_bytes);
+        self
+    }
+
+    ///Makes `Mailbox::send` wait until the transfer stream asks for the next message instead
+    ///of only for room in the queue. The stream asks while HTTP/2 flow control lets it send, so
+    ///a slow server holds senders back rather than the queue filling up. `try_send` still only
+    ///checks the queue.
+    pub fn transfer_backpressure(mut self, transfer_backpressure: bool) -> Self {
+        self.transfer_backpressure = transfer_backpressure;
+        self
+    }
+
+    ///Drops transfer events less severe than `level`, `LevelFilter::Off` drops them all.
+    pub fn event_level(mut self, level: log::LevelFilter) -> Self {
+        self.event_level = level;
+        self
+    }
+
+    ///Passes transfer events to `sink` instead of logging them.
+    pub fn event_sink<F>(mut self, sink: F) -> Self
+    where
+        F: Fn(&TransferEvent) + Send + Sync + 'static,
+    {
+        self.event_sink = Some(Arc::new(sink));
+        self
+    }
+
+    ///Runs `interceptor` on every outgoing request after the auth header has been set, e.g. to
+    ///propagate tracing context or add custom metadata. A returned error fails the request.
+    pub fn interceptor<F>(mut self, interceptor: F) -> Self
+    where
+        F: Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static,
+    {
+        self.interceptor = Some(Arc::new(interceptor));
+        self
+    }
 }
 
 #[derive(Clone)]
 pub struct Client {
     inner: DataTransferClientType,
+    in_flight: Arc<InFlightMessages>,
+    #[cfg(feature = "metrics")]
+    latency: Arc<LatencyHistogram>,
     builder: Arc<ClientBuilder>,
 }
 
@@ -144,161 +463,925 @@ impl Client {
         &mut self.inner
     }
 
+    ///Establishes the connection now instead of on the first send, e.g. for a client from
+    ///`connect_lazy`. It round-trips a progress query for an unused id, which the server answers
+    ///without side effects, so it also checks the auth token.
+    pub async fn warm_up(&mut self) -> Result<()> {
+        let msg = Message {
+            id: next_id(),
+            priority: 0,
+            total_chunks: 0,
+            chunk_index: 0,
+            data: None,
+            tag: None,
+        };
+        self.connect()
+            .progress(Request::new(msg))
+            .await
+            .map_err(Error::new)?;
+        Ok(())
+    }
+
+    ///Returns the number of chunked sends of the client and its clones that have started but not
+    ///finished, a count that keeps growing points at sends that never complete.
+    #[inline]
+    pub fn in_flight_messages(&self) -> usize {
+        self.in_flight.count.load(Ordering::SeqCst)
+    }
+
+    ///Returns the latency percentiles of the successful sends of the client and its clones so
+    ///far, from the first chunk until the response. Streaming sends are not included.
+    #[cfg(feature = "metrics")]
+    #[inline]
+    pub fn latency_snapshot(&self) -> LatencySnapshot {
+        self.latency.snapshot()
+    }
+
     #[inline]
     pub async fn send(&mut self, data: Vec<u8>) -> Result<Vec<u8>> {
-        self.send_priority(data, Priority::MIN).await
+        self.send_priority(data, self.builder.default_priority)
+            .await
     }
 
     #[inline]
     pub async fn send_priority(&mut self, data: Vec<u8>, p: Priority) -> Result<Vec<u8>> {
-        let chunk_size = self.builder.chunk_size;
+        self.send_priority_with(next_id(), None, data, p, None)
+            .await
+            .map(|(data, _)| data)
+    }
+
+    ///Like `send_priority`, the message carries `tag` so the server can hand it to the handler
+    ///registered for the tag with `Server::tag_tx` without looking at the payload.
+    #[inline]
+    pub async fn send_tagged(
+        &mut self,
+        tag: String,
+        data: Vec<u8>,
+        p: Priority,
+    ) -> Result<Vec<u8>> {
+        self.send_priority_with(next_id(), Some(tag), data, p, None)
+            .await
+            .map(|(data, _)| data)
+    }
+
+    ///Like `send_priority`, but every chunk carries the caller supplied `id` instead of a
+    ///generated one, e.g. to correlate the message with an external system. The server merges
+    ///chunks by id, so ids must be unique among the messages in flight.
+    #[inline]
+    pub async fn send_with_id(&mut self, id: Id, data: Vec<u8>, p: Priority) -> Result<Vec<u8>> {
+        self.send_priority_with(id, None, data, p, None)
+            .await
+            .map(|(data, _)| data)
+    }
+
+    ///Like `send_priority`, also returns the metadata of the response carrying the reply, which
+    ///holds the headers and trailers set by the server.
+    #[inline]
+    pub async fn send_with_response(
+        &mut self,
+        data: Vec<u8>,
+        p: Priority,
+    ) -> Result<(Vec<u8>, MetadataMap)> {
+        self.send_priority_with(next_id(), None, data, p, None)
+            .await
+    }
+
+    ///Like `send_priority`, but aborts once `token` is cancelled, no further chunks are sent and
+    ///the error is `Cancelled`. Chunks sent before the cancellation may have been delivered.
+    #[inline]
+    pub async fn send_cancellable(
+        &mut self,
+        data: Vec<u8>,
+        p: Priority,
+        token: &CancellationToken,
+    ) -> Result<Vec<u8>> {
+        self.send_priority_with(next_id(), None, data, p, Some(token))
+            .await
+            .map(|(data, _)| data)
+    }
+
+    #[inline]
+    async fn send_priority_with(
+        &mut self,
+        id: Id,
+        tag: Option<String>,
+        data: Vec<u8>,
+        p: Priority,
+        token: Option<&CancellationToken>,
+    ) -> Result<(Vec<u8>, MetadataMap)> {
+        #[cfg(feature = "metrics")]
+        let now = std::time::Instant::now();
+        let policy = self.builder.retry_policy.clone();
+        let session = policy.as_ref().map(|_| new_session_id());
+        let max_attempts = policy
+            .as_ref()
+            .map(|policy| policy.max_attempts)
+            .unwrap_or(1);
+        let mut data = data;
+        let mut attempt = 1;
+        let res = loop {
+            let attempt_data = if attempt < max_attempts {
+                data.clone()
+            } else {
+                std::mem::take(&mut data)
+            };
+            let res = self
+                .send_message_with(id, tag.clone(), attempt_data, p, token, session)
+                .await;
+            let backoff = match (&res, &policy) {
+                (Err(e), Some(policy)) if attempt < max_attempts && policy.retries(e) => {
+                    policy.backoff(attempt)
+                }
+                _ => break res,
+            };
+            log::debug!(
+                "Retry the send, message ID: {}, attempt: {}, {:?}",
+                id,
+                attempt,
+                res.as_ref().err()
+            );
+            cancellable(tokio::time::sleep(backoff), token).await?;
+            attempt += 1;
+        };
+        if let Err(e) = &res {
+            if let Some(failed) = e.downcast_ref::<ChunkedSendFailed>() {
+                self.abort(id, p, failed.total_chunks, session).await;
+            }
+        }
+        #[cfg(feature = "metrics")]
+        if res.is_ok() {
+            self.latency.record(now.elapsed());
+        }
+        res
+    }
+
+    //Asks the server to drop the chunks it already holds of a failed send, this fails too when
+    //the connection is gone, the server then drops them on its timeout
+    #[inline]
+    async fn abort(&mut self, id: Id, p: Priority, total_chunks: u32, session: Option<u64>) {
+        let mut req = session_request(
+            Message {
+                id,
+                priority: p,
+                total_chunks,
+                chunk_index: 0,
+                data: None,
+                tag: None,
+            },
+            session,
+        );
+        req.metadata_mut()
+            .insert(TRANSFER_ABORT, MetadataValue::from_static("1"));
+        if let Err(e) = self.connect().send(req).await {
+            log::debug!("Abort failed, message ID: {}, {}", id, e);
+        }
+    }
+
+    #[inline]
+    async fn send_message_with(
+        &mut self,
+        id: Id,
+        tag: Option<String>,
+        data: Vec<u8>,
+        p: Priority,
+        token: Option<&CancellationToken>,
+        session: Option<u64>,
+    ) -> Result<(Vec<u8>, MetadataMap)> {
+        let chunk_size = self.builder.chunk_limit();
+        let max_inflight_chunks = self.builder.max_inflight_chunks.max(1);
+        let in_flight = self.in_flight.clone();
         let c = self.connect();
         if data.len() > chunk_size {
-            //chunked send
-            let mut resp_data = None;
-            for msg in split_into_chunks(data.as_slice(), p, chunk_size) {
-                let resp = c.send(tonic::Request::new(msg)).await.map_err(Error::new)?;
-                let data = resp.into_inner().data;
-                if resp_data.is_none() && data.is_some() {
-                    resp_data = data;
+            //chunked send, the final chunk goes out once all others are acknowledged, so it is
+            //the one completing the message on the server and the only one carrying the response
+            let _in_flight = cancellable(in_flight.start(), token).await?;
+            let mut msgs = tag_chunks(split_into_chunks(data.as_slice(), id, p, chunk_size), tag);
+            let total_chunks = msgs.len() as u32;
+            let last = if let Some(last) = msgs.pop() {
+                last
+            } else {
+                unreachable!()
+            };
+            let mut resps = futures::stream::iter(msgs.into_iter().map(|msg| {
+                let mut c = c.clone();
+                async move { cancellable(c.send(session_request(msg, session)), token).await }
+            }))
+            .buffer_unordered(max_inflight_chunks);
+            let mut delivered = 0;
+            let mut failed = None;
+            while let Some(resp) = resps.next().await {
+                match resp? {
+                    Ok(_) => delivered += 1,
+                    Err(status) => {
+                        failed = Some(status);
+                        break;
+                    }
                 }
             }
-            if let Some(resp_data) = resp_data {
-                Ok(resp_data)
-            } else {
-                Err(anyhow!("Timeout"))
+            drop(resps);
+            let resp = match failed {
+                None => cancellable(c.send(session_request(last, session)), token).await?,
+                Some(status) => Err(status),
+            };
+            match resp {
+                Ok(resp) => {
+                    let (metadata, msg, _) = resp.into_parts();
+                    msg.data
+                        .map(|data| (data, metadata))
+                        .ok_or_else(|| anyhow!("Timeout"))
+                }
+                //The chunks the server holds are left for a retry or dropped by the caller
+                Err(status) => Err(Error::new(ChunkedSendFailed {
+                    id,
+                    delivered,
+                    total_chunks,
+                    status,
+                })),
             }
         } else {
             let msg = Message {
-                id: next_id(),
+                id,
                 priority: p,
                 total_chunks: 0,
                 chunk_index: 0,
                 data: Some(data),
+                tag,
             };
-            let resp = c.send(tonic::Request::new(msg)).await.map_err(Error::new);
-            let msg = resp?.into_inner();
-            Ok(msg.data.unwrap_or_default())
+            let resp = cancellable(c.send(session_request(msg, session)), token)
+                .await?
+                .map_err(Error::new);
+            let (metadata, msg, _) = resp?.into_parts();
+            Ok((msg.data.unwrap_or_default(), metadata))
         }
     }
 
+    ///Sends `data` and returns the stream of responses the server pushes back for it.
+    ///
+    ///The server must be started with `stream_tx`. Responses that the server sent in chunks are
+    ///reassembled before they are yielded.
+    #[inline]
+    pub async fn send_streaming(
+        &mut self,
+        data: Vec<u8>,
+        p: Priority,
+    ) -> Result<impl Stream<Item = Result<Vec<u8>>>> {
+        let chunk_size = self.builder.chunk_limit();
+        let in_flight = self.in_flight.clone();
+        let c = self.connect();
+        let _in_flight;
+        let msg = if data.len() > chunk_size {
+            //chunked send, the last chunk opens the response stream
+            _in_flight = in_flight.start().await;
+            let mut msgs = split_into_chunks(data.as_slice(), next_id(), p, chunk_size);
+            let last = if let Some(last) = msgs.pop() {
+                last
+            } else {
+                unreachable!()
+            };
+            for msg in msgs {
+                c.send(tonic::Request::new(msg)).await.map_err(Error::new)?;
+            }
+            last
+        } else {
+            Message {
+                id: next_id(),
+                priority: p,
+                total_chunks: 0,
+                chunk_index: 0,
+                data: Some(data),
+                tag: None,
+            }
+        };
+        let resp = c
+            .send_stream(tonic::Request::new(msg))
+            .await
+            .map_err(Error::new)?;
+
+        Ok(merge_chunks(resp.into_inner()))
+    }
+
+    ///Opens an `Exchange` stream, the requests sent through the returned `Exchange` and its clones
+    ///share one stream and the server answers each on it as soon as it is handled.
+    pub async fn exchange(&mut self) -> Result<Exchange> {
+        let (tx, rx) = futures::channel::mpsc::channel(EXCHANGE_BUFFER);
+        let resp = self
+            .connect()
+            .exchange(Request::new(rx))
+            .await
+            .map_err(Error::new)?;
+        let pending: PendingReplies = Arc::new(parking_lot::Mutex::new(Some(HashMap::new())));
+        tokio::spawn(exchange_responses(resp.into_inner(), pending.clone()));
+        Ok(Exchange {
+            tx,
+            pending,
+            chunk_size: self.builder.chunk_limit(),
+            default_priority: self.builder.default_priority,
+        })
+    }
+
+    ///Opens a `Duplex` stream on the client's connection: messages sent through the returned
+    ///`Mailbox` go to the server as with `transfer_start`, and the returned stream yields the
+    ///messages the server pushes. Unlike `transfer_start` it does not reconnect, when the call
+    ///fails the mailbox stops delivering and the stream ends with the error.
+    pub async fn open_duplex(
+        &mut self,
+        queue_cap: usize,
+    ) -> Result<(Mailbox, impl Stream<Item = Result<Vec<u8>>>)> {
+        let (mailbox, rx, _) = self.mailbox(queue_cap);
+        let mut req = Request::new(rx);
+        req.metadata_mut()
+            .insert(TRANSFER_SESSION, MetadataValue::from(new_session_id()));
+        let resp = self.connect().duplex(req).await.map_err(Error::new)?;
+        Ok((mailbox, merge_chunks(resp.into_inner())))
+    }
+
     #[inline]
     pub async fn transfer_start(&mut self, queue_cap: usize) -> Mailbox {
         let mut this = self.clone();
-        let queue = Arc::new(parking_lot::RwLock::new(PriorityQueue::default()));
-        let (tx, rx) = with_priority_channel(queue.clone(), queue_cap);
-        let rx = Receiver::new(rx);
-        let mailbox = Mailbox::new(tx, queue, queue_cap, self.builder.chunk_size);
+        let (mailbox, rx, queue) = self.mailbox(queue_cap);
         let addr = self.builder.addr.clone();
+        let session = new_session_id();
         tokio::spawn(async move {
             loop {
-                log::trace!("gRPC call transfer ... ");
-                if let Err(e) = this.connect().transfer(Request::new(rx.clone())).await {
-                    log::warn!(
-                        "gRPC call transfer failure, addr:{}, {}",
-                        addr,
-                        e.to_string()
-                    );
+                this.emit(TransferEvent::Connecting { addr: addr.clone() });
+                let mut req = Request::new(rx.clone());
+                req.metadata_mut()
+                    .insert(TRANSFER_SESSION, MetadataValue::from(session));
+                if let Err(e) = this.connect().transfer(req).await {
+                    //Messages taken from the queue may not have reached the server, queue them
+                    //again, the server drops the ones it has already received.
+                    let requeued = rx.requeue(&queue);
+                    this.emit(TransferEvent::Failed {
+                        addr: addr.clone(),
+                        requeued,
+                        error: e.to_string(),
+                    });
                     tokio::time::sleep(Duration::from_secs(3)).await;
                     continue;
                 }
 
-                log::info!(
-                    "transfer is exit, addr: {:?}, is_closed: {}",
-                    this.builder.addr,
-                    rx.is_closed()
-                );
+                this.emit(TransferEvent::Exited {
+                    addr: addr.clone(),
+                    closed: rx.is_closed(),
+                });
                 break;
             }
         });
         mailbox
     }
-}
-
-#[derive(Clone)]
-pub struct Mailbox {
-    tx: Sender<(Priority, Message)>,
-    queue: PriorityQueueType,
-    queue_cap: usize,
-    chunk_size: usize,
-}
 
-impl Mailbox {
     #[inline]
-    fn new(
-        tx: Sender<(Priority, Message)>,
-        queue: PriorityQueueType,
-        queue_cap: usize,
-        chunk_size: usize,
-    ) -> Self {
-        Self {
+    fn mailbox(&self, queue_cap: usize) -> (Mailbox, Receiver, PriorityQueueType) {
+        let queue = Arc::new(parking_lot::RwLock::new(PriorityQueue::default()));
+        let (tx, rx) = with_priority_channel(queue.clone(), queue_cap);
+        let rx = Receiver::new(rx);
+        let queued_bytes = rx.queued_bytes.clone();
+        let demand = if self.builder.transfer_backpressure {
+            Some(rx.demand.clone())
+        } else {
+            None
+        };
+        let mailbox = Mailbox::new(
             tx,
-            queue,
+            queue.clone(),
             queue_cap,
-            chunk_size,
-        }
+            self.builder.chunk_limit(),
+            self.builder.default_priority,
+            self.builder.max_queued_bytes,
+            queued_bytes,
+            demand,
+        );
+        (mailbox, rx, queue)
     }
 
+    ///Prepares a chunked send of `data` that can be resumed after a failure, see `ResumableSend`.
     #[inline]
-    pub fn queue_len(&self) -> usize {
-        self.queue.read().len()
+    pub fn resumable(&self, data: Vec<u8>, p: Priority) -> ResumableSend {
+        ResumableSend {
+            data,
+            priority: p,
+            id: next_id(),
+            session: new_session_id(),
+            chunk_size: self.builder.chunk_limit(),
+        }
     }
 
     #[inline]
-    pub async fn send(&mut self, data: Vec<u8>) -> Result<(), SendError<Vec<u8>>> {
-        self.send_priority(data, Priority::MIN).await
+    fn emit(&self, event: TransferEvent) {
+        let level = event.level();
+        if level > self.builder.event_level {
+            return;
+        }
+        match &self.builder.event_sink {
+            Some(sink) => sink(&event),
+            None => log::log!(level, "{}", event),
+        }
     }
+}
+
+///Connection lifecycle events of the transfer started by `Client::transfer_start`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransferEvent {
+    ///The transfer call is being made.
+    Connecting { addr: String },
+    ///The transfer call failed, `requeued` in-flight messages are sent again on the next call.
+    Failed {
+        addr: String,
+        requeued: usize,
+        error: String,
+    },
+    ///The transfer ended, `closed` tells whether the mailbox was closed.
+    Exited { addr: String, closed: bool },
+}
 
+impl TransferEvent {
+    ///The level the event is logged at when no sink is set.
     #[inline]
-    pub async fn send_priority(
-        &mut self,
-        data: Vec<u8>,
-        p: Priority,
-    ) -> Result<(), SendError<Vec<u8>>> {
-        if data.len() > self.chunk_size {
-            //chunked transfer
-            for msg in split_into_chunks(data.as_slice(), p, self.chunk_size) {
-                self.tx.send((p, msg)).await.map_err(Self::error)?;
+    pub fn level(&self) -> log::Level {
+        match self {
+            TransferEvent::Connecting { .. } => log::Level::Trace,
+            TransferEvent::Failed { .. } => log::Level::Warn,
+            TransferEvent::Exited { .. } => log::Level::Info,
+        }
+    }
+}
+
+impl std::fmt::Display for TransferEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransferEvent::Connecting { addr } => {
+                write!(f, "gRPC call transfer ... addr: {}", addr)
+            }
+            TransferEvent::Failed {
+                addr,
+                requeued,
+                error,
+            } => write!(
+                f,
+                "gRPC call transfer failure, addr:{}, requeued: {}, {}",
+                addr, requeued, error
+            ),
+            TransferEvent::Exited { addr, closed } => {
+                write!(
+                    f,
+                    "transfer is exit, addr: {:?}, is_closed: {}",
+                    addr, closed
+                )
             }
-            Ok(())
-        } else {
-            let msg = Message {
-                id: next_id(),
-                priority: p,
-                total_chunks: 0,
-                chunk_index: 0,
-                data: Some(data),
-            };
-            self.tx.send((p, msg)).await.map_err(Self::error)
         }
     }
+}
+
+///A chunked send that survives failures: every `send` asks the server for the chunks it already
+///holds and only sends the rest, so an interrupted transfer does not start over. The server drops
+///the received chunks after its `recv_chunks_timeout`.
+#[derive(Clone)]
+pub struct ResumableSend {
+    data: Vec<u8>,
+    priority: Priority,
+    id: Id,
+    session: u64,
+    chunk_size: usize,
+}
 
+impl ResumableSend {
     #[inline]
-    pub async fn quick_send(&mut self, data: Vec<u8>) -> Result<(), SendError<Vec<u8>>> {
-        self.send_priority(data, Priority::MAX).await
+    pub fn total_chunks(&self) -> u32 {
+        chunk_count(self.data.len(), self.chunk_size) as u32
     }
 
+    ///Returns the index of the first chunk the server has not received yet.
     #[inline]
-    pub fn quick_try_send(&mut self, data: Vec<u8>) -> Result<(), SendError<Vec<u8>>> {
-        self.try_send_priority(data, Priority::MAX)
+    pub async fn progress(&self, c: &mut Client) -> Result<u32> {
+        let msg = Message {
+            id: self.id,
+            priority: self.priority,
+            total_chunks: self.total_chunks(),
+            chunk_index: 0,
+            data: None,
+            tag: None,
+        };
+        let resp = c
+            .connect()
+            .progress(self.request(msg))
+            .await
+            .map_err(Error::new)?;
+        Ok(resp.into_inner().chunk_index)
     }
 
-    #[inline]
-    pub fn try_send(&mut self, data: Vec<u8>) -> Result<(), SendError<Vec<u8>>> {
-        self.try_send_priority(data, Priority::MIN)
+    ///Sends the chunks the server has not received yet and returns the response. After a failure
+    ///it can be called again, possibly with another client connected to the same server.
+    pub async fn send(&self, c: &mut Client) -> Result<Vec<u8>> {
+        let total_chunks = self.total_chunks();
+        let mut resp_data = None;
+        for chunk_index in self.progress(c).await?..total_chunks {
+            let start = chunk_index as usize * self.chunk_size;
+            let end = (start + self.chunk_size).min(self.data.len());
+            let msg = Message {
+                id: self.id,
+                priority: self.priority,
+                total_chunks,
+                chunk_index,
+                data: Some(self.data[start..end].to_vec()),
+                tag: None,
+            };
+            let resp = c
+                .connect()
+                .send(self.request(msg))
+                .await
+                .map_err(Error::new)?;
+            //Only the final chunk carries the response
+            if chunk_index + 1 == total_chunks {
+                resp_data = resp.into_inner().data;
+            }
+        }
+        resp_data.ok_or_else(|| anyhow!("Timeout"))
     }
 
+    //Chunks of a session are merged by the server whatever connection they arrive on
     #[inline]
-    pub fn try_send_priority(
-        &mut self,
-        data: Vec<u8>,
-        p: Priority,
+    fn request(&self, msg: Message) -> Request<Message> {
+        let mut req = Request::new(msg);
+        req.metadata_mut()
+            .insert(TRANSFER_SESSION, MetadataValue::from(self.session));
+        req
+    }
+}
+
+///Requests multiplexed over one `Exchange` stream, see `Client::exchange`. Every request gets a
+///new id and is answered by the response carrying it, so concurrent sends from clones of an
+///`Exchange` get their own responses whatever order the server completes them in.
+#[derive(Clone)]
+pub struct Exchange {
+    tx: futures::channel::mpsc::Sender<Message>,
+    pending: PendingReplies,
+    chunk_size: usize,
+    default_priority: Priority,
+}
+
+impl Exchange {
+    #[inline]
+    pub async fn send(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        self.send_priority(data, self.default_priority).await
+    }
+
+    pub async fn send_priority(&self, data: Vec<u8>, p: Priority) -> Result<Vec<u8>> {
+        let id = next_id();
+        let (reply_tx, reply_rx) = oneshot::channel();
+        match self.pending.lock().as_mut() {
+            Some(pending) => pending.insert(id, reply_tx),
+            None => return Err(Error::msg("exchange stream is closed")),
+        };
+        let msgs = if data.len() > self.chunk_size {
+            split_into_chunks(data.as_slice(), id, p, self.chunk_size)
+        } else {
+            vec![Message {
+                id,
+                priority: p,
+                total_chunks: 0,
+                chunk_index: 0,
+                data: Some(data),
+                tag: None,
+            }]
+        };
+        let mut tx = self.tx.clone();
+        for msg in msgs {
+            if tx.send(msg).await.is_err() {
+                if let Some(pending) = self.pending.lock().as_mut() {
+                    pending.remove(&id);
+                }
+                return Err(Error::msg("exchange stream is closed"));
+            }
+        }
+        reply_rx
+            .await
+            .map_err(|_| Error::msg("exchange stream is closed"))?
+    }
+}
+
+//Merges the chunks of the messages of a response stream, chunked messages arrive one at a time
+fn merge_chunks(resp: tonic::Streaming<Message>) -> impl Stream<Item = Result<Vec<u8>>> {
+    let mut merged_data = Vec::new();
+    resp.filter_map(move |msg| {
+        let item = match msg {
+            Ok(msg) if msg.total_chunks > 1 => {
+                merged_data.extend(msg.data.unwrap_or_default());
+                if msg.chunk_index + 1 >= msg.total_chunks {
+                    Some(Ok(std::mem::take(&mut merged_data)))
+                } else {
+                    None
+                }
+            }
+            Ok(msg) => Some(Ok(msg.data.unwrap_or_default())),
+            Err(e) => Some(Err(Error::new(e))),
+        };
+        futures::future::ready(item)
+    })
+}
+
+//Passes the responses of an `Exchange` stream to the sends waiting for them, merging chunked
+//responses by id. An error response fails only the send of its id, when the stream fails every
+//waiting send gets the error.
+async fn exchange_responses(mut resp: tonic::Streaming<Message>, pending: PendingReplies) {
+    let mut merged_data: HashMap<Id, Vec<u8>> = HashMap::new();
+    while let Some(msg) = resp.next().await {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(status) => {
+                if let Some(pending) = pending.lock().take() {
+                    for (_, reply_tx) in pending {
+                        let _ = reply_tx.send(Err(Error::new(status.clone())));
+                    }
+                }
+                return;
+            }
+        };
+        let data = if msg.tag.as_deref() == Some(EXCHANGE_ERROR_TAG) {
+            merged_data.remove(&msg.id);
+            let message = String::from_utf8_lossy(msg.data.as_deref().unwrap_or_default());
+            let status = Status::new(Code::from(msg.chunk_index as i32), message);
+            Err(Error::new(status))
+        } else if msg.total_chunks > 1 {
+            let merged = merged_data.entry(msg.id).or_default();
+            merged.extend(msg.data.unwrap_or_default());
+            if msg.chunk_index + 1 < msg.total_chunks {
+                continue;
+            }
+            Ok(merged_data.remove(&msg.id).unwrap_or_default())
+        } else {
+            Ok(msg.data.unwrap_or_default())
+        };
+        let reply_tx = pending
+            .lock()
+            .as_mut()
+            .and_then(|pending| pending.remove(&msg.id));
+        if let Some(reply_tx) = reply_tx {
+            let _ = reply_tx.send(data);
+        }
+    }
+    pending.lock().take();
+}
+
+///A set of clients connected to the same server, sends are spread over them round-robin so
+///the load is not limited by the stream limits of a single HTTP/2 connection.
+#[derive(Clone)]
+pub struct ClientPool {
+    clients: Arc<Vec<Client>>,
+    next: Arc<AtomicUsize>,
+}
+
+impl ClientPool {
+    #[inline]
+    fn new(clients: Vec<Client>) -> Self {
+        Self {
+            clients: Arc::new(clients),
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    #[inline]
+    pub async fn send(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        let mut c = self.clients[self.next_index()].clone();
+        c.send(data).await
+    }
+
+    #[inline]
+    pub async fn send_priority(&self, data: Vec<u8>, p: Priority) -> Result<Vec<u8>> {
+        let mut c = self.clients[self.next_index()].clone();
+        c.send_priority(data, p).await
+    }
+
+    #[inline]
+    fn next_index(&self) -> usize {
+        self.next.fetch_add(1, Ordering::Relaxed) % self.clients.len()
+    }
+}
+
+#[derive(Clone)]
+pub struct Mailbox {
+    tx: Sender<(Priority, Message)>,
+    queue: PriorityQueueType,
+    queue_cap: usize,
+    chunk_size: usize,
+    default_priority: Priority,
+    max_queued_bytes: Option<usize>,
+    //Payload bytes of the messages in the queue, shared with the transfer stream
+    queued_bytes: Arc<AtomicUsize>,
+    //Signalled when the transfer stream waits for a message, set with `transfer_backpressure`
+    demand: Option<Arc<Notify>>,
+    //Queue slots held by the outstanding reservations of the mailbox and its clones
+    reserved: Arc<AtomicUsize>,
+}
+
+impl Mailbox {
+    #[allow(clippy::too_many_arguments)]
+    #[inline]
+    fn new(
+        tx: Sender<(Priority, Message)>,
+        queue: PriorityQueueType,
+        queue_cap: usize,
+        chunk_size: usize,
+        default_priority: Priority,
+        max_queued_bytes: Option<usize>,
+        queued_bytes: Arc<AtomicUsize>,
+        demand: Option<Arc<Notify>>,
+    ) -> Self {
+        Self {
+            tx,
+            queue,
+            queue_cap,
+            chunk_size,
+            default_priority,
+            max_queued_bytes,
+            queued_bytes,
+            demand,
+            reserved: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    #[inline]
+    pub fn queue_len(&self) -> usize {
+        self.queue.read().len()
+    }
+
+    ///Returns the payload bytes of the messages waiting in the queue.
+    #[inline]
+    pub fn queued_bytes(&self) -> usize {
+        self.queued_bytes.load(Ordering::SeqCst)
+    }
+
+    ///Checks whether a payload of `bytes` fits in the budget set with `max_queued_bytes`, so a
+    ///producer can shed a large message before building it. Nothing is reserved, a concurrent
+    ///send may still take the room.
+    #[inline]
+    pub fn try_reserve_bytes(&self, bytes: usize) -> bool {
+        match self.max_queued_bytes {
+            Some(max_queued_bytes) => self.queued_bytes() + bytes <= max_queued_bytes,
+            None => true,
+        }
+    }
+
+    ///Returns the number of queue slots a message of `len` bytes takes, one per chunk.
+    #[inline]
+    pub fn chunk_count(&self, len: usize) -> usize {
+        chunk_count(len, self.chunk_size)
+    }
+
+    ///Reserves queue slots for the `chunks` chunks of one message up front, so `send_reserved`
+    ///can queue them all without waiting or failing halfway. Other sends see the reserved slots
+    ///as taken until the reservation is used or dropped. Returns a full error if the queue has
+    ///no room for all of them.
+    pub fn reserve(&mut self, chunks: usize) -> Result<Reservation, SendError<()>> {
+        let queue = self.queue.write();
+        let reserved = self.reserved.load(Ordering::SeqCst);
+        if queue.len() + reserved + chunks > self.queue_cap {
+            return Err(SendError::<()>::full(()));
+        }
+        self.reserved.store(reserved + chunks, Ordering::SeqCst);
+        self.tx.set_capacity(self.queue_cap - reserved - chunks);
+        Ok(Reservation {
+            chunks,
+            mailbox: self.clone(),
+        })
+    }
+
+    ///Queues all chunks of `data` into the slots held by `reservation`, then gives the slots
+    ///back. Nothing is queued and a full error is returned if the message takes more chunks
+    ///than were reserved.
+    pub fn send_reserved(
+        &mut self,
+        reservation: Reservation,
+        data: Vec<u8>,
+        p: Priority,
+    ) -> Result<(), SendError<Vec<u8>>> {
+        if self.chunk_count(data.len()) > reservation.chunks {
+            return Err(SendError::<Vec<u8>>::full(data));
+        }
+        let msgs = if data.len() > self.chunk_size {
+            split_into_chunks(data.as_slice(), next_id(), p, self.chunk_size)
+        } else {
+            vec![Message {
+                id: next_id(),
+                priority: p,
+                total_chunks: 0,
+                chunk_index: 0,
+                data: Some(data),
+                tag: None,
+            }]
+        };
+        for msg in msgs {
+            self.start_send_msg(p, msg)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub async fn send(&mut self, data: Vec<u8>) -> Result<(), SendError<Vec<u8>>> {
+        self.send_priority(data, self.default_priority).await
+    }
+
+    #[inline]
+    pub async fn send_priority(
+        &mut self,
+        data: Vec<u8>,
+        p: Priority,
+    ) -> Result<(), SendError<Vec<u8>>> {
+        self.send_with_id(next_id(), data, p).await
+    }
+
+    ///Like `send_priority`, but every chunk carries the caller supplied `id`, see
+    ///`Client::send_with_id`.
+    #[inline]
+    pub async fn send_with_id(
+        &mut self,
+        id: Id,
+        data: Vec<u8>,
+        p: Priority,
+    ) -> Result<(), SendError<Vec<u8>>> {
+        self.send_message(id, None, data, p).await
+    }
+
+    ///Like `send_priority`, the message carries `tag`, see `Client::send_tagged`.
+    #[inline]
+    pub async fn send_tagged(
+        &mut self,
+        tag: String,
+        data: Vec<u8>,
+        p: Priority,
+    ) -> Result<(), SendError<Vec<u8>>> {
+        self.send_message(next_id(), Some(tag), data, p).await
+    }
+
+    #[inline]
+    async fn send_message(
+        &mut self,
+        id: Id,
+        tag: Option<String>,
+        data: Vec<u8>,
+        p: Priority,
+    ) -> Result<(), SendError<Vec<u8>>> {
+        if data.len() > self.chunk_size {
+            //chunked transfer
+            for msg in tag_chunks(
+                split_into_chunks(data.as_slice(), id, p, self.chunk_size),
+                tag,
+            ) {
+                self.ready().await;
+                self.send_msg(p, msg).await?;
+            }
+            Ok(())
+        } else {
+            let msg = Message {
+                id,
+                priority: p,
+                total_chunks: 0,
+                chunk_index: 0,
+                data: Some(data),
+                tag,
+            };
+            self.ready().await;
+            self.send_msg(p, msg).await
+        }
+    }
+
+    #[inline]
+    pub async fn quick_send(&mut self, data: Vec<u8>) -> Result<(), SendError<Vec<u8>>> {
+        self.send_priority(data, Priority::MAX).await
+    }
+
+    #[inline]
+    pub fn quick_try_send(&mut self, data: Vec<u8>) -> Result<(), SendError<Vec<u8>>> {
+        self.try_send_priority(data, Priority::MAX)
+    }
+
+    #[inline]
+    pub fn try_send(&mut self, data: Vec<u8>) -> Result<(), SendError<Vec<u8>>> {
+        self.try_send_priority(data, self.default_priority)
+    }
+
+    #[inline]
+    pub fn try_send_priority(
+        &mut self,
+        data: Vec<u8>,
+        p: Priority,
     ) -> Result<(), SendError<Vec<u8>>> {
-        if self.queue_len() < self.queue_cap {
+        if self.queue_len() + self.reserved.load(Ordering::SeqCst) < self.queue_cap
+            && self.try_reserve_bytes(data.len())
+        {
             if data.len() > self.chunk_size {
                 //chunked transfer
-                for msg in split_into_chunks(data.as_slice(), p, self.chunk_size) {
-                    self.tx.start_send_unpin((p, msg)).map_err(Self::error)?;
+                for msg in split_into_chunks(data.as_slice(), next_id(), p, self.chunk_size) {
+                    self.start_send_msg(p, msg)?;
                 }
                 Ok(())
             } else {
@@ -308,226 +1391,2430 @@ impl Mailbox {
                     total_chunks: 0,
                     chunk_index: 0,
                     data: Some(data),
+                    tag: None,
                 };
-                self.tx.start_send_unpin((p, msg)).map_err(Self::error)
+                self.start_send_msg(p, msg)
+            }
+        } else {
+            Err(SendError::<Vec<u8>>::full(data))
+        }
+    }
+
+    //Queues a message, its payload is counted in `queued_bytes` before the stream can take it
+    #[inline]
+    async fn send_msg(&mut self, p: Priority, msg: Message) -> Result<(), SendError<Vec<u8>>> {
+        let bytes = payload_len(&msg);
+        self.queued_bytes.fetch_add(bytes, Ordering::SeqCst);
+        self.tx.send((p, msg)).await.map_err(|e| {
+            self.queued_bytes.fetch_sub(bytes, Ordering::SeqCst);
+            Self::error(e)
+        })
+    }
+
+    //Like `send_msg` without waiting for room in the queue
+    #[inline]
+    fn start_send_msg(&mut self, p: Priority, msg: Message) -> Result<(), SendError<Vec<u8>>> {
+        let bytes = payload_len(&msg);
+        self.queued_bytes.fetch_add(bytes, Ordering::SeqCst);
+        self.tx.start_send_unpin((p, msg)).map_err(|e| {
+            self.queued_bytes.fetch_sub(bytes, Ordering::SeqCst);
+            Self::error(e)
+        })
+    }
+
+    //Waits for the transfer stream to ask for a message when backpressure is enabled
+    #[inline]
+    async fn ready(&self) {
+        if let Some(demand) = &self.demand {
+            demand.notified().await;
+        }
+    }
+
+    #[inline]
+    fn error(e: SendError<(Priority, Message)>) -> SendError<Vec<u8>> {
+        if e.is_full() {
+            e.into_inner()
+                .map(|(_, msg)| SendError::<Vec<u8>>::full(msg.data.unwrap_or_default()))
+                .unwrap_or_else(|| SendError::<Vec<u8>>::disconnected(None))
+        } else if e.is_disconnected() {
+            SendError::<Vec<u8>>::disconnected(
+                e.into_inner().map(|(_, msg)| msg.data.unwrap_or_default()),
+            )
+        } else {
+            SendError::<Vec<u8>>::disconnected(None)
+        }
+    }
+}
+
+///Queue slots held for the chunks of one message, see `Mailbox::reserve`. The slots are given
+///back when it is dropped.
+pub struct Reservation {
+    chunks: usize,
+    mailbox: Mailbox,
+}
+
+impl Reservation {
+    ///Returns the number of reserved slots.
+    #[inline]
+    pub fn chunks(&self) -> usize {
+        self.chunks
+    }
+}
+
+impl std::fmt::Debug for Reservation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reservation")
+            .field("chunks", &self.chunks)
+            .finish()
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mailbox = &self.mailbox;
+        let _queue = mailbox.queue.write();
+        let reserved = mailbox.reserved.load(Ordering::SeqCst) - self.chunks;
+        mailbox.reserved.store(reserved, Ordering::SeqCst);
+        mailbox.tx.set_capacity(mailbox.queue_cap - reserved);
+    }
+}
+
+///The error returned when a send is aborted through its `CancellationToken`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "send cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+///The error returned when a chunk of a chunked send fails. The send is aborted, `delivered`
+///chunks are known to have reached the server, which is asked to discard them.
+#[derive(Debug, Clone)]
+pub struct ChunkedSendFailed {
+    pub id: Id,
+    pub delivered: u32,
+    pub total_chunks: u32,
+    pub status: Status,
+}
+
+impl std::fmt::Display for ChunkedSendFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "chunked send failed after {} of {} chunks, message ID: {}, {}",
+            self.delivered, self.total_chunks, self.id, self.status
+        )
+    }
+}
+
+impl std::error::Error for ChunkedSendFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.status)
+    }
+}
+
+///When and how often a failed send is retried, see `ClientBuilder::retry_policy`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    ///Attempts of a send in total, including the first one.
+    pub max_attempts: usize,
+    ///Wait before the first retry, doubled for every further one.
+    pub backoff: Duration,
+    ///Status codes worth a retry, a send failing with any other error fails at once.
+    pub retryable: Vec<tonic::Code>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: Duration::from_millis(100),
+            retryable: vec![tonic::Code::Unavailable],
+        }
+    }
+}
+
+impl RetryPolicy {
+    #[inline]
+    fn retries(&self, err: &Error) -> bool {
+        error_status(err)
+            .map(|status| self.retryable.contains(&status.code()))
+            .unwrap_or(false)
+    }
+
+    #[inline]
+    fn backoff(&self, attempt: usize) -> Duration {
+        let shift = (attempt - 1).min(16) as u32;
+        self.backoff.saturating_mul(1 << shift)
+    }
+}
+
+//The chunks of a session are merged and deduplicated by the server whatever connection they
+//arrive on
+#[inline]
+fn session_request(msg: Message, session: Option<u64>) -> Request<Message> {
+    let mut req = Request::new(msg);
+    if let Some(session) = session {
+        req.metadata_mut()
+            .insert(TRANSFER_SESSION, MetadataValue::from(session));
+    }
+    req
+}
+
+///Returns the gRPC status a send failed with, whether the error is the `Status` itself or a
+///`ChunkedSendFailed`, e.g. to retry on `Code::Unavailable` but not on `Code::InvalidArgument`.
+#[inline]
+pub fn error_status(err: &Error) -> Option<&Status> {
+    err.chain().find_map(|e| e.downcast_ref::<Status>())
+}
+
+#[inline]
+async fn cancellable<F: std::future::Future>(
+    fut: F,
+    token: Option<&CancellationToken>,
+) -> Result<F::Output> {
+    if let Some(token) = token {
+        token
+            .run_until_cancelled(fut)
+            .await
+            .ok_or_else(|| Error::new(Cancelled))
+    } else {
+        Ok(fut.await)
+    }
+}
+
+//Latencies in microseconds, values below 16 have a bucket each, larger ones 8 buckets per power
+//of two, so a bucket's upper bound is within 12.5% of the latencies it counts
+#[cfg(feature = "metrics")]
+struct LatencyHistogram {
+    buckets: Vec<std::sync::atomic::AtomicU64>,
+}
+
+#[cfg(feature = "metrics")]
+impl LatencyHistogram {
+    fn new() -> Self {
+        Self {
+            buckets: (0..LATENCY_BUCKETS)
+                .map(|_| std::sync::atomic::AtomicU64::new(0))
+                .collect(),
+        }
+    }
+
+    #[inline]
+    fn record(&self, latency: Duration) {
+        let micros = latency.as_micros().min(u128::from(u64::MAX)) as u64;
+        self.buckets[Self::bucket(micros)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn bucket(micros: u64) -> usize {
+        if micros < 16 {
+            return micros as usize;
+        }
+        let exp = 63 - micros.leading_zeros();
+        let sub = (micros >> (exp - 3)) & 7;
+        16 + (exp as usize - 4) * 8 + sub as usize
+    }
+
+    #[inline]
+    fn upper_bound(bucket: usize) -> u64 {
+        if bucket < 16 {
+            return bucket as u64;
+        }
+        let exp = (bucket - 16) / 8 + 4;
+        let sub = ((bucket - 16) % 8) as u64;
+        let bound = (u128::from(9 + sub) << (exp - 3)) - 1;
+        bound.min(u128::from(u64::MAX)) as u64
+    }
+
+    fn snapshot(&self) -> LatencySnapshot {
+        let counts = self
+            .buckets
+            .iter()
+            .map(|n| n.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let count = counts.iter().sum::<u64>();
+        let percentile = |percent: u64| {
+            let rank = ((count * percent + 99) / 100).max(1);
+            let mut seen = 0;
+            for (bucket, n) in counts.iter().enumerate() {
+                seen += n;
+                if seen >= rank {
+                    return Duration::from_micros(Self::upper_bound(bucket));
+                }
+            }
+            Duration::ZERO
+        };
+        LatencySnapshot {
+            count,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        }
+    }
+}
+
+///Send latency percentiles, see `Client::latency_snapshot`. A percentile is the upper bound of
+///the histogram bucket it falls in, at most 12.5% above the measured latency.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencySnapshot {
+    ///Sends recorded.
+    pub count: u64,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+}
+
+//Counts the chunked sends that have started but not finished, and limits them if configured
+struct InFlightMessages {
+    count: AtomicUsize,
+    limit: Option<Semaphore>,
+}
+
+impl InFlightMessages {
+    fn new(limit: Option<usize>) -> Self {
+        Self {
+            count: AtomicUsize::new(0),
+            limit: limit.map(|limit| Semaphore::new(limit.max(1))),
+        }
+    }
+
+    //Waits for a slot, the send counts as in flight until the guard is dropped
+    async fn start(&self) -> InFlightGuard<'_> {
+        let permit = match &self.limit {
+            Some(limit) => limit.acquire().await.ok(),
+            None => None,
+        };
+        self.count.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard {
+            count: &self.count,
+            _permit: permit,
+        }
+    }
+}
+
+struct InFlightGuard<'a> {
+    count: &'a AtomicUsize,
+    _permit: Option<SemaphorePermit<'a>>,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+//The auth token and how it is sent
+struct AuthToken {
+    header: String,
+    scheme: Option<String>,
+    token: String,
+}
+
+//Sets the auth header, then runs the user's interceptor if any
+#[derive(Clone)]
+struct AuthInterceptor {
+    auth_token: Option<(MetadataKey<Ascii>, MetadataValue<Ascii>)>,
+    interceptor: Option<RequestInterceptor>,
+}
+
+impl Interceptor for AuthInterceptor {
+    #[inline]
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+        if let Some((header, token)) = self.auth_token.clone() {
+            request.metadata_mut().insert(header, token);
+        }
+        match &self.interceptor {
+            Some(interceptor) => interceptor(request),
+            None => Ok(request),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[inline]
+async fn connect(
+    addr: &str,
+    concurrency_limit: usize,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    tls: bool,
+    tls_ca: Option<&String>,
+    tls_domain: Option<&String>,
+    tls_sni: Option<&String>,
+    tls_alpn: &[Vec<u8>],
+    min_tls_version: Option<TlsVersion>,
+    auth: Option<AuthToken>,
+    interceptor: Option<RequestInterceptor>,
+) -> Result<DataTransferClientType> {
+    let (endpoint, tls_connector, mut auth_interceptor) = build_endpoint(
+        addr,
+        concurrency_limit,
+        connect_timeout,
+        timeout,
+        tls,
+        tls_ca,
+        tls_domain,
+        tls_sni,
+        tls_alpn,
+        min_tls_version,
+        auth,
+    )?;
+
+    //Connect
+    let channel = match tls_connector {
+        Some(tls_connector) => endpoint.connect_with_connector(tls_connector).await?,
+        None => endpoint.connect().await?,
+    };
+
+    //Client
+    auth_interceptor.interceptor = interceptor;
+    Ok(DataTransferClient::with_interceptor(
+        channel,
+        auth_interceptor,
+    ))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[inline]
+fn connect_lazy(
+    addr: &str,
+    concurrency_limit: usize,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    tls: bool,
+    tls_ca: Option<&String>,
+    tls_domain: Option<&String>,
+    tls_sni: Option<&String>,
+    tls_alpn: &[Vec<u8>],
+    min_tls_version: Option<TlsVersion>,
+    auth: Option<AuthToken>,
+    interceptor: Option<RequestInterceptor>,
+) -> Result<DataTransferClientType> {
+    let (endpoint, tls_connector, mut auth_interceptor) = build_endpoint(
+        addr,
+        concurrency_limit,
+        connect_timeout,
+        timeout,
+        tls,
+        tls_ca,
+        tls_domain,
+        tls_sni,
+        tls_alpn,
+        min_tls_version,
+        auth,
+    )?;
+
+    //Connect lazy
+    let channel = match tls_connector {
+        Some(tls_connector) => endpoint.connect_with_connector_lazy(tls_connector),
+        None => endpoint.connect_lazy(),
+    };
+
+    //Client
+    auth_interceptor.interceptor = interceptor;
+    Ok(DataTransferClient::with_interceptor(
+        channel,
+        auth_interceptor,
+    ))
+}
+
+//The TLS settings of a connection without our own connector, trusting `tls_ca` and the root
+//stores selected by the `tls-*-roots` features
+#[cfg(not(feature = "tls-native"))]
+#[inline]
+fn client_tls_config(tls_ca: Option<&String>, sni: Option<&String>) -> Result<ClientTlsConfig> {
+    let mut tls_client_cfg = ClientTlsConfig::new();
+    //Trust the platform certificate store
+    #[cfg(feature = "tls-native-roots")]
+    {
+        tls_client_cfg = tls_client_cfg.with_native_roots();
+    }
+    //Trust the Mozilla root certificates bundled with webpki-roots
+    #[cfg(feature = "tls-webpki-roots")]
+    {
+        tls_client_cfg = tls_client_cfg.with_webpki_roots();
+    }
+    if let Some(tls_ca) = tls_ca {
+        let pem = std::fs::read_to_string(tls_ca)?;
+        tls_client_cfg = tls_client_cfg.ca_certificate(Certificate::from_pem(pem));
+    }
+    if let Some(sni) = sni {
+        tls_client_cfg = tls_client_cfg.domain_name(sni);
+    }
+    Ok(tls_client_cfg)
+}
+
+#[allow(clippy::too_many_arguments)]
+#[inline]
+fn build_endpoint(
+    addr: &str,
+    concurrency_limit: usize,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    tls: bool,
+    tls_ca: Option<&String>,
+    tls_domain: Option<&String>,
+    tls_sni: Option<&String>,
+    tls_alpn: &[Vec<u8>],
+    min_tls_version: Option<TlsVersion>,
+    auth: Option<AuthToken>,
+) -> Result<(Endpoint, Option<TlsConnector>, AuthInterceptor)> {
+    //TLS支持
+    let sni = tls_sni.or(tls_domain);
+    let verify_domain = tls_domain.or(tls_sni);
+    //native-tls always goes through our own connector
+    #[cfg(feature = "tls-native")]
+    let (tls_connector, tls_client_cfg) = if tls {
+        let tls_connector =
+            TlsConnector::new(tls_ca, sni, verify_domain, tls_alpn, min_tls_version)?;
+        (Some(tls_connector), None::<ClientTlsConfig>)
+    } else {
+        (None, None)
+    };
+    //ClientTlsConfig has no ALPN or protocol version settings and checks the certificate against
+    //the SNI, anything beyond that goes through our own rustls connector
+    #[cfg(not(feature = "tls-native"))]
+    let (tls_connector, tls_client_cfg) =
+        if tls && (!tls_alpn.is_empty() || min_tls_version.is_some() || sni != verify_domain) {
+            let tls_connector =
+                TlsConnector::new(tls_ca, sni, verify_domain, tls_alpn, min_tls_version)?;
+            (Some(tls_connector), None)
+        } else if tls {
+            (None, Some(client_tls_config(tls_ca, sni)?))
+        } else {
+            (None, None)
+        };
+
+    //gRPC Auth
+    let auth_token = if let Some(auth) = auth {
+        if auth.token.is_empty() {
+            return Err(Error::msg("auth token is empty"));
+        }
+        let header = MetadataKey::from_bytes(auth.header.as_bytes())?;
+        let token = match auth.scheme {
+            Some(scheme) => format!("{} {}", scheme, auth.token),
+            None => auth.token,
+        };
+        Some((header, token.parse::<MetadataValue<_>>()?))
+    } else {
+        None
+    };
+
+    //Concurrency limit
+    let concurrency_limit = if concurrency_limit == 0 {
+        1
+    } else {
+        concurrency_limit
+    };
+
+    //Endpoint
+    //tonic only applies its TLS config to https URIs, our own connector does TLS for http ones
+    let scheme = if tls_client_cfg.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    let endpoint = Channel::from_shared(format!("{}://{}", scheme, addr)).map(|endpoint| {
+        let mut endpoint = endpoint.concurrency_limit(concurrency_limit);
+        if let Some(connect_timeout) = connect_timeout {
+            endpoint = endpoint.connect_timeout(connect_timeout);
+        }
+        if let Some(timeout) = timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        if let Some(tls_client_cfg) = tls_client_cfg {
+            endpoint.tls_config(tls_client_cfg)
+        } else {
+            Ok(endpoint)
+        }
+    })??;
+    Ok((
+        endpoint,
+        tls_connector,
+        AuthInterceptor {
+            auth_token,
+            interceptor: None,
+        },
+    ))
+}
+
+///Lowest TLS protocol version the client accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+#[cfg(not(feature = "tls-native"))]
+impl TlsVersion {
+    fn protocol_versions(min: Option<TlsVersion>) -> &'static [&'static SupportedProtocolVersion] {
+        static TLS13_ONLY: &[&SupportedProtocolVersion] = &[&rustls::version::TLS13];
+        match min {
+            Some(TlsVersion::Tls13) => TLS13_ONLY,
+            Some(TlsVersion::Tls12) | None => rustls::DEFAULT_VERSIONS,
+        }
+    }
+}
+
+//Connects over TCP and performs the TLS handshake with a rustls config of our own
+#[cfg(not(feature = "tls-native"))]
+#[derive(Clone)]
+struct TlsConnector {
+    config: Arc<rustls::ClientConfig>,
+    sni: Option<ServerName<'static>>,
+}
+
+#[cfg(not(feature = "tls-native"))]
+impl TlsConnector {
+    fn new(
+        tls_ca: Option<&String>,
+        sni: Option<&String>,
+        verify_domain: Option<&String>,
+        tls_alpn: &[Vec<u8>],
+        min_tls_version: Option<TlsVersion>,
+    ) -> Result<Self> {
+        let mut roots = RootCertStore::empty();
+        if let Some(tls_ca) = tls_ca {
+            let pem = std::fs::read(tls_ca)?;
+            for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+                roots.add(cert?)?;
+            }
+        }
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(TlsVersion::protocol_versions(min_tls_version))?;
+        let mut config = match verify_domain {
+            Some(domain) if Some(domain) != sni => {
+                let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+                    .build()?;
+                let verifier = DomainVerifier {
+                    inner,
+                    domain: ServerName::try_from(domain.clone())?,
+                };
+                builder
+                    .dangerous()
+                    .with_custom_certificate_verifier(Arc::new(verifier))
+                    .with_no_client_auth()
+            }
+            _ => builder.with_root_certificates(roots).with_no_client_auth(),
+        };
+        config.alpn_protocols = if tls_alpn.is_empty() {
+            vec![b"h2".to_vec()]
+        } else {
+            tls_alpn.to_vec()
+        };
+        let sni = sni
+            .map(|sni| ServerName::try_from(sni.clone()))
+            .transpose()?;
+        Ok(Self {
+            config: Arc::new(config),
+            sni,
+        })
+    }
+}
+
+#[cfg(not(feature = "tls-native"))]
+impl tower_service::Service<Uri> for TlsConnector {
+    type Response = TokioIo<tokio_rustls::client::TlsStream<TcpStream>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connector = tokio_rustls::TlsConnector::from(self.config.clone());
+        let sni = self.sni.clone();
+        Box::pin(async move {
+            let (host, port) = host_port(&uri)?;
+            let sni = match sni {
+                Some(sni) => sni,
+                None => ServerName::try_from(host.to_string())?,
+            };
+            let stream = TcpStream::connect((host, port)).await?;
+            stream.set_nodelay(true)?;
+            Ok(TokioIo::new(connector.connect(sni, stream).await?))
+        })
+    }
+}
+
+//Connects over TCP and performs the TLS handshake with native-tls, which checks the certificate
+//against the name sent in SNI, so that name has to be the TLS domain
+#[cfg(feature = "tls-native")]
+#[derive(Clone)]
+struct TlsConnector {
+    connector: tokio_native_tls::TlsConnector,
+    domain: Option<String>,
+}
+
+#[cfg(feature = "tls-native")]
+impl TlsConnector {
+    fn new(
+        tls_ca: Option<&String>,
+        sni: Option<&String>,
+        verify_domain: Option<&String>,
+        tls_alpn: &[Vec<u8>],
+        min_tls_version: Option<TlsVersion>,
+    ) -> Result<Self> {
+        if sni != verify_domain {
+            return Err(Error::msg(
+                "native-tls requires the SNI to be the TLS domain",
+            ));
+        }
+        let mut builder = native_tls::TlsConnector::builder();
+        if let Some(tls_ca) = tls_ca {
+            let pem = std::fs::read(tls_ca)?;
+            for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+                builder.add_root_certificate(native_tls::Certificate::from_der(&cert?)?);
+            }
+        }
+        //Not every platform TLS library can be limited to TLS 1.3
+        if min_tls_version == Some(TlsVersion::Tls13) {
+            return Err(Error::msg("native-tls can not require TLS 1.3"));
+        }
+        builder.min_protocol_version(Some(native_tls::Protocol::Tlsv12));
+        let alpn = if tls_alpn.is_empty() {
+            vec!["h2"]
+        } else {
+            tls_alpn
+                .iter()
+                .map(|protocol| std::str::from_utf8(protocol))
+                .collect::<std::result::Result<Vec<_>, _>>()?
+        };
+        builder.request_alpns(&alpn);
+        Ok(Self {
+            connector: builder.build()?.into(),
+            domain: sni.cloned(),
+        })
+    }
+}
+
+#[cfg(feature = "tls-native")]
+impl tower_service::Service<Uri> for TlsConnector {
+    type Response = TokioIo<tokio_native_tls::TlsStream<TcpStream>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connector = self.connector.clone();
+        let domain = self.domain.clone();
+        Box::pin(async move {
+            let (host, port) = host_port(&uri)?;
+            let domain = domain.unwrap_or_else(|| host.to_string());
+            let stream = TcpStream::connect((host, port)).await?;
+            stream.set_nodelay(true)?;
+            Ok(TokioIo::new(connector.connect(&domain, stream).await?))
+        })
+    }
+}
+
+#[inline]
+fn host_port(uri: &Uri) -> Result<(&str, u16)> {
+    let host = uri
+        .host()
+        .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+        .ok_or_else(|| anyhow!("missing host in {}", uri))?;
+    Ok((host, uri.port_u16().unwrap_or(443)))
+}
+
+//Checks the server certificate against `domain` rather than the name sent in SNI
+#[cfg(not(feature = "tls-native"))]
+#[derive(Debug)]
+struct DomainVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    domain: ServerName<'static>,
+}
+
+#[cfg(not(feature = "tls-native"))]
+impl ServerCertVerifier for DomainVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        self.inner
+            .verify_server_cert(end_entity, intermediates, &self.domain, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[derive(Clone)]
+struct Receiver {
+    rx: Arc<RwLock<mpsc::Receiver<(Priority, Message)>>>,
+    inflight: Arc<RwLock<Inflight>>,
+    //Holds a permit while the stream is polled with the queue empty
+    demand: Arc<Notify>,
+    //Payload bytes of the messages in the queue, see `Mailbox::queued_bytes`
+    queued_bytes: Arc<AtomicUsize>,
+}
+
+impl Receiver {
+    fn new(rx: mpsc::Receiver<(Priority, Message)>) -> Self {
+        Receiver {
+            rx: Arc::new(RwLock::new(rx)),
+            inflight: Arc::new(RwLock::new(Inflight::default())),
+            demand: Arc::new(Notify::new()),
+            queued_bytes: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    #[inline]
+    pub fn is_closed(&self) -> bool {
+        self.rx.read().is_closed()
+    }
+
+    ///Puts the most recently sent messages back into the queue, returns how many were requeued.
+    #[inline]
+    fn requeue(&self, queue: &PriorityQueueType) -> usize {
+        let mut inflight = self.inflight.write();
+        let mut queue = queue.write();
+        let requeued = inflight.msgs.len();
+        for (p, msg) in inflight.msgs.drain(..) {
+            self.queued_bytes
+                .fetch_add(payload_len(&msg), Ordering::SeqCst);
+            queue.push(p, msg);
+        }
+        inflight.bytes = 0;
+        requeued
+    }
+}
+
+impl Stream for Receiver {
+    type Item = Message;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match Pin::new(self.rx.write().deref_mut()).poll_next(cx) {
+            Poll::Pending => {
+                self.demand.notify_one();
+                Poll::Pending
+            }
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Ready(Some((p, msg))) => {
+                self.queued_bytes
+                    .fetch_sub(payload_len(&msg), Ordering::SeqCst);
+                self.inflight.write().push(p, msg.clone());
+                Poll::Ready(Some(msg))
+            }
+        }
+    }
+}
+
+#[inline]
+fn payload_len(msg: &Message) -> usize {
+    msg.data.as_ref().map(|d| d.len()).unwrap_or_default()
+}
+
+///The messages most recently handed to the transfer stream, bounded by their count and total size.
+///They may still be buffered in the connection when it fails.
+#[derive(Default)]
+struct Inflight {
+    msgs: VecDeque<(Priority, Message)>,
+    bytes: usize,
+}
+
+impl Inflight {
+    #[inline]
+    fn push(&mut self, p: Priority, msg: Message) {
+        self.bytes += payload_len(&msg);
+        self.msgs.push_back((p, msg));
+        while (self.bytes > INFLIGHT_WINDOW_BYTES || self.msgs.len() > INFLIGHT_WINDOW_MSGS)
+            && self.msgs.len() > 1
+        {
+            if let Some((_, msg)) = self.msgs.pop_front() {
+                self.bytes -= payload_len(&msg);
+            }
+        }
+    }
+}
+
+#[inline]
+fn new_session_id() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(next_id());
+    hasher.finish()
+}
+
+#[inline]
+pub(crate) fn next_id() -> Id {
+    use once_cell::sync::OnceCell;
+    use std::sync::atomic::AtomicU64;
+    static ID_GENERATOR: OnceCell<AtomicU64> = OnceCell::new();
+    let id_generator = ID_GENERATOR.get_or_init(|| AtomicU64::new(1));
+    id_generator.fetch_add(1, Ordering::SeqCst)
+}
+
+//At least one, an empty payload still goes out as a message. Written so that `usize::MAX`, the
+//chunk size with chunking disabled, cannot overflow
+#[inline]
+fn chunk_count(len: usize, chunk_size: usize) -> usize {
+    (len / chunk_size + usize::from(len % chunk_size != 0)).max(1)
+}
+
+#[inline]
+pub(crate) fn split_into_chunks(
+    data: &[u8],
+    id: Id,
+    p: Priority,
+    chunk_size: usize,
+) -> Vec<transferpb::Message> {
+    //prost messages own their bytes, so each chunk is still copied once
+    let total_chunks = chunk_count(data.len(), chunk_size);
+    let mut msgs = Vec::with_capacity(total_chunks);
+    for (i, chunk) in data.chunks(chunk_size).enumerate() {
+        msgs.push(transferpb::Message {
+            id,
+            priority: p,
+            total_chunks: total_chunks as u32,
+            chunk_index: i as u32,
+            data: Some(chunk.to_vec()),
+            tag: None,
+        });
+    }
+    msgs
+}
+
+//Every chunk carries the tag, the server routes a message by the chunk completing it
+#[inline]
+fn tag_chunks(mut msgs: Vec<transferpb::Message>, tag: Option<String>) -> Vec<transferpb::Message> {
+    if tag.is_some() {
+        for msg in msgs.iter_mut() {
+            msg.tag = tag.clone();
+        }
+    }
+    msgs
+}
+
+pub(crate) const CHUNK_SIZE_LIMIT: usize = 1024 * 1024;
+const INFLIGHT_WINDOW_BYTES: usize = 8 * 1024 * 1024;
+//Far below the chunks the server remembers across all transfer sessions, so that a replay after a
+//reconnect is still recognized as one while other clients are sending too
+const INFLIGHT_WINDOW_MSGS: usize = 1024;
+const EXCHANGE_BUFFER: usize = 16;
+#[cfg(feature = "metrics")]
+const LATENCY_BUCKETS: usize = 16 + 60 * 8;
+
+#[tokio::test]
+async fn test_send_streaming() {
+    use crate::server::{server, StreamMessage};
+
+    let (tx, _rx) = mpsc::priority_channel(100);
+    let (stream_tx, mut stream_rx) = mpsc::priority_channel::<Priority, StreamMessage>(100);
+    let laddr = run_server(
+        server(([127, 0, 0, 1], 0).into(), tx)
+            .stream_tx(stream_tx)
+            .chunk_size(4),
+    )
+    .await
+    .unwrap();
+    tokio::spawn(async move {
+        while let Some((_, (data, mut reply_tx))) = stream_rx.next().await {
+            reply_tx.send(Ok(vec![1])).await.unwrap();
+            reply_tx.send(Ok(data)).await.unwrap();
+            reply_tx.send(Ok(vec![3; 10])).await.unwrap();
+        }
+    });
+
+    let mut c = Client::new(laddr.to_string())
+        .chunk_size(4)
+        .connect()
+        .await
+        .unwrap();
+    let resps = c
+        .send_streaming(vec![2; 9], 0)
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    let resps = resps.into_iter().map(|r| r.unwrap()).collect::<Vec<_>>();
+    assert_eq!(resps, [vec![1], vec![2; 9], vec![3; 10]]);
+}
+
+#[tokio::test]
+async fn test_send_cancellable() {
+    use crate::server::{server, Message};
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let laddr = run_server(server(([127, 0, 0, 1], 0).into(), tx))
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        //Hold on to the reply senders so the final chunk never gets a response
+        let mut pending = Vec::new();
+        while let Some((_, (_, reply_tx))) = rx.next().await {
+            pending.push(reply_tx);
+        }
+    });
+
+    let mut c = Client::new(laddr.to_string())
+        .chunk_size(4)
+        .connect()
+        .await
+        .unwrap();
+
+    let token = CancellationToken::new();
+    let t = token.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        t.cancel();
+    });
+    let err = c
+        .send_cancellable(vec![1; 10], 0, &token)
+        .await
+        .unwrap_err();
+    assert_eq!(err.downcast_ref::<Cancelled>(), Some(&Cancelled));
+
+    //An already cancelled token stops the send before the first chunk
+    let err = c
+        .send_cancellable(vec![1; 10], 0, &token)
+        .await
+        .unwrap_err();
+    assert!(err.is::<Cancelled>());
+}
+
+#[tokio::test]
+async fn test_client_pool() {
+    use crate::server::{server, Message};
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let laddr = run_server(server(([127, 0, 0, 1], 0).into(), tx))
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        while let Some((_, (data, reply_tx))) = rx.next().await {
+            if let Some(reply_tx) = reply_tx {
+                let _ = reply_tx.send(Ok(data));
+            }
+        }
+    });
+
+    let pool = Client::new(laddr.to_string())
+        .connect_pool(2)
+        .await
+        .unwrap();
+    assert_eq!(pool.len(), 2);
+    assert_eq!(
+        (0..4).map(|_| pool.next_index()).collect::<Vec<_>>(),
+        [0, 1, 0, 1]
+    );
+    for i in 0..4u8 {
+        assert_eq!(pool.send(vec![i]).await.unwrap(), vec![i]);
+    }
+    assert_eq!(pool.next.load(Ordering::Relaxed), 8);
+}
+
+#[tokio::test]
+async fn test_transfer_reconnect() {
+    use crate::server::{server, Message};
+    use tokio::net::{TcpListener, TcpStream};
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(1000);
+    let laddr = run_server(server(([127, 0, 0, 1], 0).into(), tx))
+        .await
+        .unwrap();
+    let received = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let received1 = received.clone();
+    tokio::spawn(async move {
+        while let Some((_, (data, _))) = rx.next().await {
+            received1.lock().push(data);
+        }
+    });
+
+    //A proxy in front of the server, killing its connections looks like a server crash to the client
+    let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = proxy.local_addr().unwrap();
+    let conns = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let conns1 = conns.clone();
+    tokio::spawn(async move {
+        while let Ok((mut inbound, _)) = proxy.accept().await {
+            let mut outbound = TcpStream::connect(laddr).await.unwrap();
+            conns1.lock().push(tokio::spawn(async move {
+                let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+            }));
+        }
+    });
+
+    let mut mailbox = Client::new(proxy_addr.to_string())
+        .chunk_size(64)
+        .connect_lazy()
+        .unwrap()
+        .transfer_start(10_000)
+        .await;
+    let payload = |i: u32| i.to_be_bytes().repeat(if i % 10 == 0 { 40 } else { 1 });
+    for i in 0..200 {
+        mailbox.send(payload(i)).await.unwrap();
+    }
+    while received.lock().len() < 50 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    for conn in conns.lock().drain(..) {
+        conn.abort();
+    }
+    for i in 200..400 {
+        mailbox.send(payload(i)).await.unwrap();
+    }
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(15);
+    while received.lock().len() < 400 && std::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let mut received = received.lock().clone();
+    received.sort();
+    let mut expected = (0..400).map(payload).collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(received, expected);
+}
+
+#[test]
+fn test_client_builder_validate() {
+    let err = |builder: ClientBuilder| builder.validate().unwrap_err().to_string();
+
+    assert!(Client::new("127.0.0.1:10000".into()).validate().is_ok());
+    assert_eq!(
+        err(Client::new("127.0.0.1:10000".into()).auth_token(Some(String::new()))),
+        "auth token is empty"
+    );
+    assert!(err(Client::new("127.0.0.1 10000".into())).starts_with("invalid address"));
+    assert!(err(Client::new(String::new())).starts_with("invalid address"));
+    assert_eq!(
+        err(Client::new("127.0.0.1:10000".into()).chunk_size(0)),
+        "chunk size is zero"
+    );
+
+    let missing = std::env::temp_dir().join("handy-grpc-missing-ca.pem");
+    let missing = missing.to_string_lossy().to_string();
+    assert!(
+        err(Client::new("127.0.0.1:10000".into()).tls(Some(missing), None))
+            .starts_with("failed to read TLS CA file")
+    );
+
+    let invalid = std::env::temp_dir().join("handy-grpc-invalid-ca.pem");
+    std::fs::write(&invalid, "not a certificate").unwrap();
+    let invalid = invalid.to_string_lossy().to_string();
+    assert!(
+        err(Client::new("127.0.0.1:10000".into()).tls(Some(invalid), None))
+            .ends_with("contains no PEM certificate")
+    );
+
+    assert!(Client::new("127.0.0.1:10000".into())
+        .chunk_size(0)
+        .connect_lazy()
+        .is_err());
+}
+
+#[cfg(not(feature = "tls-native"))]
+#[test]
+fn test_build_tls_endpoint() {
+    //Runs against whichever root store the `tls-*-roots` features select
+    let (endpoint, tls_connector, _) = build_endpoint(
+        "localhost:10000",
+        1,
+        None,
+        None,
+        true,
+        None,
+        Some(&"localhost".to_string()),
+        None,
+        &[],
+        None,
+        None,
+    )
+    .unwrap();
+    assert_eq!(endpoint.uri().host(), Some("localhost"));
+    assert!(tls_connector.is_none());
+}
+
+//`ClientTlsConfig` only shows the selected root stores through its Debug output
+#[cfg(all(feature = "tls-native-roots", not(feature = "tls-native")))]
+#[test]
+fn test_tls_native_roots() {
+    let cfg = format!("{:?}", client_tls_config(None, None).unwrap());
+    assert!(cfg.contains("with_native_roots: true"), "{}", cfg);
+}
+
+#[cfg(all(feature = "tls-webpki-roots", not(feature = "tls-native")))]
+#[test]
+fn test_tls_webpki_roots() {
+    let cfg = format!("{:?}", client_tls_config(None, None).unwrap());
+    assert!(cfg.contains("with_webpki_roots: true"), "{}", cfg);
+}
+
+#[cfg(not(any(
+    feature = "tls-native-roots",
+    feature = "tls-webpki-roots",
+    feature = "tls-native"
+)))]
+#[test]
+fn test_tls_without_roots() {
+    //Only `tls_ca` is trusted
+    let tls_ca = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/localhost.pem").to_string();
+    let cfg = format!("{:?}", client_tls_config(Some(&tls_ca), None).unwrap());
+    assert!(!cfg.contains("roots"), "{}", cfg);
+}
+
+#[cfg(not(feature = "tls-native"))]
+#[test]
+fn test_build_tls_options() {
+    let tls_ca = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/localhost.pem").to_string();
+    let (_, tls_connector, _) = build_endpoint(
+        "127.0.0.1:10000",
+        1,
+        None,
+        None,
+        true,
+        Some(&tls_ca),
+        Some(&"localhost".to_string()),
+        None,
+        &[b"h2".to_vec(), b"grpc-exp".to_vec()],
+        Some(TlsVersion::Tls13),
+        None,
+    )
+    .unwrap();
+    let tls_connector = tls_connector.unwrap();
+    assert_eq!(
+        tls_connector.config.alpn_protocols,
+        [b"h2".to_vec(), b"grpc-exp".to_vec()]
+    );
+    assert_eq!(
+        tls_connector.sni,
+        Some(ServerName::try_from("localhost").unwrap())
+    );
+    let versions = |min| {
+        TlsVersion::protocol_versions(min)
+            .iter()
+            .map(|v| v.version)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        versions(Some(TlsVersion::Tls13)),
+        [rustls::ProtocolVersion::TLSv1_3]
+    );
+    assert_eq!(
+        versions(None),
+        [
+            rustls::ProtocolVersion::TLSv1_3,
+            rustls::ProtocolVersion::TLSv1_2
+        ]
+    );
+
+    assert_eq!(
+        Client::new("127.0.0.1:10000".into())
+            .min_tls_version(TlsVersion::Tls13)
+            .validate()
+            .unwrap_err()
+            .to_string(),
+        "ALPN and minimum TLS version require TLS"
+    );
+}
+
+#[cfg(not(feature = "tls-native"))]
+#[tokio::test]
+async fn test_send_tls_options() {
+    use crate::server::{server, Message, TLS};
+
+    let tls_cert = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/localhost.pem").to_string();
+    let tls_key = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/localhost.key").to_string();
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let tls = TLS {
+        server_cert: tls_cert.clone(),
+        server_key: tls_key,
+        client_ca: None,
+        client_domain: None,
+    };
+    let laddr = run_server(server(([127, 0, 0, 1], 0).into(), tx).tls(tls))
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        while let Some((_, (data, reply_tx))) = rx.next().await {
+            if let Some(reply_tx) = reply_tx {
+                let _ = reply_tx.send(Ok(data));
+            }
+        }
+    });
+
+    let mut c = Client::new(laddr.to_string())
+        .tls(Some(tls_cert), Some("localhost".into()))
+        .tls_alpn(vec![b"h2".to_vec()])
+        .min_tls_version(TlsVersion::Tls13)
+        .connect()
+        .await
+        .unwrap();
+    assert_eq!(c.send(vec![1, 2, 3]).await.unwrap(), vec![1, 2, 3]);
+}
+
+#[cfg(not(feature = "tls-native"))]
+#[tokio::test]
+async fn test_send_tls_sni() {
+    use crate::server::{server, Message, TLS};
+
+    let tls_cert = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/localhost.pem").to_string();
+    let tls_key = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/localhost.key").to_string();
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let tls = TLS {
+        server_cert: tls_cert.clone(),
+        server_key: tls_key,
+        client_ca: None,
+        client_domain: None,
+    };
+    let laddr = run_server(server(([127, 0, 0, 1], 0).into(), tx).tls(tls))
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        while let Some((_, (data, reply_tx))) = rx.next().await {
+            if let Some(reply_tx) = reply_tx {
+                let _ = reply_tx.send(Ok(data));
+            }
+        }
+    });
+
+    //The SNI differs from the authority, which stays the IP connected to
+    let tls_sni = "api.example.com".to_string();
+    let (endpoint, tls_connector, _) = build_endpoint(
+        &laddr.to_string(),
+        1,
+        None,
+        None,
+        true,
+        Some(&tls_cert),
+        Some(&"localhost".to_string()),
+        Some(&tls_sni),
+        &[],
+        None,
+        None,
+    )
+    .unwrap();
+    assert_eq!(endpoint.uri().host(), Some("127.0.0.1"));
+    assert_eq!(
+        tls_connector.unwrap().sni,
+        Some(ServerName::try_from("api.example.com").unwrap())
+    );
+
+    //The certificate is checked against the TLS domain, not the SNI
+    let mut c = Client::new(laddr.to_string())
+        .tls(Some(tls_cert.clone()), Some("localhost".into()))
+        .tls_sni(tls_sni.clone())
+        .connect()
+        .await
+        .unwrap();
+    assert_eq!(c.send(vec![1, 2, 3]).await.unwrap(), vec![1, 2, 3]);
+
+    //Without a TLS domain the certificate is checked against the SNI
+    let mut c = Client::new(laddr.to_string())
+        .tls(Some(tls_cert.clone()), None)
+        .tls_sni("localhost".into())
+        .connect()
+        .await
+        .unwrap();
+    assert_eq!(c.send(vec![4, 5, 6]).await.unwrap(), vec![4, 5, 6]);
+
+    assert!(Client::new(laddr.to_string())
+        .tls(Some(tls_cert), None)
+        .tls_sni(tls_sni)
+        .connect()
+        .await
+        .is_err());
+}
+
+#[cfg(feature = "tls-native")]
+#[test]
+fn test_build_native_tls_endpoint() {
+    let localhost = "localhost".to_string();
+    let build = |tls_sni, min_tls_version| {
+        build_endpoint(
+            "localhost:10000",
+            1,
+            None,
+            None,
+            true,
+            None,
+            Some(&localhost),
+            tls_sni,
+            &[],
+            min_tls_version,
+            None,
+        )
+    };
+    //Every TLS endpoint goes through the native-tls connector
+    let (endpoint, tls_connector, _) = build(None, None).unwrap();
+    assert_eq!(endpoint.uri().scheme_str(), Some("http"));
+    assert_eq!(tls_connector.unwrap().domain, Some(localhost.clone()));
+    assert!(build(None, Some(TlsVersion::Tls12)).is_ok());
+
+    //Settings native-tls can not honor are refused
+    let tls_sni = "api.example.com".to_string();
+    assert!(build(Some(&tls_sni), None).is_err());
+    assert!(build(None, Some(TlsVersion::Tls13)).is_err());
+}
+
+#[cfg(feature = "tls-native")]
+#[tokio::test]
+async fn test_send_native_tls() {
+    use crate::server::{server, TLS};
+    use crate::test_util::spawn_handler;
+
+    let tls_cert = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/localhost.pem").to_string();
+    let tls_key = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/localhost.key").to_string();
+    let tls = TLS {
+        server_cert: tls_cert.clone(),
+        server_key: tls_key,
+        client_ca: None,
+        client_domain: None,
+    };
+    let laddr = run_server(server(([127, 0, 0, 1], 0).into(), spawn_handler(Ok)).tls(tls))
+        .await
+        .unwrap();
+
+    for tls_domain in [Some("localhost".to_string()), None] {
+        let mut c = Client::new(laddr.to_string())
+            .tls(Some(tls_cert.clone()), tls_domain)
+            .tls_alpn(vec![b"h2".to_vec()])
+            .min_tls_version(TlsVersion::Tls12)
+            .connect()
+            .await
+            .unwrap();
+        assert_eq!(c.send(vec![1, 2, 3]).await.unwrap(), vec![1, 2, 3]);
+    }
+
+    //The certificate is checked against the TLS domain
+    assert!(Client::new(laddr.to_string())
+        .tls(Some(tls_cert), Some("api.example.com".into()))
+        .connect()
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_send_with_response() {
+    use crate::server::{server, Message};
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let mut metadata = MetadataMap::new();
+    metadata.insert("x-served-by", "node-1".parse().unwrap());
+    let laddr = run_server(server(([127, 0, 0, 1], 0).into(), tx).response_metadata(metadata))
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        while let Some((_, (data, reply_tx))) = rx.next().await {
+            if let Some(reply_tx) = reply_tx {
+                let _ = reply_tx.send(Ok(data));
             }
-        } else {
-            Err(SendError::<Vec<u8>>::full(data))
         }
+    });
+
+    let mut c = Client::new(laddr.to_string())
+        .chunk_size(4)
+        .connect()
+        .await
+        .unwrap();
+    for data in [vec![1, 2, 3], vec![7; 10]] {
+        let (resp, metadata) = c.send_with_response(data.clone(), 0).await.unwrap();
+        assert_eq!(resp, data);
+        assert_eq!(metadata.get("x-served-by").unwrap(), "node-1");
     }
+}
 
-    #[inline]
-    fn error(e: SendError<(Priority, Message)>) -> SendError<Vec<u8>> {
-        if e.is_full() {
-            e.into_inner()
-                .map(|(_, msg)| SendError::<Vec<u8>>::full(msg.data.unwrap_or_default()))
-                .unwrap_or_else(|| SendError::<Vec<u8>>::disconnected(None))
-        } else if e.is_disconnected() {
-            SendError::<Vec<u8>>::disconnected(
-                e.into_inner().map(|(_, msg)| msg.data.unwrap_or_default()),
-            )
-        } else {
-            SendError::<Vec<u8>>::disconnected(None)
+#[tokio::test]
+async fn test_transfer_event_level() {
+    let events = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let events1 = events.clone();
+    //Nothing listens there, every transfer call fails
+    let _mailbox = Client::new(unused_addr().to_string())
+        .event_level(log::LevelFilter::Warn)
+        .event_sink(move |event| events1.lock().push(event.clone()))
+        .connect_lazy()
+        .unwrap()
+        .transfer_start(10)
+        .await;
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(3);
+    while events.lock().is_empty() && std::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let events = events.lock().clone();
+    assert!(!events.is_empty());
+    for event in events {
+        assert!(matches!(event, TransferEvent::Failed { requeued: 0, .. }));
+        assert_eq!(event.level(), log::Level::Warn);
+    }
+}
+
+#[tokio::test]
+async fn test_resumable_send() {
+    use crate::server::{server, Message};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let laddr = run_server(server(([127, 0, 0, 1], 0).into(), tx))
+        .await
+        .unwrap();
+    let received = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let received1 = received.clone();
+    tokio::spawn(async move {
+        while let Some((_, (data, reply_tx))) = rx.next().await {
+            let len = data.len();
+            received1.lock().push(data);
+            if let Some(reply_tx) = reply_tx {
+                let _ = reply_tx.send(Ok(len.to_be_bytes().to_vec()));
+            }
+        }
+    });
+
+    //A proxy in front of the server that cuts its first connection after about 20KB of requests
+    let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = proxy.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut limit = 20 * 1024;
+        while let Ok((inbound, _)) = proxy.accept().await {
+            let outbound = TcpStream::connect(laddr).await.unwrap();
+            let conn_limit = std::mem::replace(&mut limit, u64::MAX);
+            tokio::spawn(async move {
+                let (mut inbound_r, mut inbound_w) = inbound.into_split();
+                let (mut outbound_r, mut outbound_w) = outbound.into_split();
+                let resp = tokio::spawn(async move {
+                    let _ = tokio::io::copy(&mut outbound_r, &mut inbound_w).await;
+                });
+                let mut inbound_r = (&mut inbound_r).take(conn_limit);
+                let _ = tokio::io::copy(&mut inbound_r, &mut outbound_w).await;
+                resp.abort();
+            });
         }
+    });
+
+    let mut c = Client::new(proxy_addr.to_string())
+        .chunk_size(1024)
+        .connect_lazy()
+        .unwrap();
+    let data = (0..64 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+    let r = c.resumable(data.clone(), 0);
+    assert_eq!(r.total_chunks(), 64);
+    assert!(r.send(&mut c).await.is_err());
+
+    //The channel reconnects on the next call
+    let mut progress = None;
+    for _ in 0..10 {
+        if let Ok(next) = r.progress(&mut c).await {
+            progress = Some(next);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
     }
+    let progress = progress.unwrap();
+    assert!(progress > 0 && progress < 64, "progress: {}", progress);
+    assert!(received.lock().is_empty());
+
+    assert_eq!(
+        r.send(&mut c).await.unwrap(),
+        data.len().to_be_bytes().to_vec()
+    );
+    assert_eq!(received.lock().clone(), vec![data]);
 }
 
-#[derive(Clone)]
-struct AuthInterceptor {
-    auth_token: Option<MetadataValue<Ascii>>,
+#[tokio::test]
+async fn test_send_with_id() {
+    use tonic::Response;
+
+    //Records the id of every message it receives
+    let ids = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let (ids1, ids2) = (ids.clone(), ids.clone());
+    let addr = TestService::new(Ok)
+        .on_send(move |_, request| {
+            let msg = request.into_inner();
+            ids1.lock().push(msg.id);
+            async move {
+                Ok(Response::new(Message {
+                    data: Some(vec![1]),
+                    ..msg
+                }))
+            }
+        })
+        .on_transfer(move |_, request| {
+            let ids = ids2.clone();
+            async move {
+                let mut stream = request.into_inner();
+                while let Some(msg) = stream.next().await {
+                    ids.lock().push(msg?.id);
+                }
+                Ok(Response::new(transferpb::Empty {}))
+            }
+        })
+        .spawn()
+        .await
+        .unwrap();
+
+    let mut c = Client::new(addr.to_string())
+        .chunk_size(4)
+        .connect_lazy()
+        .unwrap();
+    assert_eq!(c.send_with_id(7, vec![0; 10], 0).await.unwrap(), vec![1]);
+    assert_eq!(ids.lock().drain(..).collect::<Vec<_>>(), vec![7, 7, 7]);
+    c.send_with_id(8, vec![0; 2], 0).await.unwrap();
+    assert_eq!(ids.lock().drain(..).collect::<Vec<_>>(), vec![8]);
+
+    let mut mailbox = c.transfer_start(10).await;
+    mailbox.send_with_id(9, vec![0; 10], 0).await.unwrap();
+    let deadline = std::time::Instant::now() + Duration::from_secs(3);
+    while ids.lock().len() < 3 && std::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(ids.lock().clone(), vec![9, 9, 9]);
 }
 
-impl Interceptor for AuthInterceptor {
-    #[inline]
-    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
-        if let Some(token) = self.auth_token.clone() {
-            request.metadata_mut().insert("authorization", token);
+#[tokio::test]
+async fn test_send_chunk_failure() {
+    use super::transferpb::data_transfer_server::DataTransfer;
+
+    let received = Arc::new(AtomicUsize::new(0));
+    let received1 = received.clone();
+    //Fails the second chunk of a message, everything else is served as usual
+    let service = TestService::new(move |data| {
+        received1.fetch_add(1, Ordering::SeqCst);
+        Ok(data)
+    })
+    .on_send(|service, request| async move {
+        if request.get_ref().chunk_index == 1 {
+            return Err(Status::unavailable("injected failure"));
         }
-        Ok(request)
-    }
+        service.send(request).await
+    });
+    let metrics = service.metrics();
+    let addr = service.spawn().await.unwrap();
+
+    let mut c = Client::new(addr.to_string())
+        .chunk_size(4)
+        .connect_lazy()
+        .unwrap();
+    let err = c.send_with_id(5, vec![0; 10], 0).await.unwrap_err();
+    let failed = err.downcast_ref::<ChunkedSendFailed>().unwrap();
+    assert_eq!(
+        (failed.id, failed.delivered, failed.total_chunks),
+        (5, 1, 3)
+    );
+    assert_eq!(failed.status.code(), tonic::Code::Unavailable);
+
+    //The first chunk was discarded, nothing is left to be merged or delivered
+    assert_eq!(metrics.stats().reassembly_buffers, 0);
+    assert_eq!(c.send(vec![1; 4]).await.unwrap(), vec![1; 4]);
+    assert_eq!(received.load(Ordering::SeqCst), 1);
 }
 
-#[allow(clippy::too_many_arguments)]
-#[inline]
-async fn connect(
-    addr: &str,
-    concurrency_limit: usize,
-    connect_timeout: Option<Duration>,
-    timeout: Option<Duration>,
-    tls: bool,
-    tls_ca: Option<&String>,
-    tls_domain: Option<&String>,
-    token: Option<String>,
-) -> Result<DataTransferClientType> {
-    let (endpoint, interceptor) = build_endpoint(
-        addr,
-        concurrency_limit,
-        connect_timeout,
-        timeout,
-        tls,
-        tls_ca,
-        tls_domain,
-        token,
-    )?;
+#[tokio::test]
+async fn test_send_max_inflight_chunks() {
+    use super::transferpb::data_transfer_server::DataTransfer;
 
-    //Connect
-    let channel = endpoint.connect().await?;
+    //Takes 50ms to accept every chunk
+    let addr = TestService::new(Ok)
+        .on_send(|service, request| async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            service.send(request).await
+        })
+        .spawn()
+        .await
+        .unwrap();
 
-    //Client
-    Ok(DataTransferClient::with_interceptor(channel, interceptor))
+    //16 chunks
+    let data = (0..16 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+    let mut sequential = Client::new(addr.to_string())
+        .chunk_size(1024)
+        .connect_lazy()
+        .unwrap();
+    let mut concurrent = Client::new(addr.to_string())
+        .chunk_size(1024)
+        .max_inflight_chunks(8)
+        .connect_lazy()
+        .unwrap();
+
+    let now = std::time::Instant::now();
+    assert_eq!(sequential.send(data.clone()).await.unwrap(), data);
+    let sequential_elapsed = now.elapsed();
+    let now = std::time::Instant::now();
+    assert_eq!(concurrent.send(data.clone()).await.unwrap(), data);
+    let concurrent_elapsed = now.elapsed();
+
+    assert!(sequential_elapsed >= Duration::from_millis(16 * 50));
+    assert!(
+        concurrent_elapsed * 2 < sequential_elapsed,
+        "sequential: {:?}, concurrent: {:?}",
+        sequential_elapsed,
+        concurrent_elapsed
+    );
+    assert!(Client::new(addr.to_string())
+        .max_inflight_chunks(0)
+        .validate()
+        .is_err());
 }
 
-#[allow(clippy::too_many_arguments)]
-#[inline]
-fn connect_lazy(
-    addr: &str,
-    concurrency_limit: usize,
-    connect_timeout: Option<Duration>,
-    timeout: Option<Duration>,
-    tls: bool,
-    tls_ca: Option<&String>,
-    tls_domain: Option<&String>,
-    token: Option<String>,
-) -> Result<DataTransferClientType> {
-    let (endpoint, interceptor) = build_endpoint(
-        addr,
-        concurrency_limit,
-        connect_timeout,
-        timeout,
-        tls,
-        tls_ca,
-        tls_domain,
-        token,
-    )?;
+#[tokio::test]
+#[allow(clippy::result_large_err)]
+async fn test_auth_header() {
+    use super::transferpb::data_transfer_server::DataTransferServer;
 
-    //Connect lazy
-    let channel = endpoint.connect_lazy();
+    //Captures the auth headers of every request
+    let captured = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let captured1 = captured.clone();
+    let service = InterceptedService::new(
+        DataTransferServer::new(TestService::new(Ok)),
+        move |req: Request<()>| {
+            let header = |key| {
+                req.metadata()
+                    .get(key)
+                    .map(|v: &MetadataValue<Ascii>| v.to_str().unwrap().to_string())
+            };
+            captured1
+                .lock()
+                .push((header("authorization"), header("x-api-key")));
+            Ok(req)
+        },
+    );
+    let addr = serve(tonic::transport::Server::builder().add_service(service))
+        .await
+        .unwrap();
 
-    //Client
-    Ok(DataTransferClient::with_interceptor(channel, interceptor))
+    let mut c = Client::new(addr.to_string())
+        .auth_token(Some("secret".into()))
+        .auth_header("x-api-key".into())
+        .auth_scheme(None)
+        .connect_lazy()
+        .unwrap();
+    c.send(vec![1]).await.unwrap();
+    let mut c = Client::new(addr.to_string())
+        .auth_token(Some("secret".into()))
+        .connect_lazy()
+        .unwrap();
+    c.send(vec![1]).await.unwrap();
+    let mut c = Client::new(addr.to_string())
+        .auth_token(Some("secret".into()))
+        .auth_scheme(Some("Token".into()))
+        .connect_lazy()
+        .unwrap();
+    c.send(vec![1]).await.unwrap();
+
+    assert_eq!(
+        captured.lock().clone(),
+        vec![
+            (None, Some("secret".to_string())),
+            (Some("Bearer secret".to_string()), None),
+            (Some("Token secret".to_string()), None),
+        ]
+    );
+    assert!(Client::new(addr.to_string())
+        .auth_header("bad header".into())
+        .validate()
+        .is_err());
 }
 
-#[allow(clippy::too_many_arguments)]
-#[inline]
-fn build_endpoint(
-    addr: &str,
-    concurrency_limit: usize,
-    connect_timeout: Option<Duration>,
-    timeout: Option<Duration>,
-    tls: bool,
-    tls_ca: Option<&String>,
-    tls_domain: Option<&String>,
-    token: Option<String>,
-) -> Result<(Endpoint, AuthInterceptor)> {
-    //TLS支持
-    let tls_client_cfg = if tls {
-        let mut tls_client_cfg = ClientTlsConfig::new();
-        if let Some(tls_ca) = tls_ca {
-            let pem = std::fs::read_to_string(tls_ca)?;
-            tls_client_cfg = tls_client_cfg.ca_certificate(Certificate::from_pem(pem));
-        }
-        if let Some(tls_domain) = tls_domain {
-            tls_client_cfg = tls_client_cfg.domain_name(tls_domain);
+#[tokio::test]
+async fn test_transfer_backpressure() {
+    use crate::server::server;
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, crate::server::Message>(1);
+    let addr = run_server(server(([127, 0, 0, 1], 0).into(), tx))
+        .await
+        .unwrap();
+    //The handler takes 50ms per message
+    tokio::spawn(async move {
+        while rx.next().await.is_some() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
         }
-        Some(tls_client_cfg)
-    } else {
-        None
-    };
+    });
 
-    //gRPC Auth
-    let auth_token = if let Some(token) = token {
-        if token.is_empty() {
-            return Err(Error::msg("auth token is empty"));
+    let mut mailbox = Client::new(addr.to_string())
+        .transfer_backpressure(true)
+        .connect_lazy()
+        .unwrap()
+        .transfer_start(1000)
+        .await;
+    let sent = Arc::new(AtomicUsize::new(0));
+    let sent1 = sent.clone();
+    let mut sender = mailbox.clone();
+    tokio::spawn(async move {
+        for _ in 0..200 {
+            sender.send(vec![0; 64 * 1024]).await.unwrap();
+            sent1.fetch_add(1, Ordering::SeqCst);
         }
-        Some(format!("Bearer {}", token).parse::<MetadataValue<_>>()?)
-    } else {
-        None
-    };
+    });
 
-    //Concurrency limit
-    let concurrency_limit = if concurrency_limit == 0 {
-        1
-    } else {
-        concurrency_limit
-    };
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    //The sender waits for the stream, the queue does not absorb the backlog
+    assert!(
+        mailbox.queue_len() <= 2,
+        "queue_len: {}",
+        mailbox.queue_len()
+    );
+    assert!(sent.load(Ordering::SeqCst) < 200);
+    let sent_before = sent.load(Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(sent.load(Ordering::SeqCst) > sent_before);
+    assert!(mailbox.queue_len() <= 2);
+    mailbox.send(vec![1]).await.unwrap();
+}
 
-    //Endpoint
-    let endpoint = Channel::from_shared(format!("http://{}", addr)).map(|endpoint| {
-        let mut endpoint = endpoint.concurrency_limit(concurrency_limit);
-        if let Some(connect_timeout) = connect_timeout {
-            endpoint = endpoint.connect_timeout(connect_timeout);
+#[tokio::test]
+async fn test_warm_up() {
+    use crate::server::{server, Message};
+
+    let (tx, _rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let laddr = run_server(server(([127, 0, 0, 1], 0).into(), tx))
+        .await
+        .unwrap();
+
+    let mut c = Client::new(laddr.to_string()).connect_lazy().unwrap();
+    c.warm_up().await.unwrap();
+
+    //Nothing listens on this port
+    let mut c = Client::new(unused_addr().to_string())
+        .connect_timeout(Duration::from_secs(1))
+        .connect_lazy()
+        .unwrap();
+    assert!(c.warm_up().await.is_err());
+}
+
+#[tokio::test]
+#[allow(clippy::result_large_err)]
+async fn test_interceptor() {
+    use super::transferpb::data_transfer_server::DataTransferServer;
+
+    //Captures the auth and trace headers of every request
+    let captured = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let captured1 = captured.clone();
+    let service = InterceptedService::new(
+        DataTransferServer::new(TestService::new(Ok)),
+        move |req: Request<()>| {
+            let header = |key| {
+                req.metadata()
+                    .get(key)
+                    .map(|v: &MetadataValue<Ascii>| v.to_str().unwrap().to_string())
+            };
+            captured1
+                .lock()
+                .push((header("authorization"), header("x-trace-id")));
+            Ok(req)
+        },
+    );
+    let addr = serve(tonic::transport::Server::builder().add_service(service))
+        .await
+        .unwrap();
+
+    let mut c = Client::new(addr.to_string())
+        .auth_token(Some("secret".into()))
+        .interceptor(|mut req: Request<()>| {
+            req.metadata_mut()
+                .insert("x-trace-id", MetadataValue::from_static("trace-1"));
+            Ok(req)
+        })
+        .connect_lazy()
+        .unwrap();
+    c.send(vec![1]).await.unwrap();
+    assert_eq!(
+        captured.lock().clone(),
+        vec![(
+            Some("Bearer secret".to_string()),
+            Some("trace-1".to_string())
+        )]
+    );
+
+    //An error from the interceptor fails the request before it is sent
+    let mut c = Client::new(addr.to_string())
+        .interceptor(|_| Err(Status::unauthenticated("no trace context")))
+        .connect_lazy()
+        .unwrap();
+    assert!(c.send(vec![1]).await.is_err());
+    assert_eq!(captured.lock().len(), 1);
+}
+
+#[tokio::test]
+async fn test_in_flight_messages() {
+    use crate::server::{server, Message};
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let laddr = run_server(server(([127, 0, 0, 1], 0).into(), tx))
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        //Hold on to the reply senders so no send ever finishes
+        let mut pending = Vec::new();
+        while let Some((_, (_, reply_tx))) = rx.next().await {
+            pending.push(reply_tx);
         }
-        if let Some(timeout) = timeout {
-            endpoint = endpoint.timeout(timeout);
+    });
+
+    for (limit, expected) in [(None, 3), (Some(2), 2)] {
+        let mut builder = Client::new(laddr.to_string()).chunk_size(4);
+        if let Some(limit) = limit {
+            builder = builder.max_inflight_messages(limit);
         }
-        if let Some(tls_client_cfg) = tls_client_cfg {
-            endpoint.tls_config(tls_client_cfg)
-        } else {
-            Ok(endpoint)
+        let c = builder.connect().await.unwrap();
+        let mut sends = (0..3)
+            .map(|_| {
+                let mut c = c.clone();
+                tokio::spawn(async move { c.send(vec![1; 9]).await })
+            })
+            .collect::<Vec<_>>();
+        //Unchunked sends are not counted
+        sends.push({
+            let mut c = c.clone();
+            tokio::spawn(async move { c.send(vec![1; 2]).await })
+        });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(c.in_flight_messages(), expected);
+
+        for send in sends {
+            send.abort();
         }
-    })??;
-    Ok((endpoint, AuthInterceptor { auth_token }))
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(c.in_flight_messages(), 0);
+    }
 }
 
-#[derive(Clone)]
-struct Receiver {
-    rx: Arc<RwLock<mpsc::Receiver<(Priority, Message)>>>,
+#[tokio::test]
+async fn test_mailbox_reserve() {
+    use futures::FutureExt;
+
+    //Nothing listens there, the queue is never drained
+    let mut mailbox = Client::new(unused_addr().to_string())
+        .chunk_size(4)
+        .connect_lazy()
+        .unwrap()
+        .transfer_start(4)
+        .await;
+    mailbox.try_send(vec![0]).unwrap();
+    assert_eq!(mailbox.chunk_count(12), 3);
+
+    let reservation = mailbox.reserve(3).unwrap();
+    assert!(mailbox.reserve(1).unwrap_err().is_full());
+    assert!(mailbox.try_send(vec![0]).unwrap_err().is_full());
+    assert!(mailbox.send(vec![0]).now_or_never().is_none());
+
+    //A message needing more chunks than reserved queues nothing and gives the slots back
+    let err = mailbox
+        .send_reserved(reservation, vec![1; 16], 0)
+        .unwrap_err();
+    assert!(err.is_full());
+    assert_eq!(mailbox.queue_len(), 1);
+
+    let reservation = mailbox.reserve(3).unwrap();
+    mailbox.send_reserved(reservation, vec![1; 12], 0).unwrap();
+    assert_eq!(mailbox.queue_len(), 4);
+    assert!(mailbox.reserve(1).unwrap_err().is_full());
 }
 
-impl Receiver {
-    fn new(rx: mpsc::Receiver<(Priority, Message)>) -> Self {
-        Receiver {
-            rx: Arc::new(RwLock::new(rx)),
+#[tokio::test]
+async fn test_default_priority() {
+    use crate::server::{server, Message};
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let laddr = run_server(server(([127, 0, 0, 1], 0).into(), tx))
+        .await
+        .unwrap();
+    let priorities = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let priorities1 = priorities.clone();
+    tokio::spawn(async move {
+        while let Some((p, (data, reply_tx))) = rx.next().await {
+            priorities1.lock().push(p);
+            if let Some(reply_tx) = reply_tx {
+                let _ = reply_tx.send(Ok(data));
+            }
         }
+    });
+
+    let mut c = Client::new(laddr.to_string())
+        .default_priority(5)
+        .connect()
+        .await
+        .unwrap();
+    c.send(vec![1]).await.unwrap();
+    c.send_priority(vec![1], 7).await.unwrap();
+    assert_eq!(priorities.lock().clone(), [5, 7]);
+
+    //Nothing listens there, the mailbox queue is never drained
+    let mut mailbox = Client::new(unused_addr().to_string())
+        .default_priority(5)
+        .connect_lazy()
+        .unwrap()
+        .transfer_start(10)
+        .await;
+    mailbox.send(vec![1]).await.unwrap();
+    assert_eq!(mailbox.queue.read().peek().map(|(p, _)| *p), Some(5));
+}
+
+#[tokio::test]
+async fn test_mailbox_try_reserve_bytes() {
+    //Nothing listens there, the queue is never drained
+    let mut mailbox = Client::new(unused_addr().to_string())
+        .chunk_size(4)
+        .max_queued_bytes(10)
+        .connect_lazy()
+        .unwrap()
+        .transfer_start(100)
+        .await;
+    mailbox.try_send(vec![0; 8]).unwrap();
+    assert_eq!(mailbox.queued_bytes(), 8);
+
+    assert!(!mailbox.try_reserve_bytes(3));
+    assert!(mailbox.try_reserve_bytes(2));
+    assert!(mailbox.try_send(vec![0; 3]).unwrap_err().is_full());
+    mailbox.try_send(vec![0; 2]).unwrap();
+    assert!(!mailbox.try_reserve_bytes(1));
+}
+
+#[tokio::test]
+async fn test_mailbox_queued_bytes() {
+    let c = Client::new(unused_addr().to_string())
+        .chunk_size(4)
+        .connect_lazy()
+        .unwrap();
+    let (mut mailbox, mut rx, queue) = c.mailbox(100);
+    mailbox.send(vec![0; 10]).await.unwrap();
+    mailbox.try_send(vec![0; 3]).unwrap();
+    assert_eq!(mailbox.queued_bytes(), 13);
+
+    //Taken by the transfer stream, then put back when the transfer fails
+    let msg = rx.next().await.unwrap();
+    assert_eq!(mailbox.queued_bytes(), 13 - payload_len(&msg));
+    rx.requeue(&queue);
+    assert_eq!(mailbox.queued_bytes(), 13);
+}
+
+#[test]
+fn test_inflight_window() {
+    let msg = |len| Message {
+        data: Some(vec![0; len]),
+        ..Default::default()
+    };
+    let mut inflight = Inflight::default();
+
+    //Empty and small messages are bounded by their count
+    for _ in 0..2 * INFLIGHT_WINDOW_MSGS {
+        inflight.push(0, msg(0));
+    }
+    assert_eq!(inflight.msgs.len(), INFLIGHT_WINDOW_MSGS);
+    assert_eq!(inflight.bytes, 0);
+    for _ in 0..2 * INFLIGHT_WINDOW_MSGS {
+        inflight.push(0, msg(16));
     }
+    assert_eq!(inflight.msgs.len(), INFLIGHT_WINDOW_MSGS);
+    assert_eq!(inflight.bytes, 16 * INFLIGHT_WINDOW_MSGS);
 
-    #[inline]
-    pub fn is_closed(&self) -> bool {
-        self.rx.read().is_closed()
+    //Large ones by their size, the latest is kept even when it exceeds the window alone
+    for _ in 0..3 {
+        inflight.push(0, msg(INFLIGHT_WINDOW_BYTES / 2));
     }
+    assert_eq!(inflight.msgs.len(), 2);
+    assert_eq!(inflight.bytes, INFLIGHT_WINDOW_BYTES);
+    inflight.push(0, msg(INFLIGHT_WINDOW_BYTES + 1));
+    assert_eq!(inflight.msgs.len(), 1);
+
+    assert!(INFLIGHT_WINDOW_MSGS * 10 <= crate::server::DELIVERED_WINDOW);
 }
 
-impl Stream for Receiver {
-    type Item = Message;
+#[tokio::test]
+async fn test_send_final_chunk_reply() {
+    use tonic::Response;
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match Pin::new(self.rx.write().deref_mut()).poll_next(cx) {
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(None) => Poll::Ready(None),
-            Poll::Ready(Some((_, msg))) => Poll::Ready(Some(msg)),
+    //Records the chunk indexes in arrival order and replies on the final chunk only
+    let chunk_indexes = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let chunk_indexes1 = chunk_indexes.clone();
+    let addr = TestService::new(Ok)
+        .on_send(move |_, request| {
+            let chunk_indexes = chunk_indexes1.clone();
+            async move {
+                let msg = request.into_inner();
+                //Early chunks are acknowledged in varying order
+                let delay = u64::from(msg.chunk_index % 3) * 10;
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                chunk_indexes.lock().push(msg.chunk_index);
+                let data = if msg.chunk_index + 1 == msg.total_chunks {
+                    Some(b"final".to_vec())
+                } else {
+                    None
+                };
+                Ok(Response::new(Message { data, ..msg }))
+            }
+        })
+        .spawn()
+        .await
+        .unwrap();
+
+    let mut c = Client::new(addr.to_string())
+        .chunk_size(4)
+        .max_inflight_chunks(4)
+        .connect_lazy()
+        .unwrap();
+    assert_eq!(c.send(vec![1; 30]).await.unwrap(), b"final");
+    let chunk_indexes = chunk_indexes.lock().clone();
+    assert_eq!(chunk_indexes.len(), 8);
+    assert_eq!(chunk_indexes.last(), Some(&7));
+}
+
+#[tokio::test]
+async fn test_no_chunking() {
+    use super::transferpb::data_transfer_server::DataTransferServer;
+    use tonic::Response;
+
+    const LIMIT: usize = 8 * 1024 * 1024;
+    //Records the chunk count of every message and echoes its payload
+    let total_chunks = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let total_chunks1 = total_chunks.clone();
+    let service = TestService::new(Ok).on_send(move |_, request| {
+        let msg = request.into_inner();
+        total_chunks1.lock().push(msg.total_chunks);
+        async move { Ok(Response::new(msg)) }
+    });
+    let addr = serve(
+        tonic::transport::Server::builder().add_service(
+            DataTransferServer::new(service)
+                .max_decoding_message_size(LIMIT)
+                .max_encoding_message_size(LIMIT),
+        ),
+    )
+    .await
+    .unwrap();
+
+    let mut c = Client::new(addr.to_string())
+        .no_chunking()
+        .max_decoding_message_size(LIMIT)
+        .max_encoding_message_size(LIMIT)
+        .connect_lazy()
+        .unwrap();
+    //Over the default 4MB limit, five chunks of the default chunk size
+    let data = vec![7; 5 * CHUNK_SIZE_LIMIT];
+    assert_eq!(c.send(data.clone()).await.unwrap(), data);
+    assert_eq!(*total_chunks.lock(), [0]);
+}
+
+#[tokio::test]
+async fn test_exchange() {
+    use crate::server::{server, Message};
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let laddr = run_server(server(([127, 0, 0, 1], 0).into(), tx).chunk_size(4))
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        while let Some((_, (data, reply_tx))) = rx.next().await {
+            //Later requests are answered first
+            tokio::spawn(async move {
+                let delay = 30 * u64::from(3 - data[0]);
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                let resp = data.iter().map(|b| b * 10).collect::<Vec<_>>();
+                if let Some(reply_tx) = reply_tx {
+                    reply_tx.send(Ok(resp)).unwrap();
+                }
+            });
+        }
+    });
+
+    let mut c = Client::new(laddr.to_string())
+        .chunk_size(4)
+        .connect()
+        .await
+        .unwrap();
+    let exchange = c.exchange().await.unwrap();
+    let (e1, e2, e3) = (exchange.clone(), exchange.clone(), exchange);
+    let (r1, r2, r3) = futures::join!(
+        e1.send(vec![1; 10]),
+        e2.send(vec![2]),
+        e3.send_priority(vec![3; 5], 1)
+    );
+    assert_eq!(r1.unwrap(), vec![10; 10]);
+    assert_eq!(r2.unwrap(), vec![20]);
+    assert_eq!(r3.unwrap(), vec![30; 5]);
+}
+
+#[tokio::test]
+async fn test_exchange_request_error() {
+    use crate::server::{server, Message};
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let laddr = run_server(server(([127, 0, 0, 1], 0).into(), tx).chunk_size(4))
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        while let Some((_, (data, reply_tx))) = rx.next().await {
+            tokio::spawn(async move {
+                //The failed request is answered first, the others are still pending
+                let delay = if data[0] == 2 { 0 } else { 30 };
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                let resp = if data[0] == 2 {
+                    Err(Error::msg("request 2 failed"))
+                } else {
+                    Ok(data.iter().map(|b| b * 10).collect::<Vec<_>>())
+                };
+                if let Some(reply_tx) = reply_tx {
+                    reply_tx.send(resp).unwrap();
+                }
+            });
         }
+    });
+
+    let mut c = Client::new(laddr.to_string())
+        .chunk_size(4)
+        .connect()
+        .await
+        .unwrap();
+    let exchange = c.exchange().await.unwrap();
+    let (e1, e2, e3) = (exchange.clone(), exchange.clone(), exchange.clone());
+    let (r1, r2, r3) = futures::join!(e1.send(vec![1; 10]), e2.send(vec![2; 10]), e3.send(vec![3]));
+    assert_eq!(r1.unwrap(), vec![10; 10]);
+    let status = r2.unwrap_err().downcast::<Status>().unwrap();
+    assert_eq!(status.code(), Code::Internal);
+    assert_eq!(status.message(), "request 2 failed");
+    assert_eq!(r3.unwrap(), vec![30]);
+
+    //The stream is still open after the failure
+    assert_eq!(exchange.send(vec![4]).await.unwrap(), vec![40]);
+}
+
+#[cfg(feature = "metrics")]
+#[tokio::test]
+async fn test_latency_snapshot() {
+    use crate::server::{server, Message};
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let laddr = run_server(server(([127, 0, 0, 1], 0).into(), tx))
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        while let Some((_, (data, reply_tx))) = rx.next().await {
+            tokio::time::sleep(Duration::from_millis(2)).await;
+            if let Some(reply_tx) = reply_tx {
+                reply_tx.send(Ok(data)).unwrap();
+            }
+        }
+    });
+
+    let mut c = Client::new(laddr.to_string())
+        .chunk_size(4)
+        .connect()
+        .await
+        .unwrap();
+    assert_eq!(c.latency_snapshot(), LatencySnapshot::default());
+    for i in 0..10 {
+        c.send(vec![i; i as usize + 1]).await.unwrap();
     }
+    let snapshot = c.clone().latency_snapshot();
+    assert_eq!(snapshot.count, 10);
+    assert!(snapshot.p50 >= Duration::from_millis(2));
+    assert!(snapshot.p50 <= snapshot.p90 && snapshot.p90 <= snapshot.p99);
+    assert!(snapshot.p99 < Duration::from_secs(5));
 }
 
-#[inline]
-pub(crate) fn next_id() -> Id {
-    use once_cell::sync::OnceCell;
-    use std::sync::atomic::{AtomicU64, Ordering};
-    static ID_GENERATOR: OnceCell<AtomicU64> = OnceCell::new();
-    let id_generator = ID_GENERATOR.get_or_init(|| AtomicU64::new(1));
-    id_generator.fetch_add(1, Ordering::SeqCst)
+#[cfg(feature = "metrics")]
+#[test]
+fn test_latency_histogram_buckets() {
+    for micros in [0, 1, 15, 16, 17, 18, 100, 1_000, 123_456, u64::MAX] {
+        let bucket = LatencyHistogram::bucket(micros);
+        assert!(bucket < LATENCY_BUCKETS);
+        let bound = LatencyHistogram::upper_bound(bucket);
+        assert!(micros <= bound);
+        assert!(bound - micros <= micros / 8);
+    }
 }
 
-#[inline]
-pub(crate) fn split_into_chunks(
-    data: &[u8],
-    p: Priority,
-    chunk_size: usize,
-) -> Vec<transferpb::Message> {
-    let id = next_id();
-    let chunks: Vec<_> = data.chunks(chunk_size).collect();
-    let total_chunks = chunks.len() as u32;
-    chunks
-        .into_iter()
-        .enumerate()
-        .map(|(i, chunk)| transferpb::Message {
-            id,
-            priority: p,
-            total_chunks,
-            chunk_index: i as u32,
-            data: Some(chunk.into()),
+#[tokio::test]
+async fn test_open_duplex() {
+    use crate::server::{server, DuplexMessage, Message};
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let (duplex_tx, mut duplex_rx) = mpsc::priority_channel::<Priority, DuplexMessage>(10);
+    let laddr = run_server(
+        server(([127, 0, 0, 1], 0).into(), tx)
+            .duplex_tx(duplex_tx)
+            .chunk_size(4),
+    )
+    .await
+    .unwrap();
+    tokio::spawn(async move {
+        //Greets the client, then pushes back every message it sends
+        let (_, mut push_tx) = duplex_rx.next().await.unwrap();
+        push_tx.send(b"hello".to_vec()).await.unwrap();
+        while let Some((_, (data, _))) = rx.next().await {
+            push_tx
+                .send(data.iter().map(|b| b + 1).collect())
+                .await
+                .unwrap();
+        }
+    });
+
+    let mut c = Client::new(laddr.to_string())
+        .chunk_size(4)
+        .connect()
+        .await
+        .unwrap();
+    let (mut mailbox, pushed) = c.open_duplex(10).await.unwrap();
+    let send = async {
+        for i in 0..3u8 {
+            mailbox.send(vec![i; 3 + 2 * i as usize]).await.unwrap();
+        }
+    };
+    let recv = pushed.take(4).map(|r| r.unwrap()).collect::<Vec<_>>();
+    let ((), mut pushed) = futures::join!(send, recv);
+    assert_eq!(pushed.remove(0), b"hello");
+    //Messages of the same priority may leave the mailbox in any order
+    pushed.sort();
+    assert_eq!(pushed, vec![vec![1; 3], vec![2; 5], vec![3; 7]]);
+}
+
+#[tokio::test]
+async fn test_error_status() {
+    use tonic::Code;
+
+    //Rejects every chunk with the same status
+    let addr = TestService::new(Ok)
+        .on_send(|_, _| async { Err(Status::failed_precondition("rejected")) })
+        .spawn()
+        .await
+        .unwrap();
+
+    let mut c = Client::new(addr.to_string())
+        .chunk_size(4)
+        .connect_lazy()
+        .unwrap();
+    let err = c.send(vec![1; 2]).await.unwrap_err();
+    let status = error_status(&err).unwrap();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+    assert_eq!(status.message(), "rejected");
+
+    //A chunked send fails with ChunkedSendFailed, which carries the status
+    let err = c.send(vec![1; 10]).await.unwrap_err();
+    assert!(err.downcast_ref::<ChunkedSendFailed>().is_some());
+    assert_eq!(error_status(&err).unwrap().code(), Code::FailedPrecondition);
+
+    assert!(error_status(&Error::msg("not a status")).is_none());
+}
+
+//Serves a handler replying with the reversed request, but fails the first request for chunk
+//`fail_chunk` of a message with `Unavailable`. With `lose_reply` the request is handled first, as if
+//the connection dropped before the response arrived. Returns the address, the requests the handler
+//received, the requests for `fail_chunk` and the server metrics.
+#[cfg(test)]
+async fn spawn_fail_once_server(
+    fail_chunk: u32,
+    lose_reply: bool,
+) -> (
+    std::net::SocketAddr,
+    Arc<parking_lot::Mutex<Vec<Vec<u8>>>>,
+    Arc<AtomicUsize>,
+    Arc<crate::server::ServerMetrics>,
+) {
+    use super::transferpb::data_transfer_server::DataTransfer;
+
+    let received = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let received1 = received.clone();
+    let failed = Arc::new(AtomicUsize::new(0));
+    let failed1 = failed.clone();
+    let service = TestService::new(move |data: Vec<u8>| {
+        received1.lock().push(data.clone());
+        Ok(data.into_iter().rev().collect())
+    })
+    .on_send(move |service, request| {
+        let failed = failed1.clone();
+        async move {
+            if request.get_ref().chunk_index != fail_chunk
+                || failed.fetch_add(1, Ordering::SeqCst) != 0
+            {
+                return service.send(request).await;
+            }
+            if lose_reply {
+                service.send(request).await?;
+            }
+            Err(Status::unavailable("injected failure"))
+        }
+    });
+    let metrics = service.metrics();
+    let addr = service.spawn().await.unwrap();
+    (addr, received, failed, metrics)
+}
+
+#[tokio::test]
+async fn test_retry_policy() {
+    //Fails the first attempt at the second chunk of a message
+    let (addr, received, second_chunks, metrics) = spawn_fail_once_server(1, false).await;
+
+    let mut c = Client::new(addr.to_string())
+        .chunk_size(4)
+        .retry_policy(RetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(10),
+            retryable: vec![tonic::Code::Unavailable],
         })
-        .collect()
+        .connect_lazy()
+        .unwrap();
+    let data = (0..10).collect::<Vec<u8>>();
+    let reversed = data.iter().rev().copied().collect::<Vec<u8>>();
+    assert_eq!(c.send(data.clone()).await.unwrap(), reversed);
+    assert_eq!(second_chunks.load(Ordering::SeqCst), 2);
+    //The retried first chunk was dropped as a duplicate, the message was handled once
+    assert_eq!(received.lock().clone(), vec![data]);
+    assert_eq!(metrics.stats().reassembly_buffers, 0);
 }
 
-const CHUNK_SIZE_LIMIT: usize = 1024 * 1024;
+#[tokio::test]
+async fn test_retry_policy_lost_reply() {
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        backoff: Duration::from_millis(10),
+        retryable: vec![tonic::Code::Unavailable],
+    };
+    let data = (0..10).collect::<Vec<u8>>();
+    let reversed = data.iter().rev().copied().collect::<Vec<u8>>();
+
+    //The handler replied but the response of the only request was lost
+    let (addr, received, attempts, _) = spawn_fail_once_server(0, true).await;
+    let mut c = Client::new(addr.to_string())
+        .retry_policy(policy.clone())
+        .connect_lazy()
+        .unwrap();
+    assert_eq!(c.send(data.clone()).await.unwrap(), reversed);
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    assert_eq!(received.lock().clone(), vec![data.clone()]);
+
+    //The same for the final chunk of a chunked message
+    let (addr, received, attempts, metrics) = spawn_fail_once_server(2, true).await;
+    let mut c = Client::new(addr.to_string())
+        .chunk_size(4)
+        .retry_policy(policy)
+        .connect_lazy()
+        .unwrap();
+    assert_eq!(c.send(data.clone()).await.unwrap(), reversed);
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    assert_eq!(received.lock().clone(), vec![data]);
+    assert_eq!(metrics.stats().reassembly_buffers, 0);
+}
@@ -5,9 +5,23 @@ pub mod transferpb {
     include!("transferpb.rs");
 }
 
+//Carried on the wire as a u32, larger values are sent first. For a composite key, order with
+//mpsc::priority_channel over any Ord type and map it onto a Priority before sending.
 pub type Priority = u32;
-pub(crate) type Id = u64;
+pub type Id = u64;
+
+//Metadata key identifying a transfer stream, lets the server drop messages replayed after a reconnect
+pub(crate) const TRANSFER_SESSION: &str = "transfer-session";
+//Metadata key marking a `Send` that asks the server to drop the chunks buffered for its message ID
+pub(crate) const TRANSFER_ABORT: &str = "transfer-abort";
+//Tag of an `Exchange` response reporting that its request failed, `chunk_index` carries the status
+//code and `data` the status message
+pub(crate) const EXCHANGE_ERROR_TAG: &str = "exchange-error";
+#[cfg(feature = "blocking")]
+pub mod blocking;
 pub mod client;
 pub mod server;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 
 pub use anyhow::{Error, Result};
@@ -1,53 +1,132 @@
 use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
 use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use futures::channel::oneshot;
-use futures::StreamExt;
-use tonic::metadata::{Ascii, MetadataValue};
+use futures::{SinkExt, Stream, StreamExt};
+use tonic::metadata::{Ascii, MetadataMap, MetadataValue};
 use tonic::service::{interceptor::InterceptedService, Interceptor};
+use tonic::transport::server::{Router, TcpIncoming};
 use tonic::transport::{self, Identity, ServerTlsConfig};
 use tonic::{Request, Response, Status};
 
 use anyhow::{Error, Result};
-use collections::PriorityQueue;
 use dequemap::DequeBTreeMap;
-use tokio::sync::RwLock;
+use tokio::sync::{watch, OwnedSemaphorePermit, RwLock, Semaphore};
 
 #[cfg(feature = "rate")]
 use rate::Counter;
 
+use super::client::{next_id, split_into_chunks, CHUNK_SIZE_LIMIT};
+#[cfg(test)]
+use super::test_util::run_server;
 use super::transferpb::data_transfer_server::{DataTransfer, DataTransferServer};
 use super::transferpb::{self, Empty};
-use super::{Id, Priority};
+use super::{Id, Priority, EXCHANGE_ERROR_TAG, TRANSFER_ABORT, TRANSFER_SESSION};
 
 type TX = mpsc::Sender<(Priority, Message), mpsc::SendError<(Priority, Message)>>;
+type StreamTX = mpsc::Sender<(Priority, StreamMessage), mpsc::SendError<(Priority, StreamMessage)>>;
+type ExchangeTX = futures::channel::mpsc::Sender<Result<transferpb::Message, Status>>;
+type DuplexTX = mpsc::Sender<(Priority, DuplexMessage), mpsc::SendError<(Priority, DuplexMessage)>>;
 
 pub type Message = (Vec<u8>, Option<oneshot::Sender<Result<Vec<u8>>>>);
 
+///A request served by `SendStream`, every response sent on the channel is streamed back to the
+///client, the stream ends when the sender is dropped.
+pub type StreamMessage = (Vec<u8>, futures::channel::mpsc::Sender<Result<Vec<u8>>>);
+
+///A `Duplex` stream opened by a client, everything sent on it is pushed to the client, the
+///stream ends when the sender is dropped.
+pub type DuplexMessage = futures::channel::mpsc::Sender<Vec<u8>>;
+
+type EventSink = Arc<dyn Fn(&ServerEvent) + Send + Sync>;
+//The reply of a `send` on a session, `None` until the handler replied
+type ReplyTx = watch::Sender<Option<Result<Vec<u8>, Status>>>;
+type ReplyRx = watch::Receiver<Option<Result<Vec<u8>, Status>>>;
+
+//What was recently received on transfer sessions
+#[derive(Default)]
+struct Delivered {
+    //(session, message ID, chunk index) of the chunks
+    chunks: DequeBTreeMap<(u64, Id, u32), ()>,
+    //(session, message ID) of the messages completed by a `send`, with their replies
+    replies: DequeBTreeMap<(u64, Id), ReplyRx>,
+}
+
+impl Delivered {
+    #[inline]
+    fn insert_chunk(&mut self, session: u64, req: &transferpb::Message) -> bool {
+        if self
+            .chunks
+            .contains_key(&(session, req.id, req.chunk_index))
+        {
+            return false;
+        }
+        self.chunks
+            .push_back((session, req.id, req.chunk_index), ());
+        while self.chunks.len() > DELIVERED_WINDOW {
+            self.chunks.pop_front();
+        }
+        true
+    }
+}
+
+//How a chunk received by `send` on a session is served
+enum SendDelivery {
+    //A new chunk, with the reply slot of its message if it is the final chunk
+    New(Option<ReplyTx>),
+    //A replayed chunk that is not the final one
+    Replayed,
+    //A replayed final chunk, with the reply slot of its message if it is still remembered
+    ReplayedFinal(Option<ReplyRx>),
+}
+
 pub struct Server {
     laddr: SocketAddr,
     tx: TX,
+    tag_txs: HashMap<String, TX>,
+    stream_tx: Option<StreamTX>,
+    duplex_tx: Option<DuplexTX>,
     tls: Option<TLS>,
     token: Option<String>,
     recv_chunks_timeout: Duration,
+    chunk_size: usize,
+    response_metadata: MetadataMap,
     max_decoding_message_size: Option<usize>,
     max_encoding_message_size: Option<usize>,
     reuseaddr: bool,
     reuseport: bool,
+    slow_consumer_threshold: Duration,
+    event_sink: Option<EventSink>,
+    metrics: Arc<ServerMetrics>,
+    reassemblies: Reassemblies,
 }
 
 pub fn server(laddr: SocketAddr, tx: TX) -> Server {
     Server {
         laddr,
         tx,
+        tag_txs: HashMap::new(),
+        stream_tx: None,
+        duplex_tx: None,
         tls: None,
         token: None,
         recv_chunks_timeout: RECV_CHUNKS_TIMEOUT,
+        chunk_size: CHUNK_SIZE_LIMIT,
+        response_metadata: MetadataMap::new(),
         max_decoding_message_size: None,
         max_encoding_message_size: None,
         reuseaddr: true,
         reuseport: false,
+        slow_consumer_threshold: SLOW_CONSUMER_THRESHOLD,
+        event_sink: None,
+        metrics: Arc::new(ServerMetrics::default()),
+        reassemblies: Reassemblies::new(),
     }
 }
 
@@ -67,6 +146,40 @@ impl Server {
         self
     }
 
+    ///Delivers the messages tagged with `tag` to `tx` instead of the default channel, see
+    ///`Client::send_tagged`. Messages with an unknown tag or without one go to the default channel.
+    pub fn tag_tx(mut self, tag: String, tx: TX) -> Self {
+        self.tag_txs.insert(tag, tx);
+        self
+    }
+
+    ///Enables `SendStream`, requests are delivered to `stream_tx` together with a sender for the
+    ///responses. Without it `SendStream` is answered with `Unimplemented`.
+    pub fn stream_tx(mut self, stream_tx: StreamTX) -> Self {
+        self.stream_tx = Some(stream_tx);
+        self
+    }
+
+    ///Enables `Duplex`, the messages a client sends on it are delivered like those of a transfer
+    ///and a sender for pushing messages to the client is delivered to `duplex_tx` when it opens.
+    ///Without it `Duplex` is answered with `Unimplemented`.
+    pub fn duplex_tx(mut self, duplex_tx: DuplexTX) -> Self {
+        self.duplex_tx = Some(duplex_tx);
+        self
+    }
+
+    ///Streamed, exchanged and pushed responses larger than `chunk_size` are split into chunks.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    ///Metadata sent with every `Send` response, e.g. headers identifying the serving node.
+    pub fn response_metadata(mut self, response_metadata: MetadataMap) -> Self {
+        self.response_metadata = response_metadata;
+        self
+    }
+
     pub fn max_decoding_message_size(mut self, max_decoding_message_size: usize) -> Self {
         self.max_decoding_message_size = Some(max_decoding_message_size);
         self
@@ -87,7 +200,70 @@ impl Server {
         self
     }
 
+    ///A message waiting longer than `threshold` for room in the handler channel means the
+    ///handler can not keep up, which raises `ServerEvent::SlowConsumer`.
+    pub fn slow_consumer_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_consumer_threshold = threshold;
+        self
+    }
+
+    ///Passes server events to `sink` instead of logging them.
+    pub fn event_sink<F>(mut self, sink: F) -> Self
+    where
+        F: Fn(&ServerEvent) + Send + Sync + 'static,
+    {
+        self.event_sink = Some(Arc::new(sink));
+        self
+    }
+
+    ///The metrics of the server, updated while it runs.
+    pub fn metrics(&self) -> Arc<ServerMetrics> {
+        self.metrics.clone()
+    }
+
+    ///The chunked messages the server is reassembling, readable while it runs.
+    pub fn reassemblies(&self) -> Reassemblies {
+        self.reassemblies.clone()
+    }
+
     pub async fn run(self) -> Result<()> {
+        let laddr = self.laddr;
+        #[cfg(any(feature = "reuseport", feature = "reuseaddr"))]
+        let (reuseaddr, reuseport) = (self.reuseaddr, self.reuseport);
+        let (server, protocol) = self.into_router()?;
+        log::info!(
+            "gRPC DataTransfer is listening on {}://{:?}",
+            protocol,
+            laddr
+        );
+
+        #[cfg(any(feature = "reuseport", feature = "reuseaddr"))]
+        #[cfg(all(feature = "socket2", feature = "tokio-stream"))]
+        {
+            let listener = socket2_bind(laddr, 1024, reuseaddr, reuseport)?;
+            server.serve_with_incoming(listener).await?;
+        }
+        #[cfg(not(any(feature = "reuseport", feature = "reuseaddr")))]
+        server.serve(laddr).await?;
+
+        Ok(())
+    }
+
+    ///Serves on an already bound listener instead of binding `laddr`, e.g. one bound to port 0.
+    pub async fn run_with_listener(self, listener: tokio::net::TcpListener) -> Result<()> {
+        let laddr = listener.local_addr()?;
+        let (server, protocol) = self.into_router()?;
+        log::info!(
+            "gRPC DataTransfer is listening on {}://{:?}",
+            protocol,
+            laddr
+        );
+        let incoming = TcpIncoming::from_listener(listener, false, None).map_err(Error::msg)?;
+        server.serve_with_incoming(incoming).await?;
+        Ok(())
+    }
+
+    fn into_router(self) -> Result<(Router, &'static str)> {
         let mut builder = transport::Server::builder();
 
         //Check for TLS and generate an identity.
@@ -118,8 +294,20 @@ impl Server {
             None
         };
 
-        let mut service =
-            DataTransferServer::new(DataTransferService::new(self.tx, self.recv_chunks_timeout));
+        let mut transfer_service = DataTransferService::with_state(
+            self.tx,
+            self.recv_chunks_timeout,
+            self.metrics,
+            self.reassemblies,
+        );
+        transfer_service.tag_txs = self.tag_txs;
+        transfer_service.stream_tx = self.stream_tx;
+        transfer_service.duplex_tx = self.duplex_tx;
+        transfer_service.chunk_size = self.chunk_size;
+        transfer_service.response_metadata = self.response_metadata;
+        transfer_service.slow_consumer_threshold = self.slow_consumer_threshold;
+        transfer_service.event_sink = self.event_sink;
+        let mut service = DataTransferServer::new(transfer_service);
         if let Some(limit) = self.max_decoding_message_size {
             service = service.max_decoding_message_size(limit);
         }
@@ -129,36 +317,44 @@ impl Server {
 
         let service = InterceptedService::new(service, AuthInterceptor { auth_token });
 
-        log::info!(
-            "gRPC DataTransfer is listening on {}://{:?}",
-            protocol,
-            self.laddr
-        );
-
-        let server = builder.add_service(service);
-
-        #[cfg(any(feature = "reuseport", feature = "reuseaddr"))]
-        #[cfg(all(feature = "socket2", feature = "tokio-stream"))]
-        {
-            let listener = socket2_bind(self.laddr, 1024, self.reuseaddr, self.reuseport)?;
-            server.serve_with_incoming(listener).await?;
-        }
-        #[cfg(not(any(feature = "reuseport", feature = "reuseaddr")))]
-        server.serve(self.laddr).await?;
-
-        Ok(())
+        Ok((builder.add_service(service), protocol))
     }
 }
 
+//Clones share the state, an `Exchange` stream is served by a clone on its own task
+#[derive(Clone)]
 pub struct DataTransferService {
     #[cfg(feature = "rate")]
     counter: Counter,
     tx: TX,
+    tag_txs: HashMap<String, TX>,
+    stream_tx: Option<StreamTX>,
+    duplex_tx: Option<DuplexTX>,
+    chunk_size: usize,
+    response_metadata: MetadataMap,
     chunked_buffer: ChunkedBuffer,
+    delivered: Arc<RwLock<Delivered>>,
+    slow_consumer_threshold: Duration,
+    event_sink: Option<EventSink>,
+    metrics: Arc<ServerMetrics>,
 }
 
 impl DataTransferService {
     pub fn new(tx: TX, recv_chunks_timeout: Duration) -> Self {
+        Self::with_state(
+            tx,
+            recv_chunks_timeout,
+            Arc::new(ServerMetrics::default()),
+            Reassemblies::new(),
+        )
+    }
+
+    fn with_state(
+        tx: TX,
+        recv_chunks_timeout: Duration,
+        metrics: Arc<ServerMetrics>,
+        reassemblies: Reassemblies,
+    ) -> Self {
         #[cfg(feature = "rate")]
         let counter = Counter::new(std::time::Duration::from_secs(5));
         #[cfg(feature = "rate_print")]
@@ -175,51 +371,312 @@ impl DataTransferService {
             #[cfg(feature = "rate")]
             counter,
             tx,
-            chunked_buffer: ChunkedBuffer::new(recv_chunks_timeout),
+            tag_txs: HashMap::new(),
+            stream_tx: None,
+            duplex_tx: None,
+            chunk_size: CHUNK_SIZE_LIMIT,
+            response_metadata: MetadataMap::new(),
+            chunked_buffer: ChunkedBuffer::new(recv_chunks_timeout, metrics.clone(), reassemblies),
+            delivered: Arc::new(RwLock::new(Delivered::default())),
+            slow_consumer_threshold: SLOW_CONSUMER_THRESHOLD,
+            event_sink: None,
+            metrics,
+        }
+    }
+
+    ///The metrics of the service, updated while it serves.
+    pub fn metrics(&self) -> Arc<ServerMetrics> {
+        self.metrics.clone()
+    }
+
+    ///The chunked messages the service is reassembling.
+    pub fn reassemblies(&self) -> Reassemblies {
+        Reassemblies {
+            data_buffses: self.chunked_buffer.data_buffses.clone(),
+        }
+    }
+
+    ///The handler channel of messages tagged with `tag`.
+    #[inline]
+    fn tx(&self, tag: Option<&str>) -> TX {
+        tag.and_then(|tag| self.tag_txs.get(tag))
+            .unwrap_or(&self.tx)
+            .clone()
+    }
+
+    ///Passes a message to the handler channel. When the channel stays full for longer than the
+    ///slow consumer threshold `ServerEvent::SlowConsumer` is raised, once until a message gets
+    ///through in time again.
+    #[inline]
+    async fn deliver<M, E: ToString>(
+        &self,
+        tx: &mut mpsc::Sender<M, E>,
+        msg: M,
+    ) -> Result<(), Status> {
+        let now = Instant::now();
+        tx.send(msg)
+            .await
+            .map_err(|e| Status::cancelled(e.to_string()))?;
+        let waited = now.elapsed();
+        if waited < self.slow_consumer_threshold {
+            self.metrics.slow.store(false, Ordering::Relaxed);
+        } else if !self.metrics.slow.swap(true, Ordering::Relaxed) {
+            self.metrics.slow_consumers.fetch_add(1, Ordering::Relaxed);
+            self.emit(ServerEvent::SlowConsumer { waited });
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn emit(&self, event: ServerEvent) {
+        match &self.event_sink {
+            Some(sink) => sink(&event),
+            None => log::warn!("{}", event),
+        }
+    }
+
+    ///Records a chunk received on a transfer session, returns false if it was already received,
+    ///which happens when the client replays its in-flight messages after a reconnect.
+    #[inline]
+    async fn mark_delivered(&self, session: u64, req: &transferpb::Message) -> bool {
+        self.delivered.write().await.insert_chunk(session, req)
+    }
+
+    ///Records a chunk received by `send` on a session like `mark_delivered`. The final chunk of a
+    ///message also gets a slot for the reply, a retried send whose response was lost is answered
+    ///from it instead of with an empty reply.
+    #[inline]
+    async fn mark_send_delivered(&self, session: u64, req: &transferpb::Message) -> SendDelivery {
+        let is_final = req.chunk_index + 1 >= req.total_chunks;
+        let mut delivered = self.delivered.write().await;
+        if !delivered.insert_chunk(session, req) {
+            return if is_final {
+                SendDelivery::ReplayedFinal(delivered.replies.get(&(session, req.id)).cloned())
+            } else {
+                SendDelivery::Replayed
+            };
+        }
+        if !is_final {
+            return SendDelivery::New(None);
+        }
+        let (reply_tx, reply_rx) = watch::channel(None);
+        delivered.replies.push_back((session, req.id), reply_rx);
+        while delivered.replies.len() > REPLY_WINDOW {
+            delivered.replies.pop_front();
+        }
+        SendDelivery::New(Some(reply_tx))
+    }
+
+    ///Answers a retried send with the reply to its first attempt, waiting for the handler if it
+    ///has not replied yet.
+    async fn replay_reply(
+        &self,
+        id: Id,
+        reply_rx: Option<ReplyRx>,
+    ) -> Result<Response<transferpb::Message>, Status> {
+        let Some(mut reply_rx) = reply_rx else {
+            return Err(Status::already_exists(format!(
+                "message {} was already received, its reply is no longer available",
+                id
+            )));
+        };
+        log::debug!(
+            "Answer the retried message with its reply, message ID: {}",
+            id
+        );
+        let reply = reply_rx
+            .wait_for(Option::is_some)
+            .await
+            .map(|reply| reply.clone());
+        match reply {
+            Ok(Some(Ok(data))) => Ok(self.reply(id, data)),
+            Ok(Some(Err(status))) => Err(status),
+            //The first attempt failed before the handler replied
+            _ => Err(Status::aborted(format!(
+                "message {} failed on its first attempt",
+                id
+            ))),
+        }
+    }
+
+    ///Delivers the messages of a transfer or duplex stream to the handler channels without replies.
+    async fn receive(
+        &self,
+        mut stream: tonic::Streaming<transferpb::Message>,
+        remote_addr: Option<SocketAddr>,
+        session: Option<u64>,
+    ) -> Result<(), Status> {
+        while let Some(req) = stream.next().await {
+            log::trace!("Request: {:?}", req);
+            let req = req?;
+
+            if let Some(session) = session {
+                if !self.mark_delivered(session, &req).await {
+                    log::debug!(
+                        "Drop the replayed message, message ID: {}, chunk index: {}",
+                        req.id,
+                        req.chunk_index
+                    );
+                    continue;
+                }
+            }
+
+            let mut tx = self.tx(req.tag.as_deref());
+            let (priority, data) = match self.chunked_buffer.merge(req, remote_addr, session).await
+            {
+                Ok(Some((priority, data))) => (priority, data),
+                Ok(None) => continue,
+                Err(status) => {
+                    //A transfer has no reply to fail, the message is dropped
+                    log::warn!("{}", status.message());
+                    continue;
+                }
+            };
+
+            #[cfg(feature = "rate")]
+            self.counter.inc();
+            self.metrics.received(data.len());
+
+            self.deliver(&mut tx, (priority, (data, None))).await?;
+        }
+        Ok(())
+    }
+
+    ///Serves the requests of an `Exchange` stream. A request is handed to the handler once all its
+    ///chunks arrived and its response is sent back with the request id as soon as the handler
+    ///replies, without waiting for earlier requests. An invalid request or a failed handler is
+    ///answered by an error response with the request id, only a failed stream ends the others.
+    async fn exchange_requests(
+        self,
+        mut stream: tonic::Streaming<transferpb::Message>,
+        remote_addr: Option<SocketAddr>,
+        session: Option<u64>,
+        mut res_tx: ExchangeTX,
+    ) {
+        while let Some(req) = stream.next().await {
+            log::trace!("Request: {:?}", req);
+            let req = match req {
+                Ok(req) => req,
+                Err(status) => {
+                    let _ = res_tx.send(Err(status)).await;
+                    return;
+                }
+            };
+
+            if let Some(session) = session {
+                if !self.mark_delivered(session, &req).await {
+                    log::debug!(
+                        "Drop the replayed message, message ID: {}, chunk index: {}",
+                        req.id,
+                        req.chunk_index
+                    );
+                    continue;
+                }
+            }
+
+            let id = req.id;
+            let mut tx = self.tx(req.tag.as_deref());
+            let (priority, data) = match self.chunked_buffer.merge(req, remote_addr, session).await
+            {
+                Ok(Some((priority, data))) => (priority, data),
+                Ok(None) => continue,
+                Err(status) => {
+                    if res_tx.send(Ok(error_message(id, status))).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            #[cfg(feature = "rate")]
+            self.counter.inc();
+            self.metrics.received(data.len());
+
+            let (reply_tx, reply_rx) = oneshot::channel();
+            if let Err(status) = self
+                .deliver(&mut tx, (priority, (data, Some(reply_tx))))
+                .await
+            {
+                if res_tx.send(Ok(error_message(id, status))).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+
+            let metrics = self.metrics.clone();
+            let chunk_size = self.chunk_size;
+            let mut res_tx = res_tx.clone();
+            tokio::spawn(async move {
+                let now = Instant::now();
+                let msgs = match reply_rx.await {
+                    Ok(res) => {
+                        metrics.handled(now.elapsed());
+                        match res {
+                            Ok(data) => reply_messages(id, data, chunk_size),
+                            Err(e) => vec![error_message(id, Status::internal(e.to_string()))],
+                        }
+                    }
+                    Err(e) => vec![error_message(id, Status::cancelled(e.to_string()))],
+                };
+                for msg in msgs {
+                    if res_tx.send(Ok(msg)).await.is_err() {
+                        break;
+                    }
+                }
+            });
         }
     }
 
     #[inline]
-    fn chunk_empty_result() -> Response<transferpb::Message> {
+    fn chunk_empty_result(&self) -> Response<transferpb::Message> {
         let resp = transferpb::Message {
             id: 0,
             priority: 0,
             total_chunks: 0,
             chunk_index: 0,
             data: None,
+            tag: None,
         };
-        Response::new(resp)
+        self.response(resp)
+    }
+
+    #[inline]
+    fn reply(&self, id: Id, data: Vec<u8>) -> Response<transferpb::Message> {
+        self.response(transferpb::Message {
+            id,
+            priority: 0,
+            total_chunks: 0,
+            chunk_index: 0,
+            data: Some(data),
+            tag: None,
+        })
+    }
+
+    #[inline]
+    fn response(&self, msg: transferpb::Message) -> Response<transferpb::Message> {
+        let mut resp = Response::new(msg);
+        *resp.metadata_mut() = self.response_metadata.clone();
+        resp
     }
 }
 
 #[tonic::async_trait]
 impl DataTransfer for DataTransferService {
+    type SendStreamStream =
+        Pin<Box<dyn Stream<Item = Result<transferpb::Message, Status>> + Send + 'static>>;
+    type ExchangeStream =
+        Pin<Box<dyn Stream<Item = Result<transferpb::Message, Status>> + Send + 'static>>;
+    type DuplexStream =
+        Pin<Box<dyn Stream<Item = Result<transferpb::Message, Status>> + Send + 'static>>;
+
     #[inline]
     async fn transfer(
         &self,
         request: Request<tonic::Streaming<transferpb::Message>>,
     ) -> Result<Response<Empty>, Status> {
-        let remote_addr = request.remote_addr();
-        let mut tx = self.tx.clone();
-        let mut stream = request.into_inner();
-        while let Some(req) = stream.next().await {
-            log::trace!("Request: {:?}", req);
-            let req = req?;
-
-            let (priority, data) =
-                if let Some((priority, data)) = self.chunked_buffer.merge(req, remote_addr).await {
-                    (priority, data)
-                } else {
-                    continue;
-                };
-
-            #[cfg(feature = "rate")]
-            self.counter.inc();
-
-            tx.send((priority, (data, None)))
-                .await
-                .map_err(|e| Status::cancelled(e.to_string()))?;
-        }
+        let (remote_addr, session) = chunk_source(&request);
+        self.receive(request.into_inner(), remote_addr, session)
+            .await?;
         log::trace!("Response Empty");
         Ok(Response::new(Empty {}))
     }
@@ -229,39 +686,414 @@ impl DataTransfer for DataTransferService {
         &self,
         request: Request<transferpb::Message>,
     ) -> Result<Response<transferpb::Message>, Status> {
-        let remote_addr = request.remote_addr();
+        let (remote_addr, session) = chunk_source(&request);
+        let abort = request.metadata().contains_key(TRANSFER_ABORT);
         let req = request.into_inner();
         log::trace!("Request: {:?}", req);
         let id = req.id;
-        let (priority, data) =
-            if let Some((priority, data)) = self.chunked_buffer.merge(req, remote_addr).await {
-                (priority, data)
-            } else {
-                return Ok(Self::chunk_empty_result());
-            };
+        if abort {
+            if self.chunked_buffer.discard(remote_addr, session, id).await {
+                log::debug!("Discard the aborted message, message ID: {}", id);
+            }
+            return Ok(self.chunk_empty_result());
+        }
+        let mut reply_tx = None;
+        if let Some(session) = session {
+            match self.mark_send_delivered(session, &req).await {
+                SendDelivery::New(tx) => reply_tx = tx,
+                SendDelivery::Replayed => {
+                    log::debug!(
+                        "Drop the resent chunk, message ID: {}, chunk index: {}",
+                        req.id,
+                        req.chunk_index
+                    );
+                    return Ok(self.chunk_empty_result());
+                }
+                SendDelivery::ReplayedFinal(reply_rx) => {
+                    return self.replay_reply(id, reply_rx).await
+                }
+            }
+        }
+        let mut tx = self.tx(req.tag.as_deref());
+        let (priority, data) = if let Some((priority, data)) =
+            self.chunked_buffer.merge(req, remote_addr, session).await?
+        {
+            (priority, data)
+        } else {
+            return Ok(self.chunk_empty_result());
+        };
 
         #[cfg(feature = "rate")]
         self.counter.inc();
+        self.metrics.received(data.len());
 
-        let mut tx = self.tx.clone();
         let (res_tx, res_rx) = oneshot::channel();
-        tx.send((priority, (data, Some(res_tx))))
-            .await
-            .map_err(|e| Status::cancelled(e.to_string()))?;
+        self.deliver(&mut tx, (priority, (data, Some(res_tx))))
+            .await?;
 
-        let res = res_rx
-            .await
-            .map_err(|e| Status::cancelled(e.to_string()))?
-            .map_err(|e| Status::internal(e.to_string()))?;
+        let now = Instant::now();
+        let res = res_rx.await.map_err(|e| Status::cancelled(e.to_string()))?;
+        self.metrics.handled(now.elapsed());
+        let res = res.map_err(|e| Status::internal(e.to_string()));
+        if let Some(reply_tx) = reply_tx {
+            reply_tx.send_replace(Some(res.clone()));
+        }
+        Ok(self.reply(id, res?))
+    }
 
-        let resp = transferpb::Message {
+    #[inline]
+    async fn send_stream(
+        &self,
+        request: Request<transferpb::Message>,
+    ) -> Result<Response<Self::SendStreamStream>, Status> {
+        let mut stream_tx = self
+            .stream_tx
+            .clone()
+            .ok_or_else(|| Status::unimplemented("SendStream is not enabled"))?;
+        let remote_addr = request.remote_addr();
+        let req = request.into_inner();
+        log::trace!("Request: {:?}", req);
+        let id = req.id;
+        let (priority, data) = if let Some((priority, data)) =
+            self.chunked_buffer.merge(req, remote_addr, None).await?
+        {
+            (priority, data)
+        } else {
+            return Err(Status::invalid_argument(format!(
+                "incomplete chunked message, message ID: {}",
+                id
+            )));
+        };
+
+        #[cfg(feature = "rate")]
+        self.counter.inc();
+        self.metrics.received(data.len());
+
+        let (res_tx, res_rx) = futures::channel::mpsc::channel(STREAM_RESPONSE_BUFFER);
+        self.deliver(&mut stream_tx, (priority, (data, res_tx)))
+            .await?;
+
+        let chunk_size = self.chunk_size;
+        let resp = res_rx.flat_map(move |res| {
+            let msgs = match res {
+                Ok(data) => reply_messages(next_id(), data, chunk_size)
+                    .into_iter()
+                    .map(Ok)
+                    .collect(),
+                Err(e) => vec![Err(Status::internal(e.to_string()))],
+            };
+            futures::stream::iter(msgs)
+        });
+        Ok(Response::new(Box::pin(resp)))
+    }
+
+    #[inline]
+    async fn progress(
+        &self,
+        request: Request<transferpb::Message>,
+    ) -> Result<Response<transferpb::Message>, Status> {
+        let (remote_addr, session) = chunk_source(&request);
+        let id = request.into_inner().id;
+        let (chunk_index, total_chunks) =
+            self.chunked_buffer.progress(remote_addr, session, id).await;
+        Ok(self.response(transferpb::Message {
+            id,
+            priority: 0,
+            total_chunks,
+            chunk_index,
+            data: None,
+            tag: None,
+        }))
+    }
+
+    #[inline]
+    async fn exchange(
+        &self,
+        request: Request<tonic::Streaming<transferpb::Message>>,
+    ) -> Result<Response<Self::ExchangeStream>, Status> {
+        let (remote_addr, session) = chunk_source(&request);
+        let stream = request.into_inner();
+        let (res_tx, res_rx) = futures::channel::mpsc::channel(STREAM_RESPONSE_BUFFER);
+        tokio::spawn(
+            self.clone()
+                .exchange_requests(stream, remote_addr, session, res_tx),
+        );
+        Ok(Response::new(Box::pin(res_rx)))
+    }
+
+    #[inline]
+    async fn duplex(
+        &self,
+        request: Request<tonic::Streaming<transferpb::Message>>,
+    ) -> Result<Response<Self::DuplexStream>, Status> {
+        let mut duplex_tx = self
+            .duplex_tx
+            .clone()
+            .ok_or_else(|| Status::unimplemented("Duplex is not enabled"))?;
+        let (remote_addr, session) = chunk_source(&request);
+        let stream = request.into_inner();
+
+        let (push_tx, push_rx) = futures::channel::mpsc::channel(STREAM_RESPONSE_BUFFER);
+        self.deliver(&mut duplex_tx, (Priority::MIN, push_tx))
+            .await?;
+
+        let service = self.clone();
+        tokio::spawn(async move {
+            if let Err(status) = service.receive(stream, remote_addr, session).await {
+                log::warn!("Duplex receive failed, {}", status);
+            }
+        });
+
+        let chunk_size = self.chunk_size;
+        let resp = push_rx.flat_map(move |data| {
+            let msgs = reply_messages(next_id(), data, chunk_size);
+            futures::stream::iter(msgs.into_iter().map(Ok))
+        });
+        Ok(Response::new(Box::pin(resp)))
+    }
+}
+
+//A response, split into chunks when it is larger than `chunk_size`
+#[inline]
+fn reply_messages(id: Id, data: Vec<u8>, chunk_size: usize) -> Vec<transferpb::Message> {
+    if data.len() > chunk_size {
+        split_into_chunks(data.as_slice(), id, 0, chunk_size)
+    } else {
+        vec![transferpb::Message {
             id,
             priority: 0,
             total_chunks: 0,
             chunk_index: 0,
-            data: Some(res),
-        };
-        Ok(Response::new(resp))
+            data: Some(data),
+            tag: None,
+        }]
+    }
+}
+
+//Reports the failure of one request of an `Exchange` stream without ending the stream
+#[inline]
+fn error_message(id: Id, status: Status) -> transferpb::Message {
+    transferpb::Message {
+        id,
+        priority: 0,
+        total_chunks: 0,
+        chunk_index: status.code() as u32,
+        data: Some(status.message().as_bytes().to_vec()),
+        tag: Some(EXCHANGE_ERROR_TAG.into()),
+    }
+}
+
+//The chunks of a request are merged per connection, or per session when the client sets one,
+//the chunks of a session may arrive over several connections.
+#[inline]
+fn chunk_source<T>(request: &Request<T>) -> (Option<SocketAddr>, Option<u64>) {
+    let session = request
+        .metadata()
+        .get(TRANSFER_SESSION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if session.is_some() {
+        (None, session)
+    } else {
+        (request.remote_addr(), None)
+    }
+}
+
+///Runs a handler for every received message with a concurrency limit per priority band, so a
+///burst of high priority messages can not take every worker and hold up lower priorities.
+pub struct PriorityWorkers {
+    //(lowest priority of the band, handler limit), highest band first
+    bands: Vec<(Priority, usize)>,
+}
+
+impl PriorityWorkers {
+    ///Handles all priorities with at most `limit` handlers running at once.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            bands: vec![(Priority::MIN, limit.max(1))],
+        }
+    }
+
+    ///Gives priorities from `min_priority` up to the next higher band their own `limit`.
+    pub fn band(mut self, min_priority: Priority, limit: usize) -> Self {
+        self.bands.retain(|(p, _)| *p != min_priority);
+        self.bands.push((min_priority, limit.max(1)));
+        self.bands.sort_by_key(|(p, _)| Reverse(*p));
+        self
+    }
+
+    #[inline]
+    fn band_of(&self, priority: Priority) -> usize {
+        self.bands
+            .iter()
+            .position(|(min_priority, _)| priority >= *min_priority)
+            .expect("the lowest band starts at Priority::MIN")
+    }
+
+    ///Handles the messages of `rx` until it ends, the result of `handler` is sent back as the reply.
+    ///
+    ///Up to `WAITING_MESSAGES` messages, counted across all bands, wait for a free handler. `rx`
+    ///is read as long as there is room, so a band with every handler busy does not hold up the
+    ///messages of the other bands. Once the room is taken the messages wait in the handler channel
+    ///and its backpressure reaches the clients.
+    pub async fn run<S, F, Fut>(self, mut rx: S, handler: F)
+    where
+        S: Stream<Item = (Priority, Message)> + Unpin,
+        F: Fn(Priority, Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Vec<u8>>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let waiting = Arc::new(Semaphore::new(WAITING_MESSAGES));
+        let (queues, workers): (Vec<_>, Vec<_>) = self
+            .bands
+            .iter()
+            .map(|(_, limit)| {
+                let (queue_tx, queue_rx) = tokio::sync::mpsc::unbounded_channel();
+                let worker = tokio::spawn(Self::work(queue_rx, *limit, handler.clone()));
+                (queue_tx, worker)
+            })
+            .unzip();
+        loop {
+            let Ok(room) = waiting.clone().acquire_owned().await else {
+                break;
+            };
+            let Some(msg) = rx.next().await else {
+                break;
+            };
+            if queues[self.band_of(msg.0)].send((msg, room)).is_err() {
+                break;
+            }
+        }
+        drop(queues);
+        for worker in workers {
+            let _ = worker.await;
+        }
+    }
+
+    //Starts a handler for each message of the queue of a band once one of its `limit` slots is
+    //free, the message then gives its room among the waiting messages back
+    async fn work<F, Fut>(
+        mut queue: tokio::sync::mpsc::UnboundedReceiver<(
+            (Priority, Message),
+            OwnedSemaphorePermit,
+        )>,
+        limit: usize,
+        handler: Arc<F>,
+    ) where
+        F: Fn(Priority, Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Vec<u8>>> + Send + 'static,
+    {
+        let slots = Arc::new(Semaphore::new(limit));
+        loop {
+            let Ok(slot) = slots.clone().acquire_owned().await else {
+                break;
+            };
+            let Some(((priority, (data, reply_tx)), room)) = queue.recv().await else {
+                break;
+            };
+            drop(room);
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let _slot = slot;
+                let res = handler(priority, data).await;
+                if let Some(reply_tx) = reply_tx {
+                    if reply_tx.send(res).is_err() {
+                        log::warn!("Reply dropped, the request is gone, priority: {}", priority);
+                    }
+                }
+            });
+        }
+    }
+}
+
+///Upper bounds of the handler latency buckets of `ServerStats`.
+pub const HANDLER_LATENCY_BUCKETS: [Duration; 8] = [
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+];
+
+///Counters of a server, see `Server::metrics`.
+#[derive(Debug, Default)]
+pub struct ServerMetrics {
+    messages_received: AtomicU64,
+    bytes_received: AtomicU64,
+    reassembly_buffers: AtomicUsize,
+    handler_latency: [AtomicU64; HANDLER_LATENCY_BUCKETS.len() + 1],
+    slow_consumers: AtomicU64,
+    //Whether the handler channel is currently reported as slow
+    slow: AtomicBool,
+}
+
+impl ServerMetrics {
+    ///Takes a snapshot of the counters.
+    pub fn stats(&self) -> ServerStats {
+        ServerStats {
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            reassembly_buffers: self.reassembly_buffers.load(Ordering::Relaxed),
+            handler_latency: self
+                .handler_latency
+                .iter()
+                .map(|n| n.load(Ordering::Relaxed))
+                .collect(),
+            slow_consumers: self.slow_consumers.load(Ordering::Relaxed),
+        }
+    }
+
+    #[inline]
+    fn received(&self, len: usize) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn handled(&self, latency: Duration) {
+        let bucket = HANDLER_LATENCY_BUCKETS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(HANDLER_LATENCY_BUCKETS.len());
+        self.handler_latency[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+///A snapshot of `ServerMetrics`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    ///Complete messages received, chunked messages count once.
+    pub messages_received: u64,
+    ///Payload bytes of the received messages.
+    pub bytes_received: u64,
+    ///Chunked messages being reassembled.
+    pub reassembly_buffers: usize,
+    ///`Send` requests per handler latency, entry `i` counts latencies up to
+    ///`HANDLER_LATENCY_BUCKETS[i]`, the last entry the slower ones.
+    pub handler_latency: Vec<u64>,
+    ///Times the handler channel was reported as slow.
+    pub slow_consumers: u64,
+}
+
+///Events of a running server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServerEvent {
+    ///The handler channel stayed full, a message waited `waited` to be delivered.
+    SlowConsumer { waited: Duration },
+}
+
+impl std::fmt::Display for ServerEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerEvent::SlowConsumer { waited } => write!(
+                f,
+                "Slow consumer, the handler channel was full for {:?}",
+                waited
+            ),
+        }
     }
 }
 
@@ -318,27 +1150,77 @@ fn socket2_bind(
     Ok(listener)
 }
 
-#[allow(clippy::type_complexity)]
+//The messages being merged, keyed by (remote address, session, message ID)
+type DataBuffs = RwLock<DequeBTreeMap<(Option<SocketAddr>, Option<u64>, Id), Reassembly>>;
+
+//The chunks received of a message being merged
+struct Reassembly {
+    started: Instant,
+    total_chunks: u32,
+    //Keyed by chunk index, iterating them yields the message data in order
+    chunks: BTreeMap<u32, transferpb::Message>,
+}
+
+///The chunked messages a server is reassembling, see `Server::reassemblies`.
+#[derive(Clone)]
+pub struct Reassemblies {
+    data_buffses: Arc<Vec<DataBuffs>>,
+}
+
+impl Reassemblies {
+    fn new() -> Self {
+        Self {
+            data_buffses: Arc::new(
+                (0..DATA_BUFFSES_MAX)
+                    .map(|_| RwLock::new(DequeBTreeMap::default()))
+                    .collect(),
+            ),
+        }
+    }
+
+    ///Returns the messages with chunks outstanding, oldest first within each buffer shard.
+    pub async fn pending(&self) -> Vec<PendingReassembly> {
+        let mut pending = Vec::new();
+        for data_buffs in self.data_buffses.iter() {
+            for ((_, _, id), reassembly) in data_buffs.read().await.iter() {
+                if !reassembly.chunks.is_empty() {
+                    pending.push(PendingReassembly {
+                        id: *id,
+                        received: reassembly.chunks.len() as u32,
+                        total_chunks: reassembly.total_chunks,
+                    });
+                }
+            }
+        }
+        pending
+    }
+}
+
+///A chunked message missing chunks, see `Reassemblies::pending`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PendingReassembly {
+    pub id: Id,
+    pub received: u32,
+    pub total_chunks: u32,
+}
+
+#[derive(Clone)]
 struct ChunkedBuffer {
-    data_buffses: Vec<
-        RwLock<
-            DequeBTreeMap<
-                (Option<SocketAddr>, Id),
-                (Instant, PriorityQueue<Reverse<u32>, transferpb::Message>),
-            >,
-        >,
-    >,
+    data_buffses: Arc<Vec<DataBuffs>>,
     recv_chunks_timeout: Duration,
+    metrics: Arc<ServerMetrics>,
 }
 
 impl ChunkedBuffer {
-    fn new(recv_chunks_timeout: Duration) -> Self {
-        let data_buffses = (0..DATA_BUFFSES_MAX)
-            .map(|_| RwLock::new(DequeBTreeMap::default()))
-            .collect();
+    fn new(
+        recv_chunks_timeout: Duration,
+        metrics: Arc<ServerMetrics>,
+        reassemblies: Reassemblies,
+    ) -> Self {
         ChunkedBuffer {
-            data_buffses,
+            data_buffses: reassemblies.data_buffses,
             recv_chunks_timeout,
+            metrics,
         }
     }
 
@@ -347,7 +1229,8 @@ impl ChunkedBuffer {
         &self,
         req: transferpb::Message,
         remote_addr: Option<SocketAddr>,
-    ) -> Option<(Priority, Vec<u8>)> {
+        session: Option<u64>,
+    ) -> Result<Option<(Priority, Vec<u8>)>, Status> {
         if req.total_chunks > 1 {
             let idx = req.id % DATA_BUFFSES_MAX;
             let data_buffs = if let Some(data_buffs) = self.data_buffses.get(idx as usize) {
@@ -358,14 +1241,15 @@ impl ChunkedBuffer {
 
             let mut now = None;
             let mut data_buffs = data_buffs.write().await;
+            let buffers = data_buffs.len();
             while let Some((id, is_empty, is_timeout)) =
-                data_buffs.front().and_then(|((_, id), (t, q))| {
-                    let is_empty = q.is_empty();
+                data_buffs.front().and_then(|((_, _, id), reassembly)| {
+                    let is_empty = reassembly.chunks.is_empty();
                     if now.is_none() {
                         now = Some(Instant::now());
                     };
                     let is_timeout = if let Some(now) = &now {
-                        now.duration_since(*t) > self.recv_chunks_timeout
+                        now.duration_since(reassembly.started) > self.recv_chunks_timeout
                     } else {
                         false
                     };
@@ -381,28 +1265,448 @@ impl ChunkedBuffer {
                 }
                 data_buffs.pop_front();
             }
-            let (_, data_buff) = data_buffs
-                .entry((remote_addr, req.id))
-                .or_insert_with(|| (Instant::now(), PriorityQueue::default()));
+            let key = (remote_addr, session, req.id);
+            let reassembly = data_buffs.entry(key).or_insert_with(|| Reassembly {
+                started: Instant::now(),
+                total_chunks: req.total_chunks,
+                chunks: BTreeMap::new(),
+            });
             let total_chunks = req.total_chunks;
             let priority = req.priority;
-            data_buff.push(Reverse(req.chunk_index), req);
-
-            if data_buff.len() >= total_chunks as usize {
-                let merged_data = data_buff
-                    .drain()
-                    .flat_map(|(_, msg)| msg.data.unwrap_or_default())
-                    .collect::<Vec<_>>();
-                Some((priority, merged_data))
+            let invalid = if req.chunk_index >= total_chunks {
+                Some(format!(
+                    "chunk {} out of {} chunks, message ID: {}",
+                    req.chunk_index, total_chunks, req.id
+                ))
+            } else if reassembly.total_chunks != total_chunks {
+                Some(format!(
+                    "chunk {} of {} chunks, the message has {} chunks, message ID: {}",
+                    req.chunk_index, total_chunks, reassembly.total_chunks, req.id
+                ))
             } else {
                 None
+            };
+            let duplicate = reassembly
+                .chunks
+                .get(&req.chunk_index)
+                .map(|msg| msg.data == req.data);
+
+            let merged = match (invalid, duplicate) {
+                //The message can no longer be merged correctly, drop what was received
+                (Some(invalid), _) => {
+                    data_buffs.remove(&key);
+                    Err(Status::invalid_argument(invalid))
+                }
+                //A resent chunk with the same bytes, it is already counted
+                (None, Some(true)) => Ok(None),
+                (None, Some(false)) => {
+                    data_buffs.remove(&key);
+                    Err(Status::invalid_argument(format!(
+                        "conflicting duplicate of chunk {}, message ID: {}",
+                        req.chunk_index, req.id
+                    )))
+                }
+                (None, None) => {
+                    reassembly.chunks.insert(req.chunk_index, req);
+                    if reassembly.chunks.len() < total_chunks as usize {
+                        Ok(None)
+                    } else {
+                        let merged_data = data_buffs
+                            .remove(&key)
+                            .into_iter()
+                            .flat_map(|reassembly| reassembly.chunks.into_values())
+                            .flat_map(|msg| msg.data.unwrap_or_default())
+                            .collect();
+                        Ok(Some((priority, merged_data)))
+                    }
+                }
+            };
+
+            let reassembly_buffers = &self.metrics.reassembly_buffers;
+            if data_buffs.len() > buffers {
+                reassembly_buffers.fetch_add(data_buffs.len() - buffers, Ordering::Relaxed);
+            } else {
+                reassembly_buffers.fetch_sub(buffers - data_buffs.len(), Ordering::Relaxed);
             }
+            merged
         } else {
-            Some((req.priority, req.data.unwrap_or_default()))
+            Ok(Some((req.priority, req.data.unwrap_or_default())))
+        }
+    }
+
+    ///Drops the chunks of message `id` received so far, returns false if none were buffered.
+    #[inline]
+    async fn discard(&self, remote_addr: Option<SocketAddr>, session: Option<u64>, id: Id) -> bool {
+        let data_buffs =
+            if let Some(data_buffs) = self.data_buffses.get((id % DATA_BUFFSES_MAX) as usize) {
+                data_buffs
+            } else {
+                unreachable!();
+            };
+        let discarded = data_buffs
+            .write()
+            .await
+            .remove(&(remote_addr, session, id))
+            .is_some();
+        if discarded {
+            self.metrics
+                .reassembly_buffers
+                .fetch_sub(1, Ordering::Relaxed);
         }
+        discarded
+    }
+
+    ///Returns the index of the first chunk of message `id` not received yet and the number of
+    ///chunks of the message, (0, 0) when none of its chunks are buffered.
+    #[inline]
+    async fn progress(
+        &self,
+        remote_addr: Option<SocketAddr>,
+        session: Option<u64>,
+        id: Id,
+    ) -> (u32, u32) {
+        let data_buffs =
+            if let Some(data_buffs) = self.data_buffses.get((id % DATA_BUFFSES_MAX) as usize) {
+                data_buffs
+            } else {
+                unreachable!();
+            };
+        let data_buffs = data_buffs.read().await;
+        let reassembly = match data_buffs.get(&(remote_addr, session, id)) {
+            Some(reassembly) if !reassembly.chunks.is_empty() => reassembly,
+            _ => return (0, 0),
+        };
+        //The chunk indexes are unique and in order, the first gap is the first missing chunk
+        let next = reassembly
+            .chunks
+            .keys()
+            .zip(0..)
+            .find(|(chunk_index, next)| **chunk_index != *next)
+            .map(|(_, next)| next)
+            .unwrap_or(reassembly.chunks.len() as u32);
+        (next, reassembly.total_chunks)
     }
 }
 
 //Receive chunk data timeout
 const RECV_CHUNKS_TIMEOUT: Duration = Duration::from_secs(30);
 const DATA_BUFFSES_MAX: u64 = 10;
+const STREAM_RESPONSE_BUFFER: usize = 16;
+//Messages taken by `PriorityWorkers` and waiting for a free handler, across all priority bands
+const WAITING_MESSAGES: usize = 1024;
+//Number of recently received session chunks remembered to drop replays
+pub(crate) const DELIVERED_WINDOW: usize = 100_000;
+//Number of recent replies to sends on sessions kept for retries whose response was lost
+const REPLY_WINDOW: usize = 1024;
+const SLOW_CONSUMER_THRESHOLD: Duration = Duration::from_secs(1);
+
+#[tokio::test]
+async fn test_priority_workers() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let (mut tx, rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let running_high = Arc::new(AtomicUsize::new(0));
+    let release_high = Arc::new(Semaphore::new(0));
+
+    let (running, release) = (running_high.clone(), release_high.clone());
+    let workers = PriorityWorkers::new(1).band(10, 1);
+    tokio::spawn(workers.run(rx, move |priority, data| {
+        let (running, release) = (running.clone(), release.clone());
+        async move {
+            if priority >= 10 {
+                running.fetch_add(1, Ordering::SeqCst);
+                let _ = release.acquire().await?;
+                running.fetch_sub(1, Ordering::SeqCst);
+            }
+            Ok(data)
+        }
+    }));
+
+    //Far more high priority messages than the high band handles at once
+    let mut high_replies = Vec::new();
+    for i in 0..48u8 {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        tx.send((10 + i as Priority, (vec![i], Some(reply_tx))))
+            .await
+            .unwrap();
+        high_replies.push(reply_rx);
+    }
+    let (reply_tx, reply_rx) = oneshot::channel();
+    tx.send((0, (vec![9], Some(reply_tx)))).await.unwrap();
+
+    //The low priority message is still handled while the high band is busy
+    let reply = tokio::time::timeout(Duration::from_secs(3), reply_rx)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(reply.unwrap(), vec![9]);
+    assert_eq!(running_high.load(Ordering::SeqCst), 1);
+
+    release_high.add_permits(48);
+    for reply_rx in high_replies {
+        assert!(reply_rx.await.unwrap().is_ok());
+    }
+}
+
+#[tokio::test]
+async fn test_priority_workers_backpressure() {
+    let (mut tx, rx) = mpsc::priority_channel::<Priority, Message>(4);
+    let release = Arc::new(Semaphore::new(0));
+
+    let release1 = release.clone();
+    tokio::spawn(PriorityWorkers::new(1).run(rx, move |_, data| {
+        let release = release1.clone();
+        async move {
+            let _ = release.acquire().await?;
+            Ok(data)
+        }
+    }));
+
+    //With the only handler blocked, the room for waiting messages and then the handler channel
+    //fill up
+    let mut sent = 0;
+    while sent < 2 * WAITING_MESSAGES {
+        let send = tx.send((0, (vec![sent as u8], None)));
+        if tokio::time::timeout(Duration::from_millis(100), send)
+            .await
+            .is_err()
+        {
+            break;
+        }
+        sent += 1;
+    }
+    assert!(
+        sent > WAITING_MESSAGES && sent < 2 * WAITING_MESSAGES,
+        "sent: {}",
+        sent
+    );
+    release.close();
+}
+
+#[tokio::test]
+async fn test_slow_consumer() {
+    use super::client::Client;
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(1);
+    let events = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let events1 = events.clone();
+    let server = server(([127, 0, 0, 1], 0).into(), tx)
+        .slow_consumer_threshold(Duration::from_millis(50))
+        .event_sink(move |event| events1.lock().push(event.clone()));
+    let metrics = server.metrics();
+    let addr = run_server(server).await.unwrap();
+
+    //The handler takes 100ms per message
+    tokio::spawn(async move {
+        while let Some((_, (data, reply_tx))) = rx.next().await {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            if let Some(reply_tx) = reply_tx {
+                let _ = reply_tx.send(Ok(data));
+            }
+        }
+    });
+
+    let mut c = Client::new(addr.to_string()).connect_lazy().unwrap();
+    assert_eq!(c.send(vec![0; 10]).await.unwrap(), vec![0; 10]);
+    let stats = metrics.stats();
+    assert_eq!(stats.messages_received, 1);
+    assert_eq!(stats.bytes_received, 10);
+    assert_eq!(stats.handler_latency.iter().sum::<u64>(), 1);
+    assert_eq!(stats.handler_latency[..5].iter().sum::<u64>(), 0);
+    assert!(events.lock().is_empty());
+
+    let mut mailbox = c.transfer_start(100).await;
+    for i in 0..10u8 {
+        mailbox.send(vec![i]).await.unwrap();
+    }
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while events.lock().is_empty() && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let events = events.lock().clone();
+    assert_eq!(events.len(), 1);
+    assert!(
+        matches!(events[0], ServerEvent::SlowConsumer { waited } if waited >= Duration::from_millis(50))
+    );
+    let stats = metrics.stats();
+    assert_eq!(stats.slow_consumers, 1);
+    assert!(stats.messages_received > 1);
+    assert_eq!(stats.reassembly_buffers, 0);
+}
+
+#[tokio::test]
+async fn test_tag_tx() {
+    use super::client::Client;
+
+    //Replies with the name of the channel that received the message
+    fn spawn_handler(name: &'static str) -> TX {
+        let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+        tokio::spawn(async move {
+            while let Some((_, (data, reply_tx))) = rx.next().await {
+                if let Some(reply_tx) = reply_tx {
+                    let _ = reply_tx.send(Ok([name.as_bytes(), &data].concat()));
+                }
+            }
+        });
+        tx
+    }
+    let server = server(([127, 0, 0, 1], 0).into(), spawn_handler("default"))
+        .tag_tx("json".into(), spawn_handler("json"))
+        .tag_tx("cbor".into(), spawn_handler("cbor"));
+    let addr = run_server(server).await.unwrap();
+
+    let mut c = Client::new(addr.to_string())
+        .chunk_size(4)
+        .connect_lazy()
+        .unwrap();
+    let reply = c.send_tagged("json".into(), b"1".to_vec(), 0).await;
+    assert_eq!(reply.unwrap(), b"json1");
+    let reply = c.send_tagged("cbor".into(), b"2".to_vec(), 0).await;
+    assert_eq!(reply.unwrap(), b"cbor2");
+    //Chunked
+    let reply = c
+        .send_tagged("cbor".into(), b"0123456789".to_vec(), 0)
+        .await;
+    assert_eq!(reply.unwrap(), b"cbor0123456789");
+    let reply = c.send_tagged("xml".into(), b"3".to_vec(), 0).await;
+    assert_eq!(reply.unwrap(), b"default3");
+    assert_eq!(c.send(b"4".to_vec()).await.unwrap(), b"default4");
+}
+
+#[tokio::test]
+async fn test_pending_reassemblies() {
+    use super::transferpb::data_transfer_client::DataTransferClient;
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let server = server(([127, 0, 0, 1], 0).into(), tx);
+    let reassemblies = server.reassemblies();
+    let addr = run_server(server).await.unwrap();
+    tokio::spawn(async move {
+        while let Some((_, (data, reply_tx))) = rx.next().await {
+            if let Some(reply_tx) = reply_tx {
+                let _ = reply_tx.send(Ok(data));
+            }
+        }
+    });
+
+    let mut c = DataTransferClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+    let chunk = |chunk_index| transferpb::Message {
+        id: 42,
+        priority: 0,
+        total_chunks: 3,
+        chunk_index,
+        data: Some(vec![chunk_index as u8]),
+        tag: None,
+    };
+    assert!(reassemblies.pending().await.is_empty());
+    c.send(chunk(0)).await.unwrap();
+    c.send(chunk(2)).await.unwrap();
+    assert_eq!(
+        reassemblies.pending().await,
+        vec![PendingReassembly {
+            id: 42,
+            received: 2,
+            total_chunks: 3
+        }]
+    );
+
+    let resp = c.send(chunk(1)).await.unwrap().into_inner();
+    assert_eq!(resp.data, Some(vec![0, 1, 2]));
+    assert!(reassemblies.pending().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_duplicate_chunks() {
+    use super::transferpb::data_transfer_client::DataTransferClient;
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let server = server(([127, 0, 0, 1], 0).into(), tx);
+    let reassemblies = server.reassemblies();
+    let addr = run_server(server).await.unwrap();
+    tokio::spawn(async move {
+        while let Some((_, (data, reply_tx))) = rx.next().await {
+            if let Some(reply_tx) = reply_tx {
+                let _ = reply_tx.send(Ok(data));
+            }
+        }
+    });
+
+    let mut c = DataTransferClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+    let chunk = |id, chunk_index, data| transferpb::Message {
+        id,
+        priority: 0,
+        total_chunks: 3,
+        chunk_index,
+        data: Some(vec![data]),
+        tag: None,
+    };
+
+    //An identical duplicate is ignored and not counted toward total_chunks
+    c.send(chunk(1, 0, 0)).await.unwrap();
+    c.send(chunk(1, 1, 1)).await.unwrap();
+    let resp = c.send(chunk(1, 1, 1)).await.unwrap().into_inner();
+    assert_eq!(resp.data, None);
+    let resp = c.send(chunk(1, 2, 2)).await.unwrap().into_inner();
+    assert_eq!(resp.data, Some(vec![0, 1, 2]));
+
+    //A conflicting duplicate fails the message and drops its chunks
+    c.send(chunk(2, 0, 0)).await.unwrap();
+    c.send(chunk(2, 1, 1)).await.unwrap();
+    let status = c.send(chunk(2, 1, 9)).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert!(status.message().contains("conflicting duplicate"));
+    assert!(reassemblies.pending().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_invalid_chunks() {
+    use super::transferpb::data_transfer_client::DataTransferClient;
+
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    let server = server(([127, 0, 0, 1], 0).into(), tx);
+    let reassemblies = server.reassemblies();
+    let addr = run_server(server).await.unwrap();
+    tokio::spawn(async move {
+        while let Some((_, (data, reply_tx))) = rx.next().await {
+            if let Some(reply_tx) = reply_tx {
+                let _ = reply_tx.send(Ok(data));
+            }
+        }
+    });
+
+    let mut c = DataTransferClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+    let chunk = |id, total_chunks, chunk_index| transferpb::Message {
+        id,
+        priority: 0,
+        total_chunks,
+        chunk_index,
+        data: Some(vec![chunk_index as u8]),
+        tag: None,
+    };
+
+    //A chunk index beyond the message fails it instead of completing it
+    c.send(chunk(1, 3, 0)).await.unwrap();
+    c.send(chunk(1, 3, 1)).await.unwrap();
+    let status = c.send(chunk(1, 3, 7)).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert!(reassemblies.pending().await.is_empty());
+    assert!(c.send(chunk(3, 2, 2)).await.is_err());
+    assert!(reassemblies.pending().await.is_empty());
+
+    //So does a chunk disagreeing on the number of chunks
+    c.send(chunk(2, 3, 0)).await.unwrap();
+    let status = c.send(chunk(2, 2, 1)).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert!(reassemblies.pending().await.is_empty());
+
+    c.send(chunk(4, 3, 2)).await.unwrap();
+    c.send(chunk(4, 3, 0)).await.unwrap();
+    let resp = c.send(chunk(4, 3, 1)).await.unwrap().into_inner();
+    assert_eq!(resp.data, Some(vec![0, 1, 2]));
+}
@@ -0,0 +1,255 @@
+//!Helpers for integration tests against a DataTransfer server running in the same process.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use tokio::net::TcpListener;
+use tonic::transport::server::{Router, TcpIncoming};
+use tonic::{Request, Response, Status, Streaming};
+
+use super::server::{server, DataTransferService, Message, Server, ServerMetrics};
+use super::transferpb::data_transfer_server::{DataTransfer, DataTransferServer};
+use super::transferpb::{self, Empty};
+use super::{Error, Priority, Result};
+
+pub type HandlerTx = mpsc::Sender<(Priority, Message), mpsc::SendError<(Priority, Message)>>;
+
+type SendHook = Arc<
+    dyn Fn(
+            DataTransferService,
+            Request<transferpb::Message>,
+        ) -> BoxFuture<'static, Result<Response<transferpb::Message>, Status>>
+        + Send
+        + Sync,
+>;
+type TransferHook = Arc<
+    dyn Fn(
+            DataTransferService,
+            Request<Streaming<transferpb::Message>>,
+        ) -> BoxFuture<'static, Result<Response<Empty>, Status>>
+        + Send
+        + Sync,
+>;
+
+///Starts a DataTransfer server on an ephemeral localhost port and answers every request with
+///`handler(data)`, returns the address to connect to. The server runs until the runtime shuts down.
+pub async fn spawn_server<F>(handler: F) -> Result<SocketAddr>
+where
+    F: Fn(Vec<u8>) -> Result<Vec<u8>> + Send + 'static,
+{
+    run_server(server(([127, 0, 0, 1], 0).into(), spawn_handler(handler))).await
+}
+
+///Serves `server` on an ephemeral localhost port instead of the address it was built with,
+///returns the address to connect to. The server runs until the runtime shuts down.
+pub async fn run_server(server: Server) -> Result<SocketAddr> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let laddr = listener.local_addr()?;
+    tokio::spawn(async move {
+        if let Err(e) = server.run_with_listener(listener).await {
+            log::error!("test server on {} stopped, {:?}", laddr, e);
+        }
+    });
+    Ok(laddr)
+}
+
+///Serves a hand-built tonic router on an ephemeral localhost port, see `run_server`.
+pub async fn serve(router: Router) -> Result<SocketAddr> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let laddr = listener.local_addr()?;
+    let incoming = TcpIncoming::from_listener(listener, false, None).map_err(Error::msg)?;
+    tokio::spawn(async move {
+        if let Err(e) = router.serve_with_incoming(incoming).await {
+            log::error!("test server on {} stopped, {:?}", laddr, e);
+        }
+    });
+    Ok(laddr)
+}
+
+///A localhost address nothing listens on, for clients that must never get through.
+pub fn unused_addr() -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap()
+}
+
+///Returns the handler channel of a server, answering every request with `handler(data)`. The
+///handler also sees the messages of transfer streams, which get no reply.
+pub fn spawn_handler<F>(handler: F) -> HandlerTx
+where
+    F: Fn(Vec<u8>) -> Result<Vec<u8>> + Send + 'static,
+{
+    let (tx, mut rx) = mpsc::priority_channel::<Priority, Message>(100);
+    tokio::spawn(async move {
+        while let Some((_, (data, reply_tx))) = rx.next().await {
+            let reply = handler(data);
+            if let Some(reply_tx) = reply_tx {
+                let _ = reply_tx.send(reply);
+            }
+        }
+    });
+    tx
+}
+
+///A `DataTransferService` whose `send` and `transfer` calls can be intercepted, e.g. to inject
+///failures or delays. Calls without a hook, and every other RPC, are served as usual.
+#[derive(Clone)]
+pub struct TestService {
+    service: DataTransferService,
+    send: Option<SendHook>,
+    transfer: Option<TransferHook>,
+}
+
+impl TestService {
+    ///Answers every request with `handler(data)`, see `spawn_handler`.
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(Vec<u8>) -> Result<Vec<u8>> + Send + 'static,
+    {
+        Self {
+            service: DataTransferService::new(spawn_handler(handler), Duration::from_secs(30)),
+            send: None,
+            transfer: None,
+        }
+    }
+
+    ///Calls `hook` in place of `send`, it is given the service to pass the request on to.
+    pub fn on_send<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(DataTransferService, Request<transferpb::Message>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response<transferpb::Message>, Status>> + Send + 'static,
+    {
+        self.send = Some(Arc::new(move |service, request| {
+            hook(service, request).boxed()
+        }));
+        self
+    }
+
+    ///Calls `hook` in place of `transfer`, it is given the service to pass the request on to.
+    pub fn on_transfer<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(DataTransferService, Request<Streaming<transferpb::Message>>) -> Fut
+            + Send
+            + Sync
+            + 'static,
+        Fut: Future<Output = Result<Response<Empty>, Status>> + Send + 'static,
+    {
+        self.transfer = Some(Arc::new(move |service, request| {
+            hook(service, request).boxed()
+        }));
+        self
+    }
+
+    ///The metrics of the wrapped service.
+    pub fn metrics(&self) -> Arc<ServerMetrics> {
+        self.service.metrics()
+    }
+
+    ///Serves the service on an ephemeral localhost port, see `run_server`.
+    pub async fn spawn(self) -> Result<SocketAddr> {
+        serve(tonic::transport::Server::builder().add_service(DataTransferServer::new(self))).await
+    }
+}
+
+#[tonic::async_trait]
+impl DataTransfer for TestService {
+    type SendStreamStream = <DataTransferService as DataTransfer>::SendStreamStream;
+    type ExchangeStream = <DataTransferService as DataTransfer>::ExchangeStream;
+    type DuplexStream = <DataTransferService as DataTransfer>::DuplexStream;
+
+    async fn transfer(
+        &self,
+        request: Request<Streaming<transferpb::Message>>,
+    ) -> Result<Response<Empty>, Status> {
+        match &self.transfer {
+            Some(hook) => hook(self.service.clone(), request).await,
+            None => self.service.transfer(request).await,
+        }
+    }
+
+    async fn send(
+        &self,
+        request: Request<transferpb::Message>,
+    ) -> Result<Response<transferpb::Message>, Status> {
+        match &self.send {
+            Some(hook) => hook(self.service.clone(), request).await,
+            None => self.service.send(request).await,
+        }
+    }
+
+    async fn send_stream(
+        &self,
+        request: Request<transferpb::Message>,
+    ) -> Result<Response<Self::SendStreamStream>, Status> {
+        self.service.send_stream(request).await
+    }
+
+    async fn progress(
+        &self,
+        request: Request<transferpb::Message>,
+    ) -> Result<Response<transferpb::Message>, Status> {
+        self.service.progress(request).await
+    }
+
+    async fn exchange(
+        &self,
+        request: Request<Streaming<transferpb::Message>>,
+    ) -> Result<Response<Self::ExchangeStream>, Status> {
+        self.service.exchange(request).await
+    }
+
+    async fn duplex(
+        &self,
+        request: Request<Streaming<transferpb::Message>>,
+    ) -> Result<Response<Self::DuplexStream>, Status> {
+        self.service.duplex(request).await
+    }
+}
+
+///Starts a server answering every request with the request data, see `spawn_server`.
+pub async fn spawn_echo_server() -> Result<SocketAddr> {
+    spawn_server(Ok).await
+}
+
+///Splits `data` into the chunk messages a client sends for it, for benchmarks.
+pub fn split_into_chunks(data: &[u8], chunk_size: usize) -> Vec<transferpb::Message> {
+    super::client::split_into_chunks(data, 0, 0, chunk_size)
+}
+
+#[tokio::test]
+async fn test_spawn_echo_server() {
+    use crate::client::Client;
+
+    let laddr = spawn_echo_server().await.unwrap();
+    let mut c = Client::new(laddr.to_string()).connect().await.unwrap();
+    assert_eq!(c.send(vec![1, 2, 3]).await.unwrap(), vec![1, 2, 3]);
+
+    let laddr = spawn_server(|data| Ok(data.into_iter().rev().collect()))
+        .await
+        .unwrap();
+    let mut c = Client::new(laddr.to_string())
+        .chunk_size(4)
+        .connect()
+        .await
+        .unwrap();
+    assert_eq!(
+        c.send((0..10).collect()).await.unwrap(),
+        (0..10).rev().collect::<Vec<u8>>()
+    );
+}
+
+#[test]
+fn test_split_into_chunks() {
+    let msgs = split_into_chunks(&[1, 2, 3, 4, 5], 2);
+    assert_eq!(msgs.len(), 3);
+    assert!(msgs.iter().all(|msg| msg.total_chunks == 3));
+    assert_eq!(msgs[2].data, Some(vec![5]));
+
+    //A chunk size beyond the data length must not overflow
+    let msgs = split_into_chunks(&[1, 2, 3], usize::MAX);
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].total_chunks, 1);
+}
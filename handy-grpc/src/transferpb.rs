@@ -11,6 +11,8 @@ pub struct Message {
     pub chunk_index: u32,
     #[prost(bytes = "vec", optional, tag = "10")]
     pub data: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+    #[prost(string, optional, tag = "11")]
+    pub tag: ::core::option::Option<::prost::alloc::string::String>,
 }
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct Empty {}
@@ -147,6 +149,99 @@ pub mod data_transfer_client {
                 .insert(GrpcMethod::new("transferpb.DataTransfer", "Send"));
             self.inner.unary(req, path, codec).await
         }
+        pub async fn send_stream(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Message>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::Message>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/transferpb.DataTransfer/SendStream",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("transferpb.DataTransfer", "SendStream"));
+            self.inner.server_streaming(req, path, codec).await
+        }
+        pub async fn progress(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Message>,
+        ) -> std::result::Result<tonic::Response<super::Message>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/transferpb.DataTransfer/Progress",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("transferpb.DataTransfer", "Progress"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn exchange(
+            &mut self,
+            request: impl tonic::IntoStreamingRequest<Message = super::Message>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::Message>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/transferpb.DataTransfer/Exchange",
+            );
+            let mut req = request.into_streaming_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("transferpb.DataTransfer", "Exchange"));
+            self.inner.streaming(req, path, codec).await
+        }
+        pub async fn duplex(
+            &mut self,
+            request: impl tonic::IntoStreamingRequest<Message = super::Message>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::Message>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/transferpb.DataTransfer/Duplex",
+            );
+            let mut req = request.into_streaming_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("transferpb.DataTransfer", "Duplex"));
+            self.inner.streaming(req, path, codec).await
+        }
     }
 }
 /// Generated server implementations.
@@ -170,6 +265,49 @@ pub mod data_transfer_server {
             &self,
             request: tonic::Request<super::Message>,
         ) -> std::result::Result<tonic::Response<super::Message>, tonic::Status>;
+        /// Server streaming response type for the SendStream method.
+        type SendStreamStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::Message, tonic::Status>,
+            >
+            + std::marker::Send
+            + 'static;
+        async fn send_stream(
+            &self,
+            request: tonic::Request<super::Message>,
+        ) -> std::result::Result<
+            tonic::Response<Self::SendStreamStream>,
+            tonic::Status,
+        >;
+        async fn progress(
+            &self,
+            request: tonic::Request<super::Message>,
+        ) -> std::result::Result<tonic::Response<super::Message>, tonic::Status>;
+        /// Server streaming response type for the Exchange method.
+        type ExchangeStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::Message, tonic::Status>,
+            >
+            + std::marker::Send
+            + 'static;
+        async fn exchange(
+            &self,
+            request: tonic::Request<tonic::Streaming<super::Message>>,
+        ) -> std::result::Result<
+            tonic::Response<Self::ExchangeStream>,
+            tonic::Status,
+        >;
+        /// Server streaming response type for the Duplex method.
+        type DuplexStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::Message, tonic::Status>,
+            >
+            + std::marker::Send
+            + 'static;
+        async fn duplex(
+            &self,
+            request: tonic::Request<tonic::Streaming<super::Message>>,
+        ) -> std::result::Result<
+            tonic::Response<Self::DuplexStream>,
+            tonic::Status,
+        >;
     }
     #[derive(Debug)]
     pub struct DataTransferServer<T> {
@@ -335,6 +473,187 @@ pub mod data_transfer_server {
                     };
                     Box::pin(fut)
                 }
+                "/transferpb.DataTransfer/SendStream" => {
+                    #[allow(non_camel_case_types)]
+                    struct SendStreamSvc<T: DataTransfer>(pub Arc<T>);
+                    impl<
+                        T: DataTransfer,
+                    > tonic::server::ServerStreamingService<super::Message>
+                    for SendStreamSvc<T> {
+                        type Response = super::Message;
+                        type ResponseStream = T::SendStreamStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Message>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as DataTransfer>::send_stream(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = SendStreamSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/transferpb.DataTransfer/Progress" => {
+                    #[allow(non_camel_case_types)]
+                    struct ProgressSvc<T: DataTransfer>(pub Arc<T>);
+                    impl<T: DataTransfer> tonic::server::UnaryService<super::Message>
+                    for ProgressSvc<T> {
+                        type Response = super::Message;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Message>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as DataTransfer>::progress(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = ProgressSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/transferpb.DataTransfer/Exchange" => {
+                    #[allow(non_camel_case_types)]
+                    struct ExchangeSvc<T: DataTransfer>(pub Arc<T>);
+                    impl<
+                        T: DataTransfer,
+                    > tonic::server::StreamingService<super::Message>
+                    for ExchangeSvc<T> {
+                        type Response = super::Message;
+                        type ResponseStream = T::ExchangeStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<tonic::Streaming<super::Message>>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as DataTransfer>::exchange(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = ExchangeSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/transferpb.DataTransfer/Duplex" => {
+                    #[allow(non_camel_case_types)]
+                    struct DuplexSvc<T: DataTransfer>(pub Arc<T>);
+                    impl<
+                        T: DataTransfer,
+                    > tonic::server::StreamingService<super::Message>
+                    for DuplexSvc<T> {
+                        type Response = super::Message;
+                        type ResponseStream = T::DuplexStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<tonic::Streaming<super::Message>>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as DataTransfer>::duplex(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = DuplexSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         let mut response = http::Response::new(empty_body());
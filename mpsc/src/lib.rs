@@ -1,30 +1,38 @@
+use futures::task::ArcWake;
 use futures::{Sink, SinkExt, Stream, StreamExt};
 
+use std::fmt;
 use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 pub use queue_ext::SendError;
 #[allow(unused_imports)]
 use queue_ext::{Action, QueueExt, Reply, Waker};
 
 #[cfg(feature = "priority")]
-use collections::PriorityQueue;
+pub use collections::PriorityQueue;
 
-///BinaryHeap based channel
+///BinaryHeap based channel, items are received highest priority first. The priority can be any
+///`Ord` type, e.g. a `(class, deadline)` tuple for a composite key.
 #[cfg(feature = "priority")]
 #[allow(clippy::type_complexity)]
 pub fn with_priority_channel<P: Ord + 'static, T: 'static>(
     queue: std::sync::Arc<parking_lot::RwLock<PriorityQueue<P, T>>>,
     bound: usize,
 ) -> (Sender<(P, T), SendError<(P, T)>>, Receiver<(P, T)>) {
+    let capacity = Arc::new(AtomicUsize::new(bound));
+    let bound = capacity.clone();
     let (tx, rx) = queue.queue_channel::<_, _, _, _>(
         move |s, act| match act {
             Action::Send((p, val)) => {
                 s.write().push(p, val);
                 Reply::Send(())
             }
-            Action::IsFull => Reply::IsFull(s.read().len() >= bound),
+            Action::IsFull => Reply::IsFull(s.read().len() >= bound.load(Ordering::SeqCst)),
             Action::IsEmpty => Reply::IsEmpty(s.read().is_empty()),
             Action::Len => Reply::Len(s.read().len()),
         },
@@ -37,7 +45,7 @@ pub fn with_priority_channel<P: Ord + 'static, T: 'static>(
         },
     );
 
-    (Sender::new(tx), Receiver::new(rx))
+    (Sender::with_capacity(tx, capacity), Receiver::new(rx))
 }
 
 ///BinaryHeap based channel
@@ -57,13 +65,15 @@ pub fn with_segqueue_channel<T: 'static>(
     queue: std::sync::Arc<crossbeam_queue::SegQueue<T>>,
     bound: usize,
 ) -> (Sender<T, SendError<T>>, Receiver<T>) {
+    let capacity = Arc::new(AtomicUsize::new(bound));
+    let bound = capacity.clone();
     let (tx, rx) = queue.queue_channel::<T, _, _, _>(
         move |s, act| match act {
             Action::Send(val) => {
                 s.push(val);
                 Reply::Send(())
             }
-            Action::IsFull => Reply::IsFull(s.len() >= bound),
+            Action::IsFull => Reply::IsFull(s.len() >= bound.load(Ordering::SeqCst)),
             Action::IsEmpty => Reply::IsEmpty(s.is_empty()),
             Action::Len => Reply::Len(s.len()),
         },
@@ -72,7 +82,7 @@ pub fn with_segqueue_channel<T: 'static>(
             None => Poll::Pending,
         },
     );
-    (Sender::new(tx), Receiver::new(rx))
+    (Sender::with_capacity(tx, capacity), Receiver::new(rx))
 }
 
 ///SegQueue based channel
@@ -89,13 +99,15 @@ pub fn with_vecdeque_channel<T: 'static>(
     queue: std::sync::Arc<parking_lot::RwLock<std::collections::VecDeque<T>>>,
     bound: usize,
 ) -> (Sender<T, SendError<T>>, Receiver<T>) {
+    let capacity = Arc::new(AtomicUsize::new(bound));
+    let bound = capacity.clone();
     let (tx, rx) = queue.queue_channel::<T, _, _, _>(
         move |s, act| match act {
             Action::Send(val) => {
                 s.write().push_back(val);
                 Reply::Send(())
             }
-            Action::IsFull => Reply::IsFull(s.read().len() >= bound),
+            Action::IsFull => Reply::IsFull(s.read().len() >= bound.load(Ordering::SeqCst)),
             Action::IsEmpty => Reply::IsEmpty(s.read().is_empty()),
             Action::Len => Reply::Len(s.read().len()),
         },
@@ -107,7 +119,7 @@ pub fn with_vecdeque_channel<T: 'static>(
             }
         },
     );
-    (Sender::new(tx), Receiver::new(rx))
+    (Sender::with_capacity(tx, capacity), Receiver::new(rx))
 }
 
 ///VecDeque based channel
@@ -129,6 +141,8 @@ where
     K: Eq + std::hash::Hash + 'static,
     T: 'static,
 {
+    let capacity = Arc::new(AtomicUsize::new(bound));
+    let bound = capacity.clone();
     let (tx, rx) = indexmap.queue_channel::<(K, T), _, _, _>(
         move |s, act| match act {
             Action::Send((key, val)) => {
@@ -137,7 +151,7 @@ where
                 let reply = s.insert(key, val);
                 Reply::Send(reply)
             }
-            Action::IsFull => Reply::IsFull(s.read().len() >= bound),
+            Action::IsFull => Reply::IsFull(s.read().len() >= bound.load(Ordering::SeqCst)),
             Action::IsEmpty => Reply::IsEmpty(s.read().is_empty()),
             Action::Len => Reply::Len(s.read().len()),
         },
@@ -149,7 +163,7 @@ where
             }
         },
     );
-    (Sender::new(tx), Receiver::new(rx))
+    (Sender::with_capacity(tx, capacity), Receiver::new(rx))
 }
 
 ///Indexmap based channel, remove entry if it already exists
@@ -182,6 +196,7 @@ where
 
 pub struct Sender<M, E> {
     tx: Box<dyn SenderSink<M, E>>,
+    capacity: Option<Arc<AtomicUsize>>,
 }
 
 impl<M, E> Sender<M, E> {
@@ -191,13 +206,49 @@ impl<M, E> Sender<M, E> {
         T: Sink<M, Error = E> + Sync + Send + Unpin + 'static,
         T: Clone,
     {
-        Sender { tx: Box::new(tx) }
+        Sender {
+            tx: Box::new(tx),
+            capacity: None,
+        }
+    }
+
+    #[inline]
+    fn with_capacity<T>(tx: T, capacity: Arc<AtomicUsize>) -> Self
+    where
+        T: Sink<M, Error = E> + Sync + Send + Unpin + 'static,
+        T: Clone,
+    {
+        Sender {
+            tx: Box::new(tx),
+            capacity: Some(capacity),
+        }
     }
 
     #[inline]
     pub async fn send(&mut self, t: M) -> std::result::Result<(), E> {
         self.tx.send(t).await
     }
+
+    ///Returns the current capacity bound of the channel, or None if this sender was not
+    ///created by one of the bounded channel constructors.
+    #[inline]
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity.as_ref().map(|c| c.load(Ordering::SeqCst))
+    }
+
+    ///Changes the capacity bound of the channel at runtime, returning the previous bound, or
+    ///None if this sender was not created by one of the bounded channel constructors.
+    ///
+    ///The new bound is shared by all clones of the sender. Queued items are kept; if the bound
+    ///is lowered below the current length, new sends wait until the receiver has drained the
+    ///queue below the new bound. Senders already waiting on a full channel are admitted as the
+    ///receiver takes items.
+    #[inline]
+    pub fn set_capacity(&self, capacity: usize) -> Option<usize> {
+        self.capacity
+            .as_ref()
+            .map(|c| c.swap(capacity, Ordering::SeqCst))
+    }
 }
 
 impl<M, E> Clone for Sender<M, E> {
@@ -205,6 +256,7 @@ impl<M, E> Clone for Sender<M, E> {
     fn clone(&self) -> Self {
         Sender {
             tx: self.tx.box_clone(),
+            capacity: self.capacity.clone(),
         }
     }
 }
@@ -275,12 +327,94 @@ impl<M> Receiver<M> {
         self.rx.next().await
     }
 
+    ///Takes a message if one is queued, without waiting.
+    #[inline]
+    pub fn try_recv(&mut self) -> Result<M, TryRecvError> {
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        match self.rx.poll_next_unpin(&mut cx) {
+            Poll::Ready(Some(m)) => Ok(m),
+            Poll::Ready(None) => Err(TryRecvError::Disconnected),
+            Poll::Pending => Err(TryRecvError::Empty),
+        }
+    }
+
+    ///Blocks the current thread until a message arrives or `timeout` has passed, for consumers
+    ///outside of an async runtime.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<M, RecvTimeoutError> {
+        struct ThreadWaker(std::thread::Thread);
+
+        impl ArcWake for ThreadWaker {
+            fn wake_by_ref(arc_self: &Arc<Self>) {
+                arc_self.0.unpark();
+            }
+        }
+
+        let waker = futures::task::waker(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.rx.poll_next_unpin(&mut cx) {
+                Poll::Ready(Some(m)) => return Ok(m),
+                Poll::Ready(None) => return Err(RecvTimeoutError::Disconnected),
+                Poll::Pending => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    std::thread::park_timeout(deadline - now);
+                }
+            }
+        }
+    }
+
     #[inline]
     pub fn is_closed(&self) -> bool {
         self.rx.is_closed()
     }
 }
 
+///The error returned by `Receiver::try_recv`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    ///No message is queued.
+    Empty,
+    ///No message is queued and all senders are gone.
+    Disconnected,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "receiving on an empty channel"),
+            TryRecvError::Disconnected => write!(f, "receiving on a closed channel"),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+///The error returned by `Receiver::recv_timeout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    ///No message arrived before the timeout.
+    Timeout,
+    ///No message is queued and all senders are gone.
+    Disconnected,
+}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvTimeoutError::Timeout => write!(f, "timed out waiting on channel"),
+            RecvTimeoutError::Disconnected => {
+                write!(f, "channel is empty and sending half is closed")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecvTimeoutError {}
+
 impl<M> Deref for Receiver<M> {
     type Target = Box<dyn ReceiverStream<M>>;
     #[inline]
@@ -430,3 +564,88 @@ impl<M> Stream for LocalReceiver<M> {
         Pin::new(&mut self.rx).poll_next(cx)
     }
 }
+
+#[cfg(feature = "priority")]
+#[test]
+fn test_priority_channel_set_capacity() {
+    use futures::FutureExt;
+
+    let (mut tx, mut rx) = priority_channel::<u8, u8>(2);
+    assert_eq!(tx.capacity(), Some(2));
+    assert!(tx.send((1, 1)).now_or_never().is_some());
+    assert!(tx.send((2, 2)).now_or_never().is_some());
+    assert!(tx.send((3, 3)).now_or_never().is_none());
+
+    assert_eq!(tx.clone().set_capacity(4), Some(2));
+    assert_eq!(tx.capacity(), Some(4));
+    assert!(tx.send((3, 3)).now_or_never().is_some());
+    assert!(tx.send((4, 4)).now_or_never().is_some());
+    assert!(tx.send((5, 5)).now_or_never().is_none());
+
+    //Shrinking below the current length holds new sends until the queue drains
+    tx.set_capacity(3);
+    assert_eq!(rx.recv().now_or_never(), Some(Some((4, 4))));
+    assert!(tx.send((5, 5)).now_or_never().is_none());
+    assert_eq!(rx.recv().now_or_never(), Some(Some((3, 3))));
+    assert!(tx.send((5, 5)).now_or_never().is_some());
+
+    assert_eq!(
+        Sender::<u8, ()>::new(futures::sink::drain().sink_map_err(|_| ())).set_capacity(1),
+        None
+    );
+}
+
+#[cfg(feature = "priority")]
+#[test]
+fn test_priority_channel_tuple_priority() {
+    use futures::FutureExt;
+
+    let (mut tx, mut rx) = priority_channel::<(u8, std::cmp::Reverse<u64>), &str>(10);
+    for (p, v) in vec![
+        ((1, std::cmp::Reverse(30)), "low-late"),
+        ((2, std::cmp::Reverse(20)), "high-late"),
+        ((1, std::cmp::Reverse(10)), "low-early"),
+        ((2, std::cmp::Reverse(5)), "high-early"),
+    ] {
+        assert!(tx.send((p, v)).now_or_never().is_some());
+    }
+    let mut vals = Vec::new();
+    while let Some(Some((_, v))) = rx.recv().now_or_never() {
+        vals.push(v);
+    }
+    assert_eq!(vals, ["high-early", "high-late", "low-early", "low-late"]);
+}
+
+#[cfg(feature = "segqueue")]
+#[test]
+fn test_try_recv_recv_timeout() {
+    use futures::executor::block_on;
+
+    let (mut tx, mut rx) = segqueue_channel::<u8>(10);
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    assert_eq!(
+        rx.recv_timeout(Duration::from_millis(10)),
+        Err(RecvTimeoutError::Timeout)
+    );
+
+    block_on(tx.send(1)).unwrap();
+    assert_eq!(rx.try_recv(), Ok(1));
+
+    let mut tx1 = tx.clone();
+    let sender = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        block_on(tx1.send(2)).unwrap();
+    });
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(2));
+    sender.join().unwrap();
+
+    //Queued messages are still received after the senders are gone
+    block_on(tx.send(3)).unwrap();
+    drop(tx);
+    assert_eq!(rx.try_recv(), Ok(3));
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    assert_eq!(
+        rx.recv_timeout(Duration::from_millis(10)),
+        Err(RecvTimeoutError::Disconnected)
+    );
+}